@@ -0,0 +1,383 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+
+use crate::models::Event;
+
+// RFC 5545 output for events. Times keep the event's own time zone, described by a
+// VTIMEZONE built from the tz database so clients don't need to know the IANA name.
+
+const PRODID: &str = "-//SOC//Events//EN";
+const LINE_LIMIT: usize = 75; // octets, without the CRLF
+
+// `name` is shown by clients as the calendar title, `domain` makes the UIDs globally unique
+pub fn render(name: &str, domain: &str, events: &[Event]) -> String {
+    render_at(name, domain, events, Utc::now())
+}
+
+fn render_at(name: &str, domain: &str, events: &[Event], now: DateTime<Utc>) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:{}", PRODID),
+        "CALSCALE:GREGORIAN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+        format!("X-WR-CALNAME:{}", escape(name)),
+    ];
+
+    // the span each zone has to cover
+    let mut zones: BTreeMap<&str, (Tz, DateTime<Utc>, DateTime<Utc>)> = BTreeMap::new();
+    for event in events {
+        let tz = event_tz(event);
+        if tz == Tz::UTC {
+            continue;
+        }
+        let end = event.ends_at.unwrap_or(event.date);
+        let span = zones.entry(tz.name()).or_insert((tz, event.date, end));
+        span.1 = span.1.min(event.date);
+        span.2 = span.2.max(end);
+    }
+    for (tz, from, to) in zones.values() {
+        vtimezone(&mut lines, *tz, *from, *to);
+    }
+
+    let stamp = format_utc(now);
+    for event in events {
+        vevent(&mut lines, event, domain, &stamp);
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    let mut out = String::new();
+    for line in lines {
+        fold(&mut out, &line);
+    }
+    out
+}
+
+fn vevent(lines: &mut Vec<String>, event: &Event, domain: &str, stamp: &str) {
+    let tz = event_tz(event);
+    lines.push("BEGIN:VEVENT".to_string());
+    lines.push(format!("UID:{}@{}", event.id.unwrap_or_default().to_hex(), domain));
+    lines.push(format!("DTSTAMP:{}", stamp));
+    lines.push(format!("SEQUENCE:{}", event.sequence));
+    lines.push(date_property("DTSTART", tz, event.date));
+    if let Some(ends_at) = event.ends_at {
+        lines.push(date_property("DTEND", tz, ends_at));
+    }
+    lines.push(format!("SUMMARY:{}", escape(&event.name)));
+    if !event.description.is_empty() {
+        lines.push(format!("DESCRIPTION:{}", escape(&event.description)));
+    }
+    if !event.location.is_empty() {
+        lines.push(format!("LOCATION:{}", escape(&event.location)));
+    }
+    lines.push(format!("CATEGORIES:{:?}", event.event_type));
+    let status = if event.cancelled_at.is_some() { "CANCELLED" } else { "CONFIRMED" };
+    lines.push(format!("STATUS:{}", status));
+    lines.push("END:VEVENT".to_string());
+}
+
+// unknown names were rejected when the event was saved, old events fall back to UTC
+fn event_tz(event: &Event) -> Tz {
+    match event.time_zone.parse::<Tz>() {
+        Ok(Tz::Etc__UTC) | Ok(Tz::UCT) | Ok(Tz::Etc__UCT) | Ok(Tz::Zulu) => Tz::UTC,
+        Ok(tz) => tz,
+        Err(_) => Tz::UTC,
+    }
+}
+
+fn date_property(name: &str, tz: Tz, at: DateTime<Utc>) -> String {
+    if tz == Tz::UTC {
+        format!("{}:{}", name, format_utc(at))
+    } else {
+        format!("{};TZID={}:{}", name, tz.name(), format_local(at.with_timezone(&tz).naive_local()))
+    }
+}
+
+fn format_utc(at: DateTime<Utc>) -> String {
+    at.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn format_local(at: NaiveDateTime) -> String {
+    at.format("%Y%m%dT%H%M%S").to_string()
+}
+
+// offset as ±HHMM, with seconds only when the zone has them
+fn format_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let seconds = seconds.abs();
+    let (hours, minutes, rest) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if rest == 0 {
+        format!("{}{:02}{:02}", sign, hours, minutes)
+    } else {
+        format!("{}{:02}{:02}{:02}", sign, hours, minutes, rest)
+    }
+}
+
+#[derive(Clone, PartialEq)]
+struct ZoneState {
+    offset: i32, // seconds east of UTC
+    daylight: bool,
+    abbreviation: String,
+}
+
+fn state_at(tz: Tz, at: DateTime<Utc>) -> ZoneState {
+    let offset = tz.offset_from_utc_datetime(&at.naive_utc());
+    ZoneState {
+        offset: offset.fix().local_minus_utc(),
+        daylight: !offset.dst_offset().is_zero(),
+        abbreviation: offset.abbreviation().to_string(),
+    }
+}
+
+// one observance for the state at the start of the span and one per change inside it.
+// The span starts a year early so the rule in effect at every event is on record.
+fn vtimezone(lines: &mut Vec<String>, tz: Tz, from: DateTime<Utc>, to: DateTime<Utc>) {
+    let mut at = from - Duration::days(366);
+    let end = to + Duration::days(1);
+    let mut state = state_at(tz, at);
+
+    lines.push("BEGIN:VTIMEZONE".to_string());
+    lines.push(format!("TZID:{}", tz.name()));
+    observance(lines, &state, &state, at);
+
+    while at < end {
+        let next = at + Duration::days(1);
+        let next_state = state_at(tz, next);
+        if next_state != state {
+            // narrow the change down to the second it happens
+            let (mut before, mut after) = (at, next);
+            while after - before > Duration::seconds(1) {
+                let mid = before + (after - before) / 2;
+                if state_at(tz, mid) == state {
+                    before = mid;
+                } else {
+                    after = mid;
+                }
+            }
+            observance(lines, &state, &next_state, after);
+            state = next_state;
+        }
+        at = next;
+    }
+    lines.push("END:VTIMEZONE".to_string());
+}
+
+// DTSTART of an observance is the local time just before the change
+fn observance(lines: &mut Vec<String>, from: &ZoneState, to: &ZoneState, at: DateTime<Utc>) {
+    let kind = if to.daylight { "DAYLIGHT" } else { "STANDARD" };
+    let local = at.naive_utc() + Duration::seconds(from.offset as i64);
+    lines.push(format!("BEGIN:{}", kind));
+    lines.push(format!("DTSTART:{}", format_local(local)));
+    lines.push(format!("TZOFFSETFROM:{}", format_offset(from.offset)));
+    lines.push(format!("TZOFFSETTO:{}", format_offset(to.offset)));
+    lines.push(format!("TZNAME:{}", escape(&to.abbreviation)));
+    lines.push(format!("END:{}", kind));
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            ';' => out.push_str("\\;"),
+            ',' => out.push_str("\\,"),
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// long lines are split into 75 octet chunks, continuation lines start with a space.
+// Splits never fall inside a multi-byte character.
+fn fold(out: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > LINE_LIMIT {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::oid::ObjectId;
+
+    use super::*;
+    use crate::models::event::EventType;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    fn event(date: &str, ends_at: &str, time_zone: &str) -> Event {
+        Event {
+            id: Some(ObjectId::parse_str("65f000000000000000000001").unwrap()),
+            name: "Rust, Tea; Talk".to_string(),
+            location: "Lab 2".to_string(),
+            date: utc(date),
+            ends_at: Some(utc(ends_at)),
+            time_zone: time_zone.to_string(),
+            registration_opens_at: None,
+            registration_closes_at: None,
+            description: "Bring a laptop\nand snacks".to_string(),
+            event_type: EventType::MEETUP,
+            host_id: None,
+            attendees: vec![],
+            capacity: None,
+            waitlist: vec![],
+            image_url: None,
+            image: None,
+            sequence: 0,
+            cancelled_at: None,
+            series_id: None,
+        }
+    }
+
+    fn render_lines(events: &[Event]) -> Vec<String> {
+        let out = render_at("SOC Events", "example.org", events, utc("2025-03-01T12:00:00Z"));
+        out.split_terminator("\r\n").map(str::to_string).collect()
+    }
+
+    #[test]
+    fn escapes_text() {
+        assert_eq!(escape("a,b;c\\d\ne"), "a\\,b\\;c\\\\d\\ne");
+        assert_eq!(escape("line\r\nbreak"), "line\\nbreak");
+    }
+
+    #[test]
+    fn folds_at_75_octets() {
+        let mut out = String::new();
+        fold(&mut out, &format!("SUMMARY:{}", "a".repeat(80)));
+        assert_eq!(out, format!("SUMMARY:{}\r\n {}\r\n", "a".repeat(67), "a".repeat(13)));
+    }
+
+    #[test]
+    fn folds_between_characters() {
+        let line = format!("SUMMARY:{}", "é".repeat(40));
+        let mut out = String::new();
+        fold(&mut out, &line);
+        let lines: Vec<&str> = out.split_terminator("\r\n").collect();
+        // 8 + 33 * 2 = 74, the next é would end on octet 76
+        assert_eq!(lines, [format!("SUMMARY:{}", "é".repeat(33)), format!(" {}", "é".repeat(7))]);
+        assert!(lines.iter().all(|line| line.len() <= LINE_LIMIT));
+        assert_eq!(out.replace("\r\n ", "").trim_end(), line);
+    }
+
+    #[test]
+    fn vtimezone_follows_dst_changes() {
+        let lines = render_lines(&[event("2025-03-25T17:00:00Z", "2025-04-01T18:00:00Z", "Europe/Berlin")]);
+        let zone: Vec<&str> = lines
+            .iter()
+            .map(String::as_str)
+            .skip_while(|line| *line != "BEGIN:VTIMEZONE")
+            .take_while(|line| *line != "END:VTIMEZONE")
+            .collect();
+        assert_eq!(
+            zone.join("\n"),
+            [
+                "BEGIN:VTIMEZONE",
+                "TZID:Europe/Berlin",
+                "BEGIN:STANDARD",
+                "DTSTART:20240324T180000",
+                "TZOFFSETFROM:+0100",
+                "TZOFFSETTO:+0100",
+                "TZNAME:CET",
+                "END:STANDARD",
+                "BEGIN:DAYLIGHT",
+                "DTSTART:20240331T020000",
+                "TZOFFSETFROM:+0100",
+                "TZOFFSETTO:+0200",
+                "TZNAME:CEST",
+                "END:DAYLIGHT",
+                "BEGIN:STANDARD",
+                "DTSTART:20241027T030000",
+                "TZOFFSETFROM:+0200",
+                "TZOFFSETTO:+0100",
+                "TZNAME:CET",
+                "END:STANDARD",
+                "BEGIN:DAYLIGHT",
+                "DTSTART:20250330T020000",
+                "TZOFFSETFROM:+0100",
+                "TZOFFSETTO:+0200",
+                "TZNAME:CEST",
+                "END:DAYLIGHT",
+            ]
+            .join("\n")
+        );
+        // local times on both sides of the change
+        assert!(lines.contains(&"DTSTART;TZID=Europe/Berlin:20250325T180000".to_string()));
+        assert!(lines.contains(&"DTEND;TZID=Europe/Berlin:20250401T200000".to_string()));
+    }
+
+    #[test]
+    fn cancelled_events_keep_their_sequence() {
+        let mut cancelled = event("2025-06-03T16:00:00Z", "2025-06-03T18:00:00Z", "UTC");
+        cancelled.sequence = 3;
+        cancelled.cancelled_at = Some(utc("2025-06-01T09:00:00Z"));
+        let lines = render_lines(&[cancelled]);
+        assert!(lines.contains(&"SEQUENCE:3".to_string()));
+        assert!(lines.contains(&"STATUS:CANCELLED".to_string()));
+        assert!(!lines.contains(&"STATUS:CONFIRMED".to_string()));
+        // UTC events need no VTIMEZONE
+        assert!(lines.contains(&"DTSTART:20250603T160000Z".to_string()));
+        assert!(!lines.contains(&"BEGIN:VTIMEZONE".to_string()));
+    }
+
+    #[test]
+    fn renders_an_event_in_a_dst_zone() {
+        let mut meetup = event("2025-03-25T17:00:00Z", "2025-03-25T19:00:00Z", "Europe/Berlin");
+        meetup.sequence = 2;
+        let expected = [
+            "BEGIN:VCALENDAR",
+            "VERSION:2.0",
+            "PRODID:-//SOC//Events//EN",
+            "CALSCALE:GREGORIAN",
+            "METHOD:PUBLISH",
+            "X-WR-CALNAME:SOC Events",
+            "BEGIN:VTIMEZONE",
+            "TZID:Europe/Berlin",
+            "BEGIN:STANDARD",
+            "DTSTART:20240324T180000",
+            "TZOFFSETFROM:+0100",
+            "TZOFFSETTO:+0100",
+            "TZNAME:CET",
+            "END:STANDARD",
+            "BEGIN:DAYLIGHT",
+            "DTSTART:20240331T020000",
+            "TZOFFSETFROM:+0100",
+            "TZOFFSETTO:+0200",
+            "TZNAME:CEST",
+            "END:DAYLIGHT",
+            "BEGIN:STANDARD",
+            "DTSTART:20241027T030000",
+            "TZOFFSETFROM:+0200",
+            "TZOFFSETTO:+0100",
+            "TZNAME:CET",
+            "END:STANDARD",
+            "END:VTIMEZONE",
+            "BEGIN:VEVENT",
+            "UID:65f000000000000000000001@example.org",
+            "DTSTAMP:20250301T120000Z",
+            "SEQUENCE:2",
+            "DTSTART;TZID=Europe/Berlin:20250325T180000",
+            "DTEND;TZID=Europe/Berlin:20250325T200000",
+            "SUMMARY:Rust\\, Tea\\; Talk",
+            "DESCRIPTION:Bring a laptop\\nand snacks",
+            "LOCATION:Lab 2",
+            "CATEGORIES:MEETUP",
+            "STATUS:CONFIRMED",
+            "END:VEVENT",
+            "END:VCALENDAR",
+        ];
+        let out = render_at("SOC Events", "example.org", &[meetup], utc("2025-03-01T12:00:00Z"));
+        assert_eq!(out, expected.iter().map(|line| format!("{}\r\n", line)).collect::<String>());
+    }
+}
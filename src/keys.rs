@@ -0,0 +1,287 @@
+use std::env;
+use std::fs;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL, Engine};
+use dotenv::dotenv;
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rsa::traits::PublicKeyParts;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+pub struct JwtKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    decoding: DecodingKey,
+    jwk: Option<Value>, // public part for the JWKS, None for shared secrets
+}
+
+// signing key plus the keys that are still accepted while rotating, loaded once at startup
+pub struct KeyRing {
+    pub issuer: String,
+    active: JwtKey,
+    encoding: EncodingKey,
+    previous: Vec<JwtKey>,
+}
+
+// JWT_ALGORITHM    HS256 (default), EdDSA or RS256
+// SECRET_KEY       shared secret for HS256
+// JWT_PRIVATE_KEY  path to a PKCS#8 PEM private key for EdDSA/RS256
+// JWT_KEY_ID       kid of the signing key, "default" if unset
+// JWT_PREVIOUS_KEYS retired keys still accepted, comma separated kid:ALG:value where value
+//                  is the secret for HS256 or a PEM file path for EdDSA/RS256
+// JWT_ISSUER       iss claim, "CxL" if unset
+impl KeyRing {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let algorithm = parse_algorithm(&env::var("JWT_ALGORITHM").unwrap_or_else(|_| "HS256".to_string()));
+        let kid = env::var("JWT_KEY_ID").unwrap_or_else(|_| "default".to_string());
+
+        let (active, encoding) = match algorithm {
+            Algorithm::HS256 => {
+                let secret = env::var("SECRET_KEY").expect("Secret key not set");
+                (hmac_key(&kid, &secret), EncodingKey::from_secret(secret.as_bytes()))
+            }
+            _ => {
+                let path = env::var("JWT_PRIVATE_KEY").expect("JWT_PRIVATE_KEY must be set for asymmetric algorithms");
+                let pem = fs::read_to_string(&path).expect("failed to read JWT private key");
+                let encoding = match algorithm {
+                    Algorithm::EdDSA => EncodingKey::from_ed_pem(pem.as_bytes()),
+                    _ => EncodingKey::from_rsa_pem(pem.as_bytes()),
+                }
+                .expect("invalid JWT private key");
+                (asymmetric_key(&kid, algorithm, &pem), encoding)
+            }
+        };
+
+        let previous = env::var("JWT_PREVIOUS_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let mut parts = entry.trim().splitn(3, ':');
+                let (kid, alg, value) = match (parts.next(), parts.next(), parts.next()) {
+                    (Some(kid), Some(alg), Some(value)) => (kid, parse_algorithm(alg), value),
+                    _ => panic!("JWT_PREVIOUS_KEYS entries must look like kid:ALG:value"),
+                };
+                match alg {
+                    Algorithm::HS256 => hmac_key(kid, value),
+                    _ => asymmetric_key(kid, alg, &fs::read_to_string(value).expect("failed to read previous JWT key")),
+                }
+            })
+            .collect();
+
+        KeyRing {
+            issuer: env::var("JWT_ISSUER").unwrap_or_else(|_| "CxL".to_string()),
+            active,
+            encoding,
+            previous,
+        }
+    }
+
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, jsonwebtoken::errors::Error> {
+        let mut header = Header::new(self.active.algorithm);
+        header.kid = Some(self.active.kid.clone());
+        encode(&header, claims, &self.encoding)
+    }
+
+    // checks the signature with the key named by kid, plus exp, nbf and iss
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<T, jsonwebtoken::errors::Error> {
+        let header = decode_header(token)?;
+        // tokens from before key ids existed can only belong to the active key
+        let key = match &header.kid {
+            Some(kid) => self
+                .keys()
+                .find(|key| &key.kid == kid)
+                .ok_or(jsonwebtoken::errors::ErrorKind::InvalidKeyFormat)?,
+            None => &self.active,
+        };
+
+        let mut validation = Validation::new(key.algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_required_spec_claims(&["exp", "nbf", "iss"]);
+        validation.validate_nbf = true;
+
+        Ok(decode::<T>(token, &key.decoding, &validation)?.claims)
+    }
+
+    // public keys for other services, shared secrets are never published
+    pub fn jwks(&self) -> Value {
+        let keys: Vec<&Value> = self.keys().filter_map(|key| key.jwk.as_ref()).collect();
+        json!({ "keys": keys })
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &JwtKey> {
+        std::iter::once(&self.active).chain(self.previous.iter())
+    }
+}
+
+fn parse_algorithm(algorithm: &str) -> Algorithm {
+    match algorithm.trim() {
+        "HS256" => Algorithm::HS256,
+        "EdDSA" => Algorithm::EdDSA,
+        "RS256" => Algorithm::RS256,
+        other => panic!("unsupported JWT algorithm {}", other),
+    }
+}
+
+fn hmac_key(kid: &str, secret: &str) -> JwtKey {
+    JwtKey {
+        kid: kid.to_string(),
+        algorithm: Algorithm::HS256,
+        decoding: DecodingKey::from_secret(secret.as_bytes()),
+        jwk: None,
+    }
+}
+
+// accepts either a private or a public PEM, only the public half is kept
+fn asymmetric_key(kid: &str, algorithm: Algorithm, pem: &str) -> JwtKey {
+    let is_private = pem.contains("PRIVATE KEY");
+    match algorithm {
+        Algorithm::EdDSA => {
+            let public_key = if is_private {
+                ed25519_dalek::SigningKey::from_pkcs8_pem(pem).expect("invalid Ed25519 private key").verifying_key()
+            } else {
+                ed25519_dalek::VerifyingKey::from_public_key_pem(pem).expect("invalid Ed25519 public key")
+            };
+            let x = BASE64_URL.encode(public_key.as_bytes());
+            JwtKey {
+                kid: kid.to_string(),
+                algorithm,
+                decoding: DecodingKey::from_ed_components(&x).expect("invalid Ed25519 public key"),
+                jwk: Some(json!({"kty": "OKP", "crv": "Ed25519", "x": x, "kid": kid, "alg": "EdDSA", "use": "sig"})),
+            }
+        }
+        _ => {
+            let public_key = if is_private {
+                RsaPrivateKey::from_pkcs8_pem(pem).expect("invalid RSA private key").to_public_key()
+            } else {
+                RsaPublicKey::from_public_key_pem(pem).expect("invalid RSA public key")
+            };
+            let n = BASE64_URL.encode(public_key.n().to_bytes_be());
+            let e = BASE64_URL.encode(public_key.e().to_bytes_be());
+            JwtKey {
+                kid: kid.to_string(),
+                algorithm,
+                decoding: DecodingKey::from_rsa_components(&n, &e).expect("invalid RSA public key"),
+                jwk: Some(json!({"kty": "RSA", "n": n, "e": e, "kid": kid, "alg": "RS256", "use": "sig"})),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+
+    use super::*;
+
+    fn ring(active: JwtKey, encoding: EncodingKey, previous: Vec<JwtKey>) -> KeyRing {
+        KeyRing { issuer: "CxL".to_string(), active, encoding, previous }
+    }
+
+    fn hmac_ring(kid: &str, secret: &str, previous: Vec<JwtKey>) -> KeyRing {
+        ring(hmac_key(kid, secret), EncodingKey::from_secret(secret.as_bytes()), previous)
+    }
+
+    fn ed_pem() -> String {
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+        signing_key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string()
+    }
+
+    fn ed_ring(kid: &str) -> KeyRing {
+        let pem = ed_pem();
+        ring(asymmetric_key(kid, Algorithm::EdDSA, &pem), EncodingKey::from_ed_pem(pem.as_bytes()).unwrap(), vec![])
+    }
+
+    fn claims() -> Value {
+        let now = Utc::now().timestamp();
+        json!({"sub": "ada@example.org", "iss": "CxL", "nbf": now, "exp": now + 600})
+    }
+
+    fn sign_with(kid: Option<&str>, algorithm: Algorithm, key: &EncodingKey, claims: &Value) -> String {
+        let mut header = Header::new(algorithm);
+        header.kid = kid.map(str::to_string);
+        encode(&header, claims, key).unwrap()
+    }
+
+    #[test]
+    fn verifies_tokens_it_signed() {
+        let keys = hmac_ring("2025", "current secret", vec![]);
+        let token = keys.sign(&claims()).unwrap();
+        assert_eq!(decode_header(&token).unwrap().kid.as_deref(), Some("2025"));
+        assert_eq!(keys.verify::<Value>(&token).unwrap()["sub"], "ada@example.org");
+
+        let keys = ed_ring("ed-2025");
+        let token = keys.sign(&claims()).unwrap();
+        assert_eq!(keys.verify::<Value>(&token).unwrap()["sub"], "ada@example.org");
+    }
+
+    #[test]
+    fn looks_keys_up_by_kid() {
+        let keys = hmac_ring("2025", "current secret", vec![hmac_key("2024", "old secret")]);
+        let old = EncodingKey::from_secret(b"old secret");
+        assert!(keys.verify::<Value>(&sign_with(Some("2024"), Algorithm::HS256, &old, &claims())).is_ok());
+        // the kid picks the key, a token signed with another one doesn't match it
+        assert!(keys.verify::<Value>(&sign_with(Some("2025"), Algorithm::HS256, &old, &claims())).is_err());
+        // tokens without a kid are checked against the active key only
+        let current = EncodingKey::from_secret(b"current secret");
+        assert!(keys.verify::<Value>(&sign_with(None, Algorithm::HS256, &current, &claims())).is_ok());
+        assert!(keys.verify::<Value>(&sign_with(None, Algorithm::HS256, &old, &claims())).is_err());
+    }
+
+    #[test]
+    fn retired_keys_verify_until_they_are_dropped() {
+        let before = hmac_ring("2024", "old secret", vec![]);
+        let token = before.sign(&claims()).unwrap();
+
+        let rotated = hmac_ring("2025", "current secret", vec![hmac_key("2024", "old secret")]);
+        assert!(rotated.verify::<Value>(&token).is_ok());
+        assert!(rotated.verify::<Value>(&rotated.sign(&claims()).unwrap()).is_ok());
+
+        let dropped = hmac_ring("2025", "current secret", vec![]);
+        assert!(dropped.verify::<Value>(&token).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_kids() {
+        let keys = hmac_ring("2025", "current secret", vec![]);
+        let current = EncodingKey::from_secret(b"current secret");
+        assert!(keys.verify::<Value>(&sign_with(Some("2023"), Algorithm::HS256, &current, &claims())).is_err());
+    }
+
+    #[test]
+    fn pins_the_algorithm_to_the_key() {
+        let keys = ed_ring("ed-2025");
+        // an HMAC over the published public key must not pass for the Ed25519 key
+        let public_key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]).verifying_key();
+        for secret in [public_key.as_bytes().to_vec(), BASE64_URL.encode(public_key.as_bytes()).into_bytes()] {
+            let forged = sign_with(Some("ed-2025"), Algorithm::HS256, &EncodingKey::from_secret(&secret), &claims());
+            let error = keys.verify::<Value>(&forged).unwrap_err();
+            assert_eq!(error.kind(), &jsonwebtoken::errors::ErrorKind::InvalidAlgorithm);
+        }
+    }
+
+    #[test]
+    fn rejects_other_issuers() {
+        let keys = hmac_ring("2025", "current secret", vec![]);
+        let mut claims = claims();
+        claims["iss"] = json!("someone-else");
+        assert!(keys.verify::<Value>(&keys.sign(&claims).unwrap()).is_err());
+        claims.as_object_mut().unwrap().remove("iss");
+        assert!(keys.verify::<Value>(&keys.sign(&claims).unwrap()).is_err());
+    }
+
+    #[test]
+    fn rejects_tokens_before_nbf() {
+        let keys = hmac_ring("2025", "current secret", vec![]);
+        let mut claims = claims();
+        claims["nbf"] = json!(Utc::now().timestamp() + 300);
+        assert!(keys.verify::<Value>(&keys.sign(&claims).unwrap()).is_err());
+        claims.as_object_mut().unwrap().remove("nbf");
+        assert!(keys.verify::<Value>(&keys.sign(&claims).unwrap()).is_err());
+    }
+}
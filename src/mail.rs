@@ -0,0 +1,71 @@
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use chrono::Utc;
+use dotenv::dotenv;
+use serde::Serialize;
+use serde_json::json;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// anything that can deliver a mail; handlers take `&State<Mailer>`
+#[rocket::async_trait]
+pub trait MailTransport: Send + Sync {
+    async fn send(&self, mail: &Mail) -> io::Result<()>;
+}
+
+pub type Mailer = Box<dyn MailTransport>;
+
+// prints mails to stdout, the default for local development
+pub struct LogTransport;
+
+#[rocket::async_trait]
+impl MailTransport for LogTransport {
+    async fn send(&self, mail: &Mail) -> io::Result<()> {
+        println!("mail to {}: {}\n{}", mail.to, mail.subject, mail.body);
+        Ok(())
+    }
+}
+
+// appends every mail as a json line to an outbox file, handy for tests and staging
+pub struct FileOutboxTransport {
+    pub path: PathBuf,
+}
+
+#[rocket::async_trait]
+impl MailTransport for FileOutboxTransport {
+    async fn send(&self, mail: &Mail) -> io::Result<()> {
+        let line = json!({
+            "to": mail.to,
+            "subject": mail.subject,
+            "body": mail.body,
+            "sent_at": Utc::now().to_rfc3339(),
+        });
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", line)
+    }
+}
+
+// MAIL_TRANSPORT=log (default) or file, MAIL_OUTBOX sets the file path
+pub fn from_env() -> Mailer {
+    dotenv().ok();
+    match env::var("MAIL_TRANSPORT").as_deref() {
+        Ok("file") => Box::new(FileOutboxTransport {
+            path: env::var("MAIL_OUTBOX").unwrap_or_else(|_| "outbox.jsonl".to_string()).into(),
+        }),
+        _ => Box::new(LogTransport),
+    }
+}
+
+// base url of the web frontend, used to build links in mails
+pub fn app_url() -> String {
+    dotenv().ok();
+    env::var("APP_URL").unwrap_or_else(|_| "http://localhost:3000".to_string())
+}
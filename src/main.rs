@@ -4,10 +4,21 @@ extern crate rocket;
 
 use models::user;
 use rocket::{custom, route, routes};
+use rocket::config::SecretKey;
 use rocket_cors::{AllowedOrigins, CorsOptions};
 mod db;
 mod routes;
 mod models;
+mod migrations;
+mod sui;
+mod mail;
+mod keys;
+mod totp;
+mod oidc;
+mod revocation;
+mod ical;
+mod recurrence;
+mod storage;
 
 
 
@@ -19,30 +30,101 @@ async fn rocket() -> _ {
     let event_db = db::connect::<models::Event>().await;
     let application_db = db::connect::<models::Application>().await;
     let blacklisted_tokens_db = db::connect::<models::BlackListedToken>().await;
+    let refresh_tokens_db = db::connect::<models::RefreshToken>().await;
+    let wallet_challenges_db = db::connect::<models::WalletChallenge>().await;
+    let password_resets_db = db::connect::<models::PasswordResetToken>().await;
+    let email_verifications_db = db::connect::<models::EmailVerificationToken>().await;
+    let login_throttles_db = db::connect::<models::LoginThrottle>().await;
+    let sessions_db = db::connect::<models::Session>().await;
+    let api_keys_db = db::connect::<models::ApiKey>().await;
+    let two_factor_db = db::connect::<models::TwoFactor>().await;
+    let mfa_challenges_db = db::connect::<models::MfaChallenge>().await;
+    let audit_db = db::connect::<models::AuditEntry>().await;
+    let oidc_logins_db = db::connect::<models::OidcLogin>().await;
+    let check_ins_db = db::connect::<models::CheckIn>().await;
+    let calendar_feeds_db = db::connect::<models::CalendarFeed>().await;
+    let event_series_db = db::connect::<models::EventSeries>().await;
+    let featured_db = db::connect::<models::FeaturedEvents>().await;
+
+    migrations::users(&user_db).await;
+    migrations::events(&event_db).await;
+    migrations::refresh_tokens(&refresh_tokens_db).await;
+    migrations::blacklisted_tokens(&blacklisted_tokens_db).await;
+    migrations::wallet_challenges(&wallet_challenges_db).await;
+    migrations::password_reset_tokens(&password_resets_db).await;
+    migrations::email_verification_tokens(&email_verifications_db).await;
+    migrations::login_throttles(&login_throttles_db).await;
+    migrations::sessions(&sessions_db).await;
+    migrations::api_keys(&api_keys_db).await;
+    migrations::two_factor(&two_factor_db, &mfa_challenges_db).await;
+    migrations::audit_log(&audit_db).await;
+    migrations::oidc_logins(&oidc_logins_db).await;
+    migrations::check_ins(&check_ins_db).await;
+    migrations::calendar_feeds(&calendar_feeds_db).await;
+    migrations::event_series(&event_series_db).await;
+    migrations::featured_events(&featured_db, &event_db).await;
+
+    let revocations = revocation::RevocationCache::load(&blacklisted_tokens_db).await;
+    revocations.spawn_sync(blacklisted_tokens_db.clone());
 
     let port = env::var("PORT")
     .unwrap_or_else(|_| "8000".to_string() )
     .parse::<u16>()
     .expect("Invalid PORT number");
     
-    let cors = CorsOptions::default()
-        .allowed_origins(AllowedOrigins::all())
-        .to_cors()
-        .unwrap();
-    
-
-    rocket::custom(
-        rocket::Config {
-            address: "0.0.0.0".parse().unwrap(),
-            port,
-            ..rocket::Config::default()
+    // cookie sessions need credentialed requests, which browsers only allow from listed origins
+    let cors = match env::var("CORS_ALLOWED_ORIGINS") {
+        Ok(origins) => {
+            let origins: Vec<&str> = origins.split(',').map(|origin| origin.trim()).collect();
+            CorsOptions::default()
+                .allowed_origins(AllowedOrigins::some_exact(&origins))
+                .allow_credentials(true)
         }
-    )
+        Err(_) => CorsOptions::default().allowed_origins(AllowedOrigins::all()),
+    }
+    .to_cors()
+    .unwrap();
+
+    let mut config = rocket::Config {
+        address: "0.0.0.0".parse().unwrap(),
+        port,
+        limits: routes::media::upload_limits(),
+        ..rocket::Config::default()
+    };
+    // encrypts the private session cookies, at least 32 characters; release builds refuse to start without it
+    if let Ok(secret) = env::var("COOKIE_SECRET_KEY") {
+        config.secret_key = SecretKey::derive_from(secret.as_bytes());
+    }
+
+    rocket::custom(config)
     .attach(cors) // Attach CORS Middleware
     .manage(user_db)
     .manage(event_db)
     .manage(application_db)
     .manage(blacklisted_tokens_db)
+    .manage(refresh_tokens_db)
+    .manage(wallet_challenges_db)
+    .manage(password_resets_db)
+    .manage(email_verifications_db)
+    .manage(login_throttles_db)
+    .manage(sessions_db)
+    .manage(api_keys_db)
+    .manage(two_factor_db)
+    .manage(mfa_challenges_db)
+    .manage(audit_db)
+    .manage(oidc_logins_db)
+    .manage(check_ins_db)
+    .manage(calendar_feeds_db)
+    .manage(event_series_db)
+    .manage(featured_db)
+    .manage(storage::from_env())
+    .manage(oidc::Providers::from_env())
+    .manage(revocations)
+    .manage(mail::from_env())
+    .manage(keys::KeyRing::from_env())
+    .manage(routes::verification::EmailVerificationPolicy::from_env())
+    .manage(routes::lockout::TrustedProxies::from_env())
+    .mount("/", routes![routes::jwks])
     .mount(
         "/api/v1",
         routes![
@@ -54,17 +136,68 @@ async fn rocket() -> _ {
             routes::create_event,
             routes::read_event,
             routes::read_events,
+            routes::export_events,
+            routes::read_event_attendees,
             routes::update_event,
             routes::drop_event,
+            routes::cancel_event,
             routes::apply_for_event,
             routes::read_applicants,
             routes::update_user_rank,
+            routes::grant_role,
+            routes::revoke_role,
             routes::login,
+            routes::refresh,
+            routes::logout,
+            routes::wallet_challenge,
+            routes::wallet_login,
+            routes::link_wallet,
+            routes::oidc_start,
+            routes::oidc_callback,
+            routes::forgot_password,
+            routes::reset_password,
+            routes::verify_email,
+            routes::resend_verification,
+            routes::unlock_user,
+            routes::list_sessions,
+            routes::revoke_session,
+            routes::revoke_other_sessions,
+            routes::revoke_user_sessions,
+            routes::create_api_key,
+            routes::read_api_keys,
+            routes::revoke_api_key,
+            routes::enroll_two_factor,
+            routes::confirm_two_factor,
+            routes::disable_two_factor,
+            routes::verify_two_factor,
+            routes::read_audit_log,
             routes::profile,
             routes::join_event,
             routes::leave_event,
+            routes::read_registration,
+            routes::read_ticket,
+            routes::check_in_attendee,
+            routes::read_check_in_stats,
+            routes::create_series,
+            routes::read_series,
+            routes::update_occurrence,
+            routes::join_series,
+            routes::leave_series,
+            routes::upload_event_image,
+            routes::delete_event_image,
+            routes::read_event_image,
+            routes::read_media,
+            routes::read_event_calendar,
+            routes::read_events_calendar,
+            routes::read_personal_calendar,
+            routes::create_calendar_feed,
+            routes::revoke_calendar_feed,
             routes::get_multiple_events,
             routes::update_pinned,
+            routes::read_featured_events,
+            routes::update_featured_events,
+            routes::feature_event,
+            routes::unfeature_event,
             routes::delete_all_events,
             routes::delete_all_users,
             routes::read_upcoming_events,
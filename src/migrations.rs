@@ -0,0 +1,412 @@
+use std::time::Duration;
+
+use futures::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document, Regex};
+use mongodb::options::IndexOptions;
+use mongodb::{Collection, IndexModel};
+
+use chrono::{DateTime, Utc};
+
+use crate::models::event::stored_date;
+use crate::models::{ApiKey, AuditEntry, BlackListedToken, CalendarFeed, CheckIn, EmailVerificationToken, Event, EventSeries, FeaturedEvents, FeaturedSlot, LoginThrottle, MfaChallenge, OidcLogin, PasswordResetToken, RefreshToken, Session, TwoFactor, User, WalletChallenge};
+
+// Indexes are created on startup; create_index is a no-op when the index already exists.
+
+// users created before roles existed only have the admin flag
+pub async fn users(collection: &Collection<User>) {
+    collection
+        .update_many(
+            doc! {"roles": {"$exists": false}, "admin": true},
+            doc! {"$set": {"roles": ["super_admin"]}},
+        )
+        .await
+        .expect("failed to migrate admin users to roles");
+    collection
+        .update_many(
+            doc! {"roles": {"$exists": false}},
+            doc! {"$set": {"roles": ["member"]}},
+        )
+        .await
+        .expect("failed to migrate users to roles");
+    collection
+        .update_many(doc! {"admin": {"$exists": true}}, doc! {"$unset": {"admin": ""}})
+        .await
+        .expect("failed to drop admin flag");
+    // accounts from before email verification are trusted as they are
+    collection
+        .update_many(
+            doc! {"email_verified_at": {"$exists": false}},
+            doc! {"$set": {"email_verified_at": Utc::now().timestamp()}},
+        )
+        .await
+        .expect("failed to mark existing users as verified");
+    collection
+        .create_index(IndexModel::builder().keys(doc! {"identities.provider": 1, "identities.subject": 1}).build())
+        .await
+        .expect("failed to create user identity index");
+
+    // wallets used to be stored as typed, without proof that the user owns them. Anyone could
+    // have entered someone else's address, so they are dropped and have to be linked again.
+    collection
+        .update_many(
+            doc! {"wallet": {"$gt": ""}, "wallet_linked_at": Bson::Null},
+            doc! {"$set": {"wallet": ""}},
+        )
+        .await
+        .expect("failed to clear unproven user wallets");
+    // users without a wallet store an empty string, only real addresses have to be unique
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"wallet": 1})
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .partial_filter_expression(doc! {"wallet": {"$gt": ""}})
+                        .build(),
+                )
+                .build(),
+        )
+        .await
+        .expect("failed to create user wallet index");
+}
+
+pub async fn refresh_tokens(collection: &Collection<RefreshToken>) {
+    let indexes = vec![
+        IndexModel::builder()
+            .keys(doc! {"token_hash": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! {"family_id": 1})
+            .build(),
+        // mongo purges the document once expires_at has passed
+        IndexModel::builder()
+            .keys(doc! {"expires_at": 1})
+            .options(IndexOptions::builder().expire_after(Duration::from_secs(0)).build())
+            .build(),
+    ];
+    collection
+        .create_indexes(indexes)
+        .await
+        .expect("failed to create refresh token indexes");
+}
+
+pub async fn blacklisted_tokens(collection: &Collection<BlackListedToken>) {
+    // entries from before token ids only hold the raw token. Those tokens have no jti and fail
+    // to decode now, so the entries protect nothing.
+    collection
+        .delete_many(doc! {"token": {"$exists": true}})
+        .await
+        .expect("failed to remove legacy blacklist entries");
+
+    let indexes = vec![
+        IndexModel::builder()
+            .keys(doc! {"jti": 1})
+            // entries from before token ids have no jti, they must not collide on null
+            .options(
+                IndexOptions::builder()
+                    .unique(true)
+                    .partial_filter_expression(doc! {"jti": {"$exists": true}})
+                    .build(),
+            )
+            .build(),
+        IndexModel::builder()
+            .keys(doc! {"sid": 1})
+            .options(
+                IndexOptions::builder()
+                    .unique(true)
+                    .partial_filter_expression(doc! {"sid": {"$exists": true}})
+                    .build(),
+            )
+            .build(),
+        IndexModel::builder()
+            .keys(doc! {"expires_at": 1})
+            .options(IndexOptions::builder().expire_after(Duration::from_secs(0)).build())
+            .build(),
+    ];
+    collection
+        .create_indexes(indexes)
+        .await
+        .expect("failed to create blacklisted token indexes");
+}
+
+pub async fn wallet_challenges(collection: &Collection<WalletChallenge>) {
+    let indexes = vec![
+        IndexModel::builder()
+            .keys(doc! {"wallet": 1, "nonce": 1})
+            .build(),
+        IndexModel::builder()
+            .keys(doc! {"expires_at": 1})
+            .options(IndexOptions::builder().expire_after(Duration::from_secs(0)).build())
+            .build(),
+    ];
+    collection
+        .create_indexes(indexes)
+        .await
+        .expect("failed to create wallet challenge indexes");
+}
+
+pub async fn password_reset_tokens(collection: &Collection<PasswordResetToken>) {
+    let indexes = vec![
+        IndexModel::builder()
+            .keys(doc! {"token_hash": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! {"email": 1})
+            .build(),
+        IndexModel::builder()
+            .keys(doc! {"expires_at": 1})
+            .options(IndexOptions::builder().expire_after(Duration::from_secs(0)).build())
+            .build(),
+    ];
+    collection
+        .create_indexes(indexes)
+        .await
+        .expect("failed to create password reset token indexes");
+}
+
+pub async fn email_verification_tokens(collection: &Collection<EmailVerificationToken>) {
+    let indexes = vec![
+        IndexModel::builder()
+            .keys(doc! {"token_hash": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! {"email": 1, "created_at": -1})
+            .build(),
+        IndexModel::builder()
+            .keys(doc! {"expires_at": 1})
+            .options(IndexOptions::builder().expire_after(Duration::from_secs(0)).build())
+            .build(),
+    ];
+    collection
+        .create_indexes(indexes)
+        .await
+        .expect("failed to create email verification token indexes");
+}
+
+pub async fn login_throttles(collection: &Collection<LoginThrottle>) {
+    let indexes = vec![
+        IndexModel::builder()
+            .keys(doc! {"key": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! {"expires_at": 1})
+            .options(IndexOptions::builder().expire_after(Duration::from_secs(0)).build())
+            .build(),
+    ];
+    collection
+        .create_indexes(indexes)
+        .await
+        .expect("failed to create login throttle indexes");
+}
+
+pub async fn sessions(collection: &Collection<Session>) {
+    let indexes = vec![
+        IndexModel::builder()
+            .keys(doc! {"family_id": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! {"email": 1, "last_used_at": -1})
+            .build(),
+        IndexModel::builder()
+            .keys(doc! {"expires_at": 1})
+            .options(IndexOptions::builder().expire_after(Duration::from_secs(0)).build())
+            .build(),
+    ];
+    collection
+        .create_indexes(indexes)
+        .await
+        .expect("failed to create session indexes");
+}
+
+pub async fn api_keys(collection: &Collection<ApiKey>) {
+    let index = IndexModel::builder()
+        .keys(doc! {"key_hash": 1})
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    collection
+        .create_index(index)
+        .await
+        .expect("failed to create api key indexes");
+}
+
+pub async fn two_factor(collection: &Collection<TwoFactor>, challenges: &Collection<MfaChallenge>) {
+    let index = IndexModel::builder()
+        .keys(doc! {"email": 1})
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    collection
+        .create_index(index)
+        .await
+        .expect("failed to create two factor indexes");
+
+    let indexes = vec![
+        IndexModel::builder()
+            .keys(doc! {"token_hash": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! {"expires_at": 1})
+            .options(IndexOptions::builder().expire_after(Duration::from_secs(0)).build())
+            .build(),
+    ];
+    challenges
+        .create_indexes(indexes)
+        .await
+        .expect("failed to create mfa challenge indexes");
+}
+
+pub async fn audit_log(collection: &Collection<AuditEntry>) {
+    let indexes = vec![
+        IndexModel::builder().keys(doc! {"at": -1}).build(),
+        IndexModel::builder().keys(doc! {"actor": 1, "at": -1}).build(),
+        IndexModel::builder().keys(doc! {"action": 1, "at": -1}).build(),
+        IndexModel::builder().keys(doc! {"target_ids": 1, "at": -1}).build(),
+    ];
+    collection
+        .create_indexes(indexes)
+        .await
+        .expect("failed to create audit log indexes");
+}
+
+pub async fn oidc_logins(collection: &Collection<OidcLogin>) {
+    let indexes = vec![
+        IndexModel::builder()
+            .keys(doc! {"state_hash": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! {"expires_at": 1})
+            .options(IndexOptions::builder().expire_after(Duration::from_secs(0)).build())
+            .build(),
+    ];
+    collection
+        .create_indexes(indexes)
+        .await
+        .expect("failed to create oidc login indexes");
+}
+
+// the unique index is what turns a second scan of the same ticket into a duplicate
+pub async fn check_ins(collection: &Collection<CheckIn>) {
+    let index = IndexModel::builder()
+        .keys(doc! {"event_id": 1, "user_id": 1})
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    collection
+        .create_index(index)
+        .await
+        .expect("failed to create check in indexes");
+}
+
+pub async fn calendar_feeds(collection: &Collection<CalendarFeed>) {
+    let indexes = vec![
+        IndexModel::builder()
+            .keys(doc! {"token_hash": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        IndexModel::builder()
+            .keys(doc! {"user_id": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+    ];
+    collection
+        .create_indexes(indexes)
+        .await
+        .expect("failed to create calendar feed indexes");
+}
+
+const DATE_FIELDS: [&str; 5] = ["date", "ends_at", "registration_opens_at", "registration_closes_at", "cancelled_at"];
+const STORED_DATE: &str = r"^\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}\.\d{3}Z$"; // models::event::stored_date
+
+// the filters and sort keys of GET /events
+pub async fn events(collection: &Collection<Event>) {
+    // dates used to be stored with as many fractional digits as they had, which doesn't sort as
+    // a string. Rewrite them in the fixed format of models::event::stored_date; only events
+    // with a date in another format are read, so once they are done this finds nothing.
+    let stale: Vec<Document> = DATE_FIELDS
+        .iter()
+        .map(|field| doc! {*field: {"$type": "string", "$not": Regex { pattern: STORED_DATE.to_string(), options: String::new() }}})
+        .collect();
+    let raw = collection.clone_with_type::<Document>();
+    let mut stored = raw.find(doc! {"$or": stale}).await.expect("failed to read event dates");
+    while let Some(event) = stored.try_next().await.expect("failed to read event dates") {
+        let mut set = Document::new();
+        for field in DATE_FIELDS {
+            if let Ok(value) = event.get_str(field) {
+                if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+                    let normalized = stored_date(&date.with_timezone(&Utc));
+                    if normalized != value {
+                        set.insert(field, normalized);
+                    }
+                }
+            }
+        }
+        if !set.is_empty() {
+            raw.update_one(doc! {"_id": event.get("_id")}, doc! {"$set": set})
+                .await
+                .expect("failed to normalize event dates");
+        }
+    }
+
+    let indexes = vec![
+        IndexModel::builder().keys(doc! {"date": 1, "_id": 1}).build(),
+        IndexModel::builder().keys(doc! {"event_type": 1, "date": 1}).build(),
+        IndexModel::builder().keys(doc! {"host_id": 1, "date": 1}).build(),
+        IndexModel::builder().keys(doc! {"series_id": 1, "date": 1}).build(),
+    ];
+    collection
+        .create_indexes(indexes)
+        .await
+        .expect("failed to create event indexes");
+}
+
+// series used to keep a member list nothing read; it held names and emails, so it goes
+pub async fn event_series(collection: &Collection<EventSeries>) {
+    collection
+        .update_many(doc! {"members": {"$exists": true}}, doc! {"$unset": {"members": ""}})
+        .await
+        .expect("failed to remove series members");
+}
+
+// events used to carry a pinned flag; whatever was pinned becomes the featured list, in date order
+pub async fn featured_events(collection: &Collection<FeaturedEvents>, events: &Collection<Event>) {
+    let existing = collection
+        .count_documents(doc! {"_id": crate::models::featured::FEATURED_ID})
+        .await
+        .expect("failed to read featured events");
+    if existing == 0 {
+        let pinned: Vec<Event> = events
+            .find(doc! {"pinned": true})
+            .sort(doc! {"date": 1})
+            .await
+            .expect("failed to read pinned events")
+            .try_collect()
+            .await
+            .expect("failed to read pinned events");
+        let mut slots: Vec<FeaturedSlot> = pinned
+            .iter()
+            .filter_map(|event| event.id)
+            .map(|event_id| FeaturedSlot { event_id, until: None, featured_by: "migration".to_string(), featured_at: Utc::now() })
+            .collect();
+        slots.truncate(crate::routes::featured::MAX_FEATURED);
+        let list = FeaturedEvents {
+            id: crate::models::featured::FEATURED_ID.to_string(),
+            slots,
+            version: 0,
+            updated_by: None,
+            updated_at: None,
+        };
+        collection.insert_one(list).await.expect("failed to seed featured events");
+    }
+    events
+        .update_many(doc! {"pinned": {"$exists": true}}, doc! {"$unset": {"pinned": ""}})
+        .await
+        .expect("failed to migrate pinned events");
+    // the index on the old flag isn't used anymore, fine if it's already gone
+    events.drop_index("pinned_1_date_1").await.ok();
+}
@@ -0,0 +1,31 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum ApiScope {
+    #[serde(rename = "events:read")]
+    EventsRead,
+    #[serde(rename = "attendees:check_in")]
+    AttendeesCheckIn,
+    #[serde(rename = "export")]
+    Export,
+}
+
+// long lived credential for machine clients (check-in scanners, website build)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiKey {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    pub prefix: String, // first characters of the key so admins can tell keys apart
+    pub key_hash: String, // sha256 of the key, the key itself is only shown once
+    pub scopes: Vec<ApiScope>,
+    pub created_by: String,
+    pub created_at: DateTime,
+    #[serde(default)]
+    pub expires_at: Option<DateTime>,
+    #[serde(default)]
+    pub last_used_at: Option<DateTime>,
+    #[serde(default)]
+    pub revoked_at: Option<DateTime>,
+}
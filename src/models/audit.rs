@@ -0,0 +1,24 @@
+use mongodb::bson::{oid::ObjectId, DateTime, Document};
+use serde::{Deserialize, Serialize};
+
+// one privileged or destructive action, written once and never updated
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditEntry {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub actor: String, // email of the admin who did it
+    pub action: String, // e.g. "event.delete", "user.role_grant"
+    #[serde(default)]
+    pub target_ids: Vec<String>,
+    #[serde(default)]
+    pub before: Option<Document>,
+    #[serde(default)]
+    pub after: Option<Document>,
+    pub method: String,
+    pub path: String,
+    #[serde(default)]
+    pub ip: Option<String>,
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    pub at: DateTime,
+}
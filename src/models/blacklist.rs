@@ -1,8 +1,14 @@
 use mongodb::bson::{doc, DateTime};
 use serde::{Deserialize, Serialize};
 
+// a revoked access token (jti), or a revoked session (sid) which covers every access token
+// issued for that refresh token family
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BlackListedToken {
-    pub token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    pub expires_at: DateTime, // exp of the revoked token, or of the session's last one; mongo drops the entry after it
     pub blacklist_at: DateTime
-}
\ No newline at end of file
+}
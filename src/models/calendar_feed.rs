@@ -0,0 +1,13 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+// secret url a member subscribes to from their calendar app, one per user.
+// Only the hash of the token is stored, rotating replaces it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CalendarFeed {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: ObjectId,
+    pub token_hash: String,
+    pub created_at: DateTime,
+}
@@ -0,0 +1,15 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+// an attendee showing up at an event, at most one per event and user
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CheckIn {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub event_id: ObjectId,
+    pub user_id: ObjectId,
+    pub checked_in_by: String, // email of the organizer or name of the api key that scanned the ticket
+    #[serde(default)]
+    pub api_key_id: Option<ObjectId>,
+    pub checked_in_at: DateTime,
+}
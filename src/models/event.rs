@@ -1,6 +1,6 @@
 use mongodb::bson::{oid::ObjectId};
 use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, SecondsFormat, Utc};
 
 use super::Attendee;
 
@@ -21,8 +21,16 @@ pub struct Event {
     pub name: String,
     #[serde(default)] // if location is missing use default value
     pub location: String,
-    #[serde(default)]
-    pub date: DateTime<Utc>,
+    #[serde(default, with = "stored_date")]
+    pub date: DateTime<Utc>, // start of the event
+    #[serde(default, with = "stored_date::option")]
+    pub ends_at: Option<DateTime<Utc>>,
+    #[serde(default = "default_time_zone")]
+    pub time_zone: String, // IANA name the event is scheduled in, e.g. "Africa/Lagos"
+    #[serde(default, with = "stored_date::option")]
+    pub registration_opens_at: Option<DateTime<Utc>>, // open right away when unset
+    #[serde(default, with = "stored_date::option")]
+    pub registration_closes_at: Option<DateTime<Utc>>, // the start of the event when unset
 
 
     pub description: String,
@@ -35,14 +43,123 @@ pub struct Event {
 
     #[serde(default)]
     pub attendees: Vec<Attendee>,
+    #[serde(default)]
+    pub capacity: Option<i32>, // seats, unlimited when unset
+    #[serde(default)]
+    pub waitlist: Vec<Attendee>, // in the order people joined, promoted when a seat frees up
 
     #[serde(skip_serializing_if = "Option::is_none")] // Only include if present
     pub image_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<EventImage>, // the stored cover image, image_url points at it
 
-    pub pinned: bool,
+    #[serde(default)]
+    pub sequence: i32, // iCalendar SEQUENCE, bumped on every change calendars should pick up
+    #[serde(default, with = "stored_date::option")]
+    pub cancelled_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series_id: Option<ObjectId>, // set on occurrences of a recurring series
 
 }
 
+// one upload, kept in every size we render
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EventImage {
+    pub upload_id: String,
+    pub variants: Vec<ImageVariant>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImageVariant {
+    pub name: String, // "original", "large", "medium" or "thumb"
+    pub key: String, // storage key
+    pub content_type: String,
+    pub width: u32,
+    pub height: u32,
+    pub size: u64, // bytes
+}
+
+impl Event {
+    pub fn registration_open(&self, now: DateTime<Utc>) -> bool {
+        if self.cancelled_at.is_some() {
+            return false;
+        }
+        let opened = self.registration_opens_at.map(|opens| now >= opens).unwrap_or(true);
+        let closes = self.registration_closes_at.unwrap_or(self.date);
+        opened && now < closes
+    }
+}
+
 pub fn default_datetime() -> DateTime<Utc> {
     Utc::now()
 }
+
+pub fn default_time_zone() -> String {
+    "UTC".to_string()
+}
+
+// event dates are stored as RFC 3339 strings in UTC with exactly three fractional digits, so
+// that comparing them as strings orders them in time. Queries have to use the same format.
+pub fn stored_date(date: &DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+pub mod stored_date {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(date: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&crate::models::event::stored_date(date))
+    }
+
+    // anything RFC 3339 is read, older documents were stored with whole seconds
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        DateTime::<Utc>::deserialize(deserializer)
+    }
+
+    pub mod option {
+        use chrono::{DateTime, Utc};
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(date: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error> {
+            match date {
+                Some(date) => serializer.serialize_some(&crate::models::event::stored_date(date)),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+            Option::<DateTime<Utc>>::deserialize(deserializer)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn stored_dates_sort_in_time() {
+        let whole = Utc.with_ymd_and_hms(2025, 6, 14, 18, 0, 0).unwrap();
+        let half = whole + chrono::Duration::milliseconds(500);
+        assert_eq!(stored_date(&whole), "2025-06-14T18:00:00.000Z");
+        assert!(stored_date(&whole) < stored_date(&half));
+    }
+
+    #[test]
+    fn reads_dates_stored_before_the_fixed_format() {
+        #[derive(Serialize, Deserialize)]
+        struct Dated {
+            #[serde(with = "stored_date")]
+            date: DateTime<Utc>,
+            #[serde(default, with = "stored_date::option")]
+            cancelled_at: Option<DateTime<Utc>>,
+        }
+        let dated: Dated = serde_json::from_str(r#"{"date": "2025-06-14T18:00:00Z", "cancelled_at": null}"#).unwrap();
+        assert_eq!(
+            serde_json::to_string(&dated).unwrap(),
+            r#"{"date":"2025-06-14T18:00:00.000Z","cancelled_at":null}"#
+        );
+    }
+}
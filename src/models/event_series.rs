@@ -0,0 +1,28 @@
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+use super::event::EventType;
+
+// template for recurring events, every occurrence is a regular `Event` with `series_id` set
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EventSeries {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    #[serde(default)]
+    pub location: String,
+    pub description: String,
+    pub event_type: EventType,
+    pub time_zone: String,
+    pub starts_at: String, // local start of the first occurrence, e.g. "2025-06-03T18:00"
+    pub duration_minutes: i64,
+    pub rrule: String, // e.g. "FREQ=WEEKLY;BYDAY=TU;COUNT=12"
+    #[serde(default)]
+    pub exceptions: Vec<String>, // local dates without an occurrence, e.g. "2025-12-23"
+    #[serde(default)]
+    pub capacity: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_id: Option<ObjectId>,
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,36 @@
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+pub const FEATURED_ID: &str = "featured";
+
+// the featured events live in one document so reordering them is a single atomic write.
+// `version` goes up with every change, writers compare it to avoid overwriting each other.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FeaturedEvents {
+    #[serde(rename = "_id")]
+    pub id: String, // always FEATURED_ID
+    #[serde(default)]
+    pub slots: Vec<FeaturedSlot>, // in display order
+    #[serde(default)]
+    pub version: i64,
+    #[serde(default)]
+    pub updated_by: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FeaturedSlot {
+    pub event_id: ObjectId,
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>, // drops out of the featured list after this, kept forever when unset
+    pub featured_by: String,
+    pub featured_at: DateTime<Utc>,
+}
+
+impl FeaturedSlot {
+    pub fn active(&self, now: DateTime<Utc>) -> bool {
+        self.until.map(|until| now < until).unwrap_or(true)
+    }
+}
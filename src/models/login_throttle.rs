@@ -0,0 +1,15 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+// failed login attempts for one key, either "email:<address>" or "ip:<address>"
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LoginThrottle {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub key: String,
+    pub failures: i32,
+    pub last_failure_at: DateTime,
+    #[serde(default)]
+    pub locked_until: Option<DateTime>,
+    pub expires_at: DateTime, // the counter is forgotten after a quiet period
+}
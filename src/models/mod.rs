@@ -3,8 +3,38 @@ pub mod event;
 pub  mod application;
 pub mod blacklist;
 pub mod attendee;
+pub mod refresh_token;
+pub mod role;
+pub mod wallet_challenge;
+pub mod password_reset;
+pub mod email_verification;
+pub mod login_throttle;
+pub mod session;
+pub mod api_key;
+pub mod two_factor;
+pub mod audit;
+pub mod oidc;
+pub mod check_in;
+pub mod calendar_feed;
+pub mod event_series;
+pub mod featured;
 pub use user::User;
 pub use event::Event;
 pub  use blacklist::BlackListedToken;
 pub use application::{Application, ApplicationStatus};
-pub use attendee::Attendee;
\ No newline at end of file
+pub use attendee::Attendee;
+pub use refresh_token::RefreshToken;
+pub use role::{Permission, Role};
+pub use wallet_challenge::WalletChallenge;
+pub use password_reset::PasswordResetToken;
+pub use email_verification::EmailVerificationToken;
+pub use login_throttle::LoginThrottle;
+pub use session::Session;
+pub use api_key::{ApiKey, ApiScope};
+pub use two_factor::{MfaChallenge, TwoFactor};
+pub use audit::AuditEntry;
+pub use oidc::{OidcIdentity, OidcLogin};
+pub use check_in::CheckIn;
+pub use calendar_feed::CalendarFeed;
+pub use event_series::EventSeries;
+pub use featured::{FeaturedEvents, FeaturedSlot};
\ No newline at end of file
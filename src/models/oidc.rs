@@ -0,0 +1,23 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+// account at an external OpenID Connect provider linked to a user
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct OidcIdentity {
+    pub provider: String,
+    pub subject: String, // `sub` claim, stable per provider
+}
+
+// an authorization request in flight, matched back up by `state` on the callback
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OidcLogin {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub provider: String,
+    pub state_hash: String,
+    pub code_verifier: String, // PKCE verifier, only its S256 challenge leaves the server
+    pub nonce: String,
+    #[serde(default)]
+    pub cookie_session: bool, // finish with a cookie session instead of returning tokens
+    pub expires_at: DateTime,
+}
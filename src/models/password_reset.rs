@@ -0,0 +1,13 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PasswordResetToken {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub token_hash: String, // sha256 of the token sent by mail
+    pub email: String,
+    pub expires_at: DateTime,
+    #[serde(default)]
+    pub used_at: Option<DateTime>,
+}
@@ -0,0 +1,17 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RefreshToken {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub token_hash: String, // sha256 of the token handed to the client, never the raw token
+    pub email: String,
+    pub family_id: String, // every token rotated from the same login shares this id
+    pub expires_at: DateTime,
+    pub created_at: DateTime,
+    #[serde(default)]
+    pub used_at: Option<DateTime>,
+    #[serde(default)]
+    pub revoked: bool,
+}
@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Member,
+    Organizer,
+    Reviewer,
+    CoreTeam,
+    SuperAdmin,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum Permission {
+    #[serde(rename = "event:create")]
+    EventCreate,
+    #[serde(rename = "event:update")]
+    EventUpdate,
+    #[serde(rename = "event:delete")]
+    EventDelete,
+    #[serde(rename = "application:review")]
+    ApplicationReview,
+    #[serde(rename = "user:manage")]
+    UserManage,
+    #[serde(rename = "role:manage")]
+    RoleManage,
+    #[serde(rename = "api_key:manage")]
+    ApiKeyManage,
+    #[serde(rename = "audit:read")]
+    AuditRead,
+    #[serde(rename = "event:feature")]
+    EventFeature,
+}
+
+impl Role {
+    pub fn permissions(&self) -> &'static [Permission] {
+        use Permission::*;
+        match self {
+            Role::Member => &[],
+            Role::Organizer => &[EventCreate, EventUpdate],
+            Role::Reviewer => &[ApplicationReview],
+            Role::CoreTeam => &[EventCreate, EventUpdate, EventDelete, ApplicationReview],
+            Role::SuperAdmin => &[EventCreate, EventUpdate, EventDelete, ApplicationReview, UserManage, RoleManage, ApiKeyManage, AuditRead, EventFeature],
+        }
+    }
+
+    // accounts holding these roles must have 2FA enabled to use them
+    pub fn requires_two_factor(&self) -> bool {
+        matches!(self, Role::CoreTeam | Role::SuperAdmin)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Member => "member",
+            Role::Organizer => "organizer",
+            Role::Reviewer => "reviewer",
+            Role::CoreTeam => "core_team",
+            Role::SuperAdmin => "super_admin",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Role> {
+        match role {
+            "member" => Some(Role::Member),
+            "organizer" => Some(Role::Organizer),
+            "reviewer" => Some(Role::Reviewer),
+            "core_team" => Some(Role::CoreTeam),
+            "super_admin" => Some(Role::SuperAdmin),
+            _ => None,
+        }
+    }
+}
+
+pub fn has_permission(roles: &[Role], permission: Permission) -> bool {
+    roles.iter().any(|role| role.permissions().contains(&permission))
+}
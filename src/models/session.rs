@@ -0,0 +1,23 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+// one login on one device, lives as long as its refresh token family
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Session {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub family_id: String,
+    pub email: String,
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    #[serde(default)]
+    pub ip: Option<String>,
+    pub created_at: DateTime,
+    pub last_used_at: DateTime,
+    pub expires_at: DateTime,
+    // latest access token handed out; the session stays blacklisted until it expires once revoked
+    pub access_jti: String,
+    pub access_expires_at: DateTime,
+    #[serde(default)]
+    pub revoked_at: Option<DateTime>,
+}
@@ -0,0 +1,27 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TwoFactor {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub email: String,
+    pub secret: String, // base32 TOTP secret
+    #[serde(default)]
+    pub enabled_at: Option<DateTime>, // None while enrollment is not confirmed
+    #[serde(default)]
+    pub recovery_codes: Vec<String>, // sha256 hashes, removed once used
+    #[serde(default)]
+    pub last_used_step: i64, // codes for this step or older are rejected
+}
+
+// issued after a correct password when the account has 2FA, exchanged for tokens with a code
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MfaChallenge {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub token_hash: String,
+    pub email: String,
+    pub attempts: i32,
+    pub expires_at: DateTime,
+}
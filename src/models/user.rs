@@ -2,23 +2,18 @@ use chrono::{DateTime, Utc};
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
-#[serde(rename_all = "lowercase")]
-pub enum UserType {
-    CORETEAM,
-    HACKER,
-    RANDOM
-}
+use super::oidc::OidcIdentity;
+use super::role::{self, Permission, Role};
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 #[serde(rename_all = "lowercase")]
-pub enum StudentClub {
+pub enum UserType {
     CORETEAM,
     HACKER,
     RANDOM
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 // #[serde(rename_all = "camelCase")]
 pub struct User {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
@@ -28,7 +23,10 @@ pub struct User {
     pub tel: String,
     pub password: String,
     pub wallet: String,
-    pub admin: Option<bool>,
+    #[serde(with = "chrono::serde::ts_seconds_option", default)] // set when a signed challenge proved the wallet
+    pub wallet_linked_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub roles: Vec<Role>,
     pub user_type: UserType,
     pub role: String,
     pub stack: Vec<String>,
@@ -43,6 +41,22 @@ pub struct User {
     pub created_at: DateTime<Utc>,
     #[serde(with = "chrono::serde::ts_seconds", default = "default_datetime")]
     pub updated_at: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_seconds_option", default)] // None until the email is confirmed
+    pub email_verified_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub two_factor_enabled: bool,
+    #[serde(default)]
+    pub identities: Vec<OidcIdentity>, // external logins linked to this account
+}
+
+impl User {
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        role::has_permission(&self.roles, permission)
+    }
+
+    pub fn requires_two_factor(&self) -> bool {
+        self.roles.iter().any(|role| role.requires_two_factor())
+    }
 }
 
 pub fn default_datetime() -> DateTime<Utc> {
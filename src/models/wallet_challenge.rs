@@ -0,0 +1,12 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WalletChallenge {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub wallet: String, // normalized Sui address
+    pub nonce: String,
+    pub message: String, // exact text the wallet has to sign
+    pub expires_at: DateTime,
+}
@@ -0,0 +1,469 @@
+use std::env;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL, Engine};
+use dotenv::dotenv;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+
+#[derive(Debug)]
+pub enum OidcError {
+    UnknownProvider,
+    Upstream(String), // the provider could not be reached or answered with garbage
+    InvalidToken,
+    EmailNotVerified,
+    DomainNotAllowed,
+}
+
+// the parts of the discovery document we use
+#[derive(Debug, Deserialize, Clone)]
+struct Metadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenEndpointResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: Option<Value>, // some providers send "true" as a string
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    nonce: Option<String>,
+}
+
+// who the provider says signed in
+pub struct VerifiedIdentity {
+    pub subject: String,
+    pub email: String,
+    pub email_verified: bool, // asserted by the provider, false when only let through by trust_email
+    pub name: Option<String>,
+}
+
+pub struct Provider {
+    pub name: String,
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    scopes: String,
+    allowed_domains: Vec<String>,
+    trust_email: bool,
+    metadata: RwLock<Option<Metadata>>,
+    jwks: RwLock<Option<JwkSet>>,
+}
+
+// configured identity providers, discovery documents and keys are fetched on first use and cached
+pub struct Providers {
+    providers: Vec<Provider>,
+    http: reqwest::Client,
+}
+
+// OIDC_PROVIDERS                 comma separated names, e.g. "google,microsoft"
+// OIDC_<NAME>_ISSUER             issuer URL, discovery is read from <issuer>/.well-known/openid-configuration
+// OIDC_<NAME>_CLIENT_ID
+// OIDC_<NAME>_CLIENT_SECRET      optional, public clients rely on PKCE alone
+// OIDC_<NAME>_REDIRECT_URI       must point at /api/v1/auth/oidc/<name>/callback
+// OIDC_<NAME>_SCOPES             "openid email profile" if unset
+// OIDC_<NAME>_ALLOWED_DOMAINS    optional comma separated email domains, e.g. the university's
+// OIDC_<NAME>_TRUST_EMAIL        "true" for providers that don't send email_verified (Microsoft),
+//                                such logins create accounts but never link to existing ones
+impl Providers {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let providers = env::var("OIDC_PROVIDERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let var = |key: &str| env::var(format!("OIDC_{}_{}", name.to_uppercase(), key)).ok();
+                let required = |key: &str| var(key).unwrap_or_else(|| panic!("OIDC_{}_{} must be set", name.to_uppercase(), key));
+                Provider {
+                    issuer: required("ISSUER").trim_end_matches('/').to_string(),
+                    client_id: required("CLIENT_ID"),
+                    client_secret: var("CLIENT_SECRET"),
+                    redirect_uri: required("REDIRECT_URI"),
+                    scopes: var("SCOPES").unwrap_or_else(|| "openid email profile".to_string()),
+                    allowed_domains: var("ALLOWED_DOMAINS")
+                        .unwrap_or_default()
+                        .split(',')
+                        .map(|domain| domain.trim().to_lowercase())
+                        .filter(|domain| !domain.is_empty())
+                        .collect(),
+                    trust_email: var("TRUST_EMAIL").map(|value| value == "true").unwrap_or(false),
+                    metadata: RwLock::new(None),
+                    jwks: RwLock::new(None),
+                    name,
+                }
+            })
+            .collect();
+
+        Providers { providers, http: reqwest::Client::new() }
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Provider, OidcError> {
+        self.providers.iter().find(|provider| provider.name == name).ok_or(OidcError::UnknownProvider)
+    }
+
+    async fn metadata(&self, provider: &Provider) -> Result<Metadata, OidcError> {
+        if let Some(metadata) = provider.metadata.read().await.as_ref() {
+            return Ok(metadata.clone());
+        }
+        let url = format!("{}/.well-known/openid-configuration", provider.issuer);
+        let metadata: Metadata = self.get_json(&url).await?;
+        if metadata.issuer.trim_end_matches('/') != provider.issuer {
+            return Err(OidcError::Upstream(format!("discovery issuer {} does not match", metadata.issuer)));
+        }
+        *provider.metadata.write().await = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    // providers rotate keys, so an unknown kid triggers one refetch
+    async fn decoding_key(&self, provider: &Provider, kid: &str) -> Result<DecodingKey, OidcError> {
+        for refresh in [false, true] {
+            if refresh || provider.jwks.read().await.is_none() {
+                let metadata = self.metadata(provider).await?;
+                let jwks: JwkSet = self.get_json(&metadata.jwks_uri).await?;
+                *provider.jwks.write().await = Some(jwks);
+            }
+            if let Some(jwk) = provider.jwks.read().await.as_ref().and_then(|jwks| jwks.find(kid)) {
+                return DecodingKey::from_jwk(jwk).map_err(|_| OidcError::InvalidToken);
+            }
+        }
+        Err(OidcError::InvalidToken)
+    }
+
+    async fn get_json<T: for<'de> Deserialize<'de>>(&self, url: &str) -> Result<T, OidcError> {
+        self.http
+            .get(url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| OidcError::Upstream(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| OidcError::Upstream(e.to_string()))
+    }
+
+    pub async fn authorization_url(&self, provider: &Provider, state: &str, nonce: &str, code_verifier: &str) -> Result<String, OidcError> {
+        let metadata = self.metadata(provider).await?;
+        let separator = if metadata.authorization_endpoint.contains('?') { '&' } else { '?' };
+        Ok(format!(
+            "{}{}response_type=code&client_id={}&redirect_uri={}&scope={}&state={}&nonce={}&code_challenge={}&code_challenge_method=S256",
+            metadata.authorization_endpoint,
+            separator,
+            urlencoding::encode(&provider.client_id),
+            urlencoding::encode(&provider.redirect_uri),
+            urlencoding::encode(&provider.scopes),
+            urlencoding::encode(state),
+            urlencoding::encode(nonce),
+            code_challenge(code_verifier),
+        ))
+    }
+
+    // trades the authorization code for an ID token and checks it
+    pub async fn complete(&self, provider: &Provider, code: &str, code_verifier: &str, nonce: &str) -> Result<VerifiedIdentity, OidcError> {
+        let metadata = self.metadata(provider).await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", provider.redirect_uri.as_str()),
+            ("client_id", provider.client_id.as_str()),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(secret) = &provider.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+        let tokens: TokenEndpointResponse = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| OidcError::Upstream(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| OidcError::Upstream(e.to_string()))?;
+
+        self.verify_id_token(provider, &metadata, &tokens.id_token, nonce).await
+    }
+
+    async fn verify_id_token(&self, provider: &Provider, metadata: &Metadata, id_token: &str, nonce: &str) -> Result<VerifiedIdentity, OidcError> {
+        let header = decode_header(id_token).map_err(|_| OidcError::InvalidToken)?;
+        if !matches!(header.alg, Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 | Algorithm::ES256 | Algorithm::ES384 | Algorithm::EdDSA) {
+            return Err(OidcError::InvalidToken);
+        }
+        let key = self.decoding_key(provider, header.kid.as_deref().unwrap_or_default()).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|_| OidcError::InvalidToken)?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(OidcError::InvalidToken);
+        }
+
+        let email = claims.email.map(|email| email.trim().to_lowercase()).ok_or(OidcError::EmailNotVerified)?;
+        let verified = match claims.email_verified {
+            Some(Value::Bool(verified)) => verified,
+            Some(Value::String(verified)) => verified == "true",
+            _ => false,
+        };
+        if !verified && !provider.trust_email {
+            return Err(OidcError::EmailNotVerified);
+        }
+        if !provider.allowed_domains.is_empty() {
+            let domain = email.rsplit('@').next().unwrap_or_default();
+            if !provider.allowed_domains.iter().any(|allowed| allowed == domain) {
+                return Err(OidcError::DomainNotAllowed);
+            }
+        }
+
+        Ok(VerifiedIdentity { subject: claims.sub, email, email_verified: verified, name: claims.name })
+    }
+}
+
+fn code_challenge(code_verifier: &str) -> String {
+    BASE64_URL.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::pkcs8::EncodePrivateKey;
+    use ed25519_dalek::SigningKey;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use rocket::tokio::net::TcpListener;
+    use serde_json::json;
+
+    const CLIENT_ID: &str = "soc-test";
+    const KID: &str = "mock-1";
+
+    // a local stand-in for the provider: discovery, JWKS and a token endpoint that
+    // hands back the authorization code as the ID token, so each test signs what it needs
+    struct MockIssuer {
+        issuer: String,
+        key: EncodingKey,
+    }
+
+    impl MockIssuer {
+        async fn start() -> MockIssuer {
+            let signing_key = SigningKey::from_bytes(&[7; 32]);
+            let x = BASE64_URL.encode(signing_key.verifying_key().as_bytes());
+            let key = EncodingKey::from_ed_der(signing_key.to_pkcs8_der().unwrap().as_bytes());
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let issuer = format!("http://{}", listener.local_addr().unwrap());
+            let discovery = json!({
+                "issuer": issuer,
+                "authorization_endpoint": format!("{}/authorize", issuer),
+                "token_endpoint": format!("{}/token", issuer),
+                "jwks_uri": format!("{}/jwks", issuer),
+            });
+            let jwks = json!({"keys": [{"kty": "OKP", "crv": "Ed25519", "x": x, "kid": KID, "alg": "EdDSA", "use": "sig"}]});
+
+            rocket::tokio::spawn(async move {
+                loop {
+                    let Ok((mut stream, _)) = listener.accept().await else { break };
+                    let (discovery, jwks) = (discovery.clone(), jwks.clone());
+                    rocket::tokio::spawn(async move {
+                        let request = read_request(&mut stream).await;
+                        let body = if request.starts_with("GET /.well-known/openid-configuration ") {
+                            discovery.to_string()
+                        } else if request.starts_with("GET /jwks ") {
+                            jwks.to_string()
+                        } else if request.starts_with("POST /token ") {
+                            let form = request.split("\r\n\r\n").nth(1).unwrap_or_default();
+                            let field = |name: &str| {
+                                form.split('&')
+                                    .find_map(|pair| pair.strip_prefix(&format!("{}=", name)))
+                                    .unwrap_or_default()
+                                    .to_string()
+                            };
+                            assert!(!field("code_verifier").is_empty(), "PKCE verifier missing");
+                            json!({"id_token": field("code"), "token_type": "Bearer"}).to_string()
+                        } else {
+                            String::new()
+                        };
+                        let status = if body.is_empty() { "404 Not Found" } else { "200 OK" };
+                        let response = format!(
+                            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            status,
+                            body.len(),
+                            body
+                        );
+                        stream.write_all(response.as_bytes()).await.ok();
+                    });
+                }
+            });
+            MockIssuer { issuer, key }
+        }
+
+        fn provider(&self, allowed_domains: &[&str]) -> Provider {
+            Provider {
+                name: "mock".to_string(),
+                issuer: self.issuer.clone(),
+                client_id: CLIENT_ID.to_string(),
+                client_secret: None,
+                redirect_uri: "http://localhost/api/v1/auth/oidc/mock/callback".to_string(),
+                scopes: "openid email profile".to_string(),
+                allowed_domains: allowed_domains.iter().map(|domain| domain.to_string()).collect(),
+                trust_email: false,
+                metadata: RwLock::new(None),
+                jwks: RwLock::new(None),
+            }
+        }
+
+        // an authorization code the token endpoint turns into this ID token
+        fn code(&self, claims: Value) -> String {
+            let mut header = Header::new(Algorithm::EdDSA);
+            header.kid = Some(KID.to_string());
+            encode(&header, &claims, &self.key).unwrap()
+        }
+
+        fn claims(&self, nonce: &str) -> Value {
+            json!({
+                "iss": self.issuer,
+                "aud": CLIENT_ID,
+                "sub": "user-123",
+                "exp": chrono::Utc::now().timestamp() + 300,
+                "email": "Ada@Example.org",
+                "email_verified": true,
+                "name": "Ada",
+                "nonce": nonce,
+            })
+        }
+    }
+
+    async fn read_request(stream: &mut rocket::tokio::net::TcpStream) -> String {
+        let mut buffer = Vec::new();
+        let mut chunk = [0; 4096];
+        loop {
+            let read = stream.read(&mut chunk).await.unwrap_or(0);
+            buffer.extend_from_slice(&chunk[..read]);
+            let text = String::from_utf8_lossy(&buffer).to_string();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|value| value.trim().to_string()))
+                    .and_then(|value| value.parse::<usize>().ok())
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    return text;
+                }
+            }
+            if read == 0 {
+                return text;
+            }
+        }
+    }
+
+    fn providers(provider: Provider) -> Providers {
+        Providers { providers: vec![provider], http: reqwest::Client::new() }
+    }
+
+    #[rocket::async_test]
+    async fn signs_in_with_a_valid_id_token() {
+        let issuer = MockIssuer::start().await;
+        let providers = providers(issuer.provider(&[]));
+        let provider = providers.get("mock").unwrap();
+
+        let url = providers.authorization_url(provider, "state-1", "nonce-1", "verifier-1").await.unwrap();
+        assert!(url.starts_with(&format!("{}/authorize?", issuer.issuer)));
+        assert!(url.contains("state=state-1"));
+        assert!(url.contains(&format!("code_challenge={}", code_challenge("verifier-1"))));
+
+        let code = issuer.code(issuer.claims("nonce-1"));
+        let identity = providers.complete(provider, &code, "verifier-1", "nonce-1").await.unwrap();
+        assert_eq!(identity.subject, "user-123");
+        assert_eq!(identity.email, "ada@example.org");
+        assert_eq!(identity.name.as_deref(), Some("Ada"));
+        assert!(identity.email_verified);
+    }
+
+    #[rocket::async_test]
+    async fn rejects_a_foreign_nonce() {
+        let issuer = MockIssuer::start().await;
+        let providers = providers(issuer.provider(&[]));
+        let provider = providers.get("mock").unwrap();
+
+        let code = issuer.code(issuer.claims("someone-elses-nonce"));
+        let result = providers.complete(provider, &code, "verifier-1", "nonce-1").await;
+        assert!(matches!(result, Err(OidcError::InvalidToken)));
+    }
+
+    #[rocket::async_test]
+    async fn rejects_tokens_for_another_client_or_issuer() {
+        let issuer = MockIssuer::start().await;
+        let providers = providers(issuer.provider(&[]));
+        let provider = providers.get("mock").unwrap();
+
+        let mut claims = issuer.claims("nonce-1");
+        claims["aud"] = json!("another-client");
+        let result = providers.complete(provider, &issuer.code(claims), "verifier-1", "nonce-1").await;
+        assert!(matches!(result, Err(OidcError::InvalidToken)));
+
+        let mut claims = issuer.claims("nonce-1");
+        claims["iss"] = json!("https://evil.example");
+        let result = providers.complete(provider, &issuer.code(claims), "verifier-1", "nonce-1").await;
+        assert!(matches!(result, Err(OidcError::InvalidToken)));
+    }
+
+    #[rocket::async_test]
+    async fn rejects_unverified_emails() {
+        let issuer = MockIssuer::start().await;
+        let providers = providers(issuer.provider(&[]));
+        let provider = providers.get("mock").unwrap();
+
+        let mut claims = issuer.claims("nonce-1");
+        claims["email_verified"] = json!("false");
+        let result = providers.complete(provider, &issuer.code(claims), "verifier-1", "nonce-1").await;
+        assert!(matches!(result, Err(OidcError::EmailNotVerified)));
+    }
+
+    #[rocket::async_test]
+    async fn trusted_emails_are_let_through_unverified() {
+        let issuer = MockIssuer::start().await;
+        let mut provider = issuer.provider(&[]);
+        provider.trust_email = true;
+        let providers = providers(provider);
+        let provider = providers.get("mock").unwrap();
+
+        let mut claims = issuer.claims("nonce-1");
+        claims.as_object_mut().unwrap().remove("email_verified");
+        let identity = providers.complete(provider, &issuer.code(claims), "verifier-1", "nonce-1").await.unwrap();
+        // the caller must not link this to an existing account
+        assert!(!identity.email_verified);
+    }
+
+    #[rocket::async_test]
+    async fn rejects_other_domains() {
+        let issuer = MockIssuer::start().await;
+        let providers = providers(issuer.provider(&["university.edu"]));
+        let provider = providers.get("mock").unwrap();
+
+        let code = issuer.code(issuer.claims("nonce-1"));
+        let result = providers.complete(provider, &code, "verifier-1", "nonce-1").await;
+        assert!(matches!(result, Err(OidcError::DomainNotAllowed)));
+    }
+}
@@ -0,0 +1,405 @@
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+
+// The RRULE subset event series support (RFC 5545 3.3.10):
+//   FREQ=WEEKLY  with BYDAY=MO,WE,...           e.g. FREQ=WEEKLY;BYDAY=TU,TH;COUNT=10
+//   FREQ=MONTHLY with BYMONTHDAY=n or BYDAY=nWD  e.g. FREQ=MONTHLY;BYDAY=-1FR;UNTIL=20251231T000000Z
+// plus INTERVAL and exactly one of COUNT or UNTIL, since every occurrence is stored as an event.
+// COUNT counts occurrences before exceptions are taken out, like EXDATE does.
+
+pub const MAX_OCCURRENCES: usize = 104; // two years of a weekly series
+const MAX_PERIODS: u32 = 1200; // weeks or months looked at before giving up
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frequency {
+    Weekly,
+    Monthly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Until {
+    Instant(DateTime<Utc>),
+    Date(NaiveDate), // inclusive, in the series' time zone
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub frequency: Frequency,
+    interval: u32,
+    by_day: Vec<(i32, Weekday)>, // ordinal is 0 unless monthly, -1 is the last one in the month
+    by_month_day: Option<i32>, // negative counts from the end of the month
+    count: Option<u32>,
+    until: Option<Until>,
+}
+
+impl Rule {
+    pub fn parse(rule: &str) -> Result<Rule, String> {
+        let rule = rule.trim();
+        let rule = rule.strip_prefix("RRULE:").unwrap_or(rule);
+
+        let mut frequency = None;
+        let mut interval = 1;
+        let mut by_day = Vec::new();
+        let mut by_month_day = None;
+        let mut count = None;
+        let mut until = None;
+
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or_else(|| format!("Malformed rule part {}.", part))?;
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.to_ascii_uppercase().as_str() {
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        other => return Err(format!("FREQ={} is not supported, use WEEKLY or MONTHLY.", other)),
+                    })
+                }
+                "INTERVAL" => {
+                    interval = value.parse().ok().filter(|interval| *interval >= 1).ok_or("INTERVAL has to be a positive number.")?
+                }
+                "COUNT" => count = Some(value.parse::<u32>().ok().filter(|count| *count >= 1).ok_or("COUNT has to be a positive number.")?),
+                "UNTIL" => until = Some(parse_until(value)?),
+                "BYDAY" => {
+                    by_day = value.split(',').map(parse_by_day).collect::<Result<Vec<_>, _>>()?;
+                }
+                "BYMONTHDAY" => {
+                    let day: i32 = value.parse().map_err(|_| "BYMONTHDAY has to be a single day of the month.")?;
+                    if day == 0 || day.abs() > 31 {
+                        return Err("BYMONTHDAY has to be between 1 and 31, or -31 and -1.".to_string());
+                    }
+                    by_month_day = Some(day);
+                }
+                other => return Err(format!("{} is not supported in recurrence rules.", other)),
+            }
+        }
+
+        let frequency = frequency.ok_or("The rule needs a FREQ.")?;
+        match (count, until) {
+            (Some(_), Some(_)) => return Err("Use either COUNT or UNTIL, not both.".to_string()),
+            (None, None) => return Err("The rule needs a COUNT or an UNTIL.".to_string()),
+            _ => {}
+        }
+        match frequency {
+            Frequency::Weekly => {
+                if by_month_day.is_some() {
+                    return Err("BYMONTHDAY only works with FREQ=MONTHLY.".to_string());
+                }
+                if by_day.iter().any(|(ordinal, _)| *ordinal != 0) {
+                    return Err("Weekly rules take plain weekdays in BYDAY, like TU.".to_string());
+                }
+            }
+            Frequency::Monthly => {
+                if by_month_day.is_some() && !by_day.is_empty() {
+                    return Err("Use either BYDAY or BYMONTHDAY, not both.".to_string());
+                }
+                if by_day.len() > 1 || by_day.iter().any(|(ordinal, _)| *ordinal == 0 || ordinal.abs() > 5) {
+                    return Err("Monthly rules take one weekday with its position in BYDAY, like 2TU or -1FR.".to_string());
+                }
+            }
+        }
+
+        Ok(Rule { frequency, interval, by_day, by_month_day, count, until })
+    }
+
+    // start times of the occurrences, the first one on or after `start`
+    pub fn expand(&self, start: NaiveDateTime, time_zone: Tz, exceptions: &[NaiveDate]) -> Result<Vec<DateTime<Utc>>, String> {
+        let mut occurrences = Occurrences { rule: self, time_zone, exceptions, seen: 0, found: Vec::new() };
+        let first_day = start.date();
+
+        match self.frequency {
+            Frequency::Weekly => {
+                let mut weekdays: Vec<Weekday> = if self.by_day.is_empty() {
+                    vec![first_day.weekday()]
+                } else {
+                    self.by_day.iter().map(|(_, weekday)| *weekday).collect()
+                };
+                weekdays.sort_by_key(|weekday| weekday.num_days_from_monday());
+                weekdays.dedup();
+
+                let first_monday = first_day - Duration::days(first_day.weekday().num_days_from_monday() as i64);
+                'weeks: for period in (0..MAX_PERIODS).step_by(self.interval as usize) {
+                    for weekday in &weekdays {
+                        let day = first_monday + Duration::weeks(period as i64) + Duration::days(weekday.num_days_from_monday() as i64);
+                        if day >= first_day && !occurrences.push(day.and_time(start.time()))? {
+                            break 'weeks;
+                        }
+                    }
+                }
+            }
+            Frequency::Monthly => {
+                for period in (0..MAX_PERIODS).step_by(self.interval as usize) {
+                    let months = first_day.month0() + period;
+                    let year = first_day.year() + (months / 12) as i32;
+                    let month = months % 12 + 1;
+                    let day = match (self.by_month_day, self.by_day.first()) {
+                        (Some(month_day), _) => nth_month_day(year, month, month_day),
+                        (None, Some((ordinal, weekday))) => nth_weekday(year, month, *ordinal, *weekday),
+                        (None, None) => NaiveDate::from_ymd_opt(year, month, first_day.day()),
+                    };
+                    // months without that day are skipped, like RFC 5545 does
+                    if let Some(day) = day.filter(|day| *day >= first_day) {
+                        if !occurrences.push(day.and_time(start.time()))? {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        if occurrences.found.is_empty() {
+            return Err("The rule doesn't produce any occurrences.".to_string());
+        }
+        Ok(occurrences.found)
+    }
+}
+
+struct Occurrences<'a> {
+    rule: &'a Rule,
+    time_zone: Tz,
+    exceptions: &'a [NaiveDate],
+    seen: u32,
+    found: Vec<DateTime<Utc>>,
+}
+
+impl Occurrences<'_> {
+    // false once the rule has ended
+    fn push(&mut self, local: NaiveDateTime) -> Result<bool, String> {
+        if self.rule.count.map(|count| self.seen >= count).unwrap_or(false) {
+            return Ok(false);
+        }
+        let instant = resolve_local(self.time_zone, local);
+        let ended = match self.rule.until {
+            Some(Until::Instant(until)) => instant > until,
+            Some(Until::Date(until)) => local.date() > until,
+            None => false,
+        };
+        if ended {
+            return Ok(false);
+        }
+
+        self.seen += 1;
+        if !self.exceptions.contains(&local.date()) {
+            if self.found.len() == MAX_OCCURRENCES {
+                return Err(format!("A series can have at most {} occurrences.", MAX_OCCURRENCES));
+            }
+            self.found.push(instant);
+        }
+        Ok(true)
+    }
+}
+
+// a local time in a DST gap moves forward by the gap, in an overlap the first one is used
+pub fn resolve_local(time_zone: Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    match time_zone.from_local_datetime(&local) {
+        LocalResult::Single(instant) | LocalResult::Ambiguous(instant, _) => instant.with_timezone(&Utc),
+        LocalResult::None => resolve_local(time_zone, local + Duration::hours(1)),
+    }
+}
+
+fn parse_until(value: &str) -> Result<Until, String> {
+    if let Ok(instant) = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ") {
+        return Ok(Until::Instant(instant.and_utc()));
+    }
+    NaiveDate::parse_from_str(value, "%Y%m%d")
+        .map(Until::Date)
+        .map_err(|_| "UNTIL has to look like 20251231 or 20251231T235959Z.".to_string())
+}
+
+fn parse_by_day(value: &str) -> Result<(i32, Weekday), String> {
+    let value = value.trim().to_ascii_uppercase();
+    // split_at below takes a byte index, the weekday has to be two ASCII letters
+    if value.len() < 2 || !value.is_char_boundary(value.len() - 2) {
+        return Err(format!("{} is not a weekday.", value));
+    }
+    let (ordinal, day) = value.split_at(value.len() - 2);
+    let weekday = match day {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return Err(format!("{} is not a weekday.", value)),
+    };
+    let ordinal = match ordinal {
+        "" => 0,
+        ordinal => ordinal.trim_start_matches('+').parse().map_err(|_| format!("{} is not a weekday.", value))?,
+    };
+    Ok((ordinal, weekday))
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    NaiveDate::from_ymd_opt(next_year, next_month, 1)
+        .and_then(|first| first.pred_opt())
+        .map(|last| last.day())
+        .unwrap_or(28)
+}
+
+fn nth_month_day(year: i32, month: u32, day: i32) -> Option<NaiveDate> {
+    let days = days_in_month(year, month) as i32;
+    let day = if day > 0 { day } else { days + 1 + day };
+    if day < 1 || day > days {
+        return None;
+    }
+    NaiveDate::from_ymd_opt(year, month, day as u32)
+}
+
+fn nth_weekday(year: i32, month: u32, ordinal: i32, weekday: Weekday) -> Option<NaiveDate> {
+    let days = days_in_month(year, month) as i32;
+    let day = if ordinal > 0 {
+        let first = NaiveDate::from_ymd_opt(year, month, 1)?.weekday();
+        let offset = (weekday.num_days_from_monday() as i32 - first.num_days_from_monday() as i32).rem_euclid(7);
+        1 + offset + (ordinal - 1) * 7
+    } else {
+        let last = NaiveDate::from_ymd_opt(year, month, days as u32)?.weekday();
+        let offset = (last.num_days_from_monday() as i32 - weekday.num_days_from_monday() as i32).rem_euclid(7);
+        days - offset + (ordinal + 1) * 7
+    };
+    if day < 1 || day > days {
+        return None; // no fifth Friday this month
+    }
+    NaiveDate::from_ymd_opt(year, month, day as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M").unwrap()
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    // occurrences as UTC instants, e.g. "2025-06-03T16:00Z"
+    fn expand(rule: &str, start: &str, time_zone: Tz, exceptions: &[&str]) -> Vec<String> {
+        let exceptions: Vec<NaiveDate> = exceptions.iter().map(|day| date(day)).collect();
+        Rule::parse(rule)
+            .unwrap()
+            .expand(local(start), time_zone, &exceptions)
+            .unwrap()
+            .iter()
+            .map(|instant| instant.format("%Y-%m-%dT%H:%MZ").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn weekly_with_several_days_and_an_interval() {
+        assert_eq!(
+            expand("FREQ=WEEKLY;INTERVAL=2;BYDAY=TH,TU;COUNT=6", "2025-06-03T18:00", Tz::UTC, &[]),
+            [
+                "2025-06-03T18:00Z",
+                "2025-06-05T18:00Z",
+                "2025-06-17T18:00Z",
+                "2025-06-19T18:00Z",
+                "2025-07-01T18:00Z",
+                "2025-07-03T18:00Z",
+            ]
+        );
+        // days of the first week before the start don't count
+        assert_eq!(
+            expand("FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,TH;COUNT=3", "2025-06-05T18:00", Tz::UTC, &[]),
+            ["2025-06-05T18:00Z", "2025-06-17T18:00Z", "2025-06-19T18:00Z"]
+        );
+    }
+
+    #[test]
+    fn monthly_by_weekday_position() {
+        assert_eq!(
+            expand("FREQ=MONTHLY;BYDAY=-1FR;COUNT=4", "2025-01-01T18:00", Tz::UTC, &[]),
+            ["2025-01-31T18:00Z", "2025-02-28T18:00Z", "2025-03-28T18:00Z", "2025-04-25T18:00Z"]
+        );
+        // February to April and June and July 2025 have four Fridays
+        assert_eq!(
+            expand("FREQ=MONTHLY;BYDAY=5FR;COUNT=3", "2025-01-01T18:00", Tz::UTC, &[]),
+            ["2025-01-31T18:00Z", "2025-05-30T18:00Z", "2025-08-29T18:00Z"]
+        );
+    }
+
+    #[test]
+    fn monthly_by_negative_month_day() {
+        assert_eq!(
+            expand("FREQ=MONTHLY;BYMONTHDAY=-1;COUNT=4", "2024-01-01T18:00", Tz::UTC, &[]),
+            ["2024-01-31T18:00Z", "2024-02-29T18:00Z", "2024-03-31T18:00Z", "2024-04-30T18:00Z"]
+        );
+        assert_eq!(
+            expand("FREQ=MONTHLY;BYMONTHDAY=-3;COUNT=2", "2025-02-01T18:00", Tz::UTC, &[]),
+            ["2025-02-26T18:00Z", "2025-03-29T18:00Z"]
+        );
+    }
+
+    #[test]
+    fn until_a_date_includes_that_local_day() {
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        assert_eq!(
+            expand("FREQ=WEEKLY;BYDAY=TU;UNTIL=20250617", "2025-06-03T18:00", berlin, &[]),
+            ["2025-06-03T16:00Z", "2025-06-10T16:00Z", "2025-06-17T16:00Z"]
+        );
+    }
+
+    #[test]
+    fn until_an_instant_cuts_off_at_that_instant() {
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        assert_eq!(
+            expand("FREQ=WEEKLY;BYDAY=TU;UNTIL=20250617T155959Z", "2025-06-03T18:00", berlin, &[]),
+            ["2025-06-03T16:00Z", "2025-06-10T16:00Z"]
+        );
+        assert_eq!(
+            expand("FREQ=WEEKLY;BYDAY=TU;UNTIL=20250617T160000Z", "2025-06-03T18:00", berlin, &[]),
+            ["2025-06-03T16:00Z", "2025-06-10T16:00Z", "2025-06-17T16:00Z"]
+        );
+    }
+
+    #[test]
+    fn count_includes_exceptions() {
+        assert_eq!(
+            expand("FREQ=WEEKLY;BYDAY=TU;COUNT=4", "2025-06-03T18:00", Tz::UTC, &["2025-06-10"]),
+            ["2025-06-03T18:00Z", "2025-06-17T18:00Z", "2025-06-24T18:00Z"]
+        );
+    }
+
+    #[test]
+    fn refuses_more_than_max_occurrences() {
+        let start = local("2025-06-03T18:00");
+        let rule = |count: usize| Rule::parse(&format!("FREQ=WEEKLY;COUNT={}", count)).unwrap();
+        assert_eq!(rule(MAX_OCCURRENCES).expand(start, Tz::UTC, &[]).unwrap().len(), MAX_OCCURRENCES);
+        assert!(rule(MAX_OCCURRENCES + 1).expand(start, Tz::UTC, &[]).is_err());
+        // an exception makes room for one more
+        let found = rule(MAX_OCCURRENCES + 1).expand(start, Tz::UTC, &[date("2025-06-10")]).unwrap();
+        assert_eq!(found.len(), MAX_OCCURRENCES);
+        // rules without an end in sight are refused too
+        let until = Rule::parse("FREQ=WEEKLY;UNTIL=20991231").unwrap();
+        assert!(until.expand(start, Tz::UTC, &[]).is_err());
+    }
+
+    #[test]
+    fn local_times_stay_put_across_dst() {
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        assert_eq!(
+            expand("FREQ=WEEKLY;BYDAY=TU;COUNT=2", "2025-03-25T18:00", berlin, &[]),
+            ["2025-03-25T17:00Z", "2025-04-01T16:00Z"]
+        );
+        // 02:30 doesn't exist on 2025-03-30 and moves to 03:30
+        assert_eq!(
+            expand("FREQ=WEEKLY;BYDAY=SU;COUNT=3", "2025-03-23T02:30", berlin, &[]),
+            ["2025-03-23T01:30Z", "2025-03-30T01:30Z", "2025-04-06T00:30Z"]
+        );
+        // 02:30 happens twice on 2025-10-26, the first one (still CEST) is used
+        assert_eq!(
+            expand("FREQ=WEEKLY;BYDAY=SU;COUNT=3", "2025-10-19T02:30", berlin, &[]),
+            ["2025-10-19T00:30Z", "2025-10-26T00:30Z", "2025-11-02T01:30Z"]
+        );
+    }
+
+    #[test]
+    fn by_day_rejects_non_ascii_without_panicking() {
+        assert!(Rule::parse("FREQ=WEEKLY;BYDAY=€;COUNT=3").is_err());
+        assert!(Rule::parse("FREQ=MONTHLY;BYDAY=1€;COUNT=3").is_err());
+        assert!(Rule::parse("FREQ=MONTHLY;BYDAY=€MO;COUNT=3").is_err());
+        assert!(Rule::parse("FREQ=WEEKLY;BYDAY=TU,TH;COUNT=3").is_ok());
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use futures::TryStreamExt;
+use mongodb::bson::{doc, DateTime as BsonDateTime};
+use mongodb::change_stream::event::OperationType;
+use mongodb::Collection;
+
+use crate::models::BlackListedToken;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+// re-read a little before the last revocation seen to cover clock skew between instances
+const POLL_OVERLAP_MS: i64 = 5_000;
+
+// revoked access token ids and session ids kept in memory until the tokens expire, so checking
+// a token never waits on the database. Loaded at startup and kept in sync with revocations made
+// by other instances by tailing the blacklist collection.
+#[derive(Clone, Default)]
+pub struct RevocationCache {
+    revoked: Arc<RwLock<HashMap<String, i64>>>, // jti -> exp in millis
+    sessions: Arc<RwLock<HashMap<String, i64>>>, // sid -> exp of the session's last access token in millis
+}
+
+impl RevocationCache {
+    pub async fn load(db: &Collection<BlackListedToken>) -> Self {
+        let cache = RevocationCache::default();
+        cache
+            .catch_up(db, BsonDateTime::from_millis(0))
+            .await
+            .expect("failed to load revoked tokens");
+        cache
+    }
+
+    pub fn is_revoked(&self, jti: &str) -> bool {
+        contains(&self.revoked, jti)
+    }
+
+    pub fn is_session_revoked(&self, sid: &str) -> bool {
+        contains(&self.sessions, sid)
+    }
+
+    pub fn insert(&self, jti: &str, expires_at: BsonDateTime) {
+        let mut revoked = self.revoked.write().unwrap_or_else(|e| e.into_inner());
+        revoked.insert(jti.to_string(), expires_at.timestamp_millis());
+    }
+
+    pub fn insert_session(&self, sid: &str, expires_at: BsonDateTime) {
+        let mut sessions = self.sessions.write().unwrap_or_else(|e| e.into_inner());
+        sessions.insert(sid.to_string(), expires_at.timestamp_millis());
+    }
+
+    fn remember(&self, entry: &BlackListedToken) {
+        if let Some(jti) = &entry.jti {
+            self.insert(jti, entry.expires_at);
+        }
+        if let Some(sid) = &entry.sid {
+            self.insert_session(sid, entry.expires_at);
+        }
+    }
+
+    // expired tokens fail the signature check anyway
+    fn prune(&self) {
+        let now = BsonDateTime::now().timestamp_millis();
+        for map in [&self.revoked, &self.sessions] {
+            let mut map = map.write().unwrap_or_else(|e| e.into_inner());
+            map.retain(|_, expires_at| *expires_at > now);
+        }
+    }
+
+    // adds every revocation made since `since`, returns the newest blacklist_at seen
+    async fn catch_up(&self, db: &Collection<BlackListedToken>, since: BsonDateTime) -> Result<BsonDateTime, mongodb::error::Error> {
+        let filter = doc! {"blacklist_at": {"$gte": since}, "expires_at": {"$gt": BsonDateTime::now()}};
+        let mut cursor = db.find(filter).await?;
+        let mut latest = since;
+        while let Some(entry) = cursor.try_next().await? {
+            self.remember(&entry);
+            latest = latest.max(entry.blacklist_at);
+        }
+        Ok(latest)
+    }
+
+    // follows new revocations with a change stream, or by polling where the server has none
+    // (standalone mongod in development)
+    pub fn spawn_sync(&self, db: Collection<BlackListedToken>) {
+        let cache = self.clone();
+        tokio::spawn(async move {
+            let started = BsonDateTime::now();
+            if let Err(e) = cache.tail(&db, started).await {
+                rocket::log::private::warn!("Revocation change stream unavailable, polling instead: {}", e);
+            }
+            cache.poll(&db, started).await;
+        });
+
+        let cache = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(PRUNE_INTERVAL).await;
+                cache.prune();
+            }
+        });
+    }
+
+    async fn tail(&self, db: &Collection<BlackListedToken>, started: BsonDateTime) -> Result<(), mongodb::error::Error> {
+        let mut stream = db.watch().pipeline([doc! {"$match": {"operationType": "insert"}}]).await?;
+        // revocations between loading and the stream opening
+        self.catch_up(db, BsonDateTime::from_millis(started.timestamp_millis() - POLL_OVERLAP_MS)).await?;
+        while let Some(event) = stream.try_next().await? {
+            if event.operation_type != OperationType::Insert {
+                continue;
+            }
+            if let Some(entry) = event.full_document {
+                self.remember(&entry);
+            }
+        }
+        Ok(())
+    }
+
+    async fn poll(&self, db: &Collection<BlackListedToken>, started: BsonDateTime) {
+        let mut since = started;
+        loop {
+            let from = BsonDateTime::from_millis(since.timestamp_millis() - POLL_OVERLAP_MS);
+            match self.catch_up(db, from).await {
+                Ok(latest) => since = since.max(latest),
+                Err(e) => rocket::log::private::warn!("Failed to poll revoked tokens: {}", e),
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+fn contains(map: &RwLock<HashMap<String, i64>>, key: &str) -> bool {
+    let map = map.read().unwrap_or_else(|e| e.into_inner());
+    match map.get(key) {
+        Some(expires_at) => *expires_at > BsonDateTime::now().timestamp_millis(),
+        None => false,
+    }
+}
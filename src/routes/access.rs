@@ -0,0 +1,223 @@
+use mongodb::bson::oid::ObjectId;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+
+use crate::models::{Permission, User};
+use super::auth::{check_permission, AuthContext};
+
+// which permission lets a caller act for another user, per route. Acting for yourself needs none.
+pub mod policy {
+    use crate::models::Permission;
+
+    pub const DROP_USER: Permission = Permission::UserManage;
+    pub const UPDATE_USER: Permission = Permission::UserManage;
+    pub const JOIN_EVENT: Permission = Permission::EventUpdate;
+    pub const LEAVE_EVENT: Permission = Permission::EventUpdate;
+    pub const APPLY_FOR_EVENT: Permission = Permission::ApplicationReview;
+}
+
+// the user making the request, taken from the request's auth context
+pub struct Actor {
+    pub user: User,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Actor {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        req.guard::<&AuthContext>().await.map(|context| Actor { user: context.user.clone() })
+    }
+}
+
+impl Actor {
+    pub fn id(&self) -> ObjectId {
+        self.user.id.unwrap_or_default()
+    }
+
+    // members may act on their own resources, anything else needs `permission`, with the same
+    // 2FA rule as Authorized
+    pub fn can_act_for(&self, owner: &ObjectId, permission: Permission) -> bool {
+        &self.id() == owner || check_permission(&self.user, permission).is_ok()
+    }
+
+    pub fn authorize(&self, owner: &ObjectId, permission: Permission) -> Result<(), Status> {
+        if self.can_act_for(owner, permission) {
+            Ok(())
+        } else {
+            Err(Status::Forbidden)
+        }
+    }
+
+    // the user a request targets: the caller when no id is given, otherwise the parsed id
+    // once the caller is allowed to act for it
+    pub fn resolve_target(&self, user_id: Option<&str>, permission: Permission) -> Result<ObjectId, Status> {
+        let target = match user_id {
+            Some(id) => ObjectId::parse_str(id).map_err(|_| Status::BadRequest)?,
+            None => return Ok(self.id()),
+        };
+        self.authorize(&target, permission)?;
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use crate::models::user::UserType;
+    use crate::models::Role;
+    use crate::routes::application::ApplyRequest;
+    use crate::routes::event::UserJoinRequest;
+
+    fn user(roles: &[Role]) -> User {
+        User {
+            id: Some(ObjectId::new()),
+            name: "Ada".to_string(),
+            email: "ada@example.org".to_string(),
+            tel: String::new(),
+            password: String::new(),
+            wallet: String::new(),
+            wallet_linked_at: None,
+            roles: roles.to_vec(),
+            user_type: UserType::RANDOM,
+            role: String::new(),
+            stack: vec![],
+            graduate: false,
+            level: 0,
+            department: String::new(),
+            university: String::new(),
+            student: String::new(),
+            attending_events: vec![],
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            email_verified_at: Some(Utc::now()),
+            two_factor_enabled: false,
+            identities: vec![],
+        }
+    }
+
+    fn actor(roles: &[Role]) -> Actor {
+        Actor { user: user(roles) }
+    }
+
+    // routes where members act for themselves, with a role that may act for others
+    const ROUTES: [(&str, Permission, Role); 5] = [
+        ("drop_user", policy::DROP_USER, Role::SuperAdmin),
+        ("update_user", policy::UPDATE_USER, Role::SuperAdmin),
+        ("join_event", policy::JOIN_EVENT, Role::Organizer),
+        ("leave_event", policy::LEAVE_EVENT, Role::Organizer),
+        ("apply_for_event", policy::APPLY_FOR_EVENT, Role::Reviewer),
+    ];
+
+    #[test]
+    fn members_act_for_themselves() {
+        for (route, permission, _) in ROUTES {
+            let member = actor(&[Role::Member]);
+            let own_id = member.id();
+            assert_eq!(member.authorize(&own_id, permission), Ok(()), "{}", route);
+            assert_eq!(member.resolve_target(None, permission), Ok(own_id), "{}", route);
+            assert_eq!(member.resolve_target(Some(&own_id.to_hex()), permission), Ok(own_id), "{}", route);
+        }
+    }
+
+    #[test]
+    fn members_cannot_act_for_others() {
+        for (route, permission, _) in ROUTES {
+            let member = actor(&[Role::Member]);
+            let other = ObjectId::new();
+            assert_eq!(member.authorize(&other, permission), Err(Status::Forbidden), "{}", route);
+            assert_eq!(member.resolve_target(Some(&other.to_hex()), permission), Err(Status::Forbidden), "{}", route);
+        }
+    }
+
+    #[test]
+    fn permitted_roles_act_for_others() {
+        for (route, permission, role) in ROUTES {
+            let mut privileged = actor(&[Role::Member, role]);
+            privileged.user.two_factor_enabled = true;
+            let other = ObjectId::new();
+            assert_eq!(privileged.authorize(&other, permission), Ok(()), "{}", route);
+            assert_eq!(privileged.resolve_target(Some(&other.to_hex()), permission), Ok(other), "{}", route);
+        }
+    }
+
+    #[test]
+    fn privileged_roles_need_two_factor_to_act_for_others() {
+        for (route, permission, role) in ROUTES {
+            let privileged = actor(&[Role::Member, role]);
+            let other = ObjectId::new();
+            let expected = if role.requires_two_factor() { Err(Status::Forbidden) } else { Ok(()) };
+            assert_eq!(privileged.authorize(&other, permission), expected, "{}", route);
+        }
+        // core team can register people for events, but not before turning on 2FA
+        let core_team = actor(&[Role::CoreTeam]);
+        let other = ObjectId::new();
+        assert_eq!(core_team.resolve_target(Some(&other.to_hex()), policy::JOIN_EVENT), Err(Status::Forbidden));
+        // their own account stays reachable so they can turn it on
+        assert_eq!(core_team.authorize(&core_team.id(), policy::UPDATE_USER), Ok(()));
+    }
+
+    // the calls below are the ones the routes make with their request bodies and path ids
+    #[test]
+    fn routes_resolve_targets_from_their_requests() {
+        let member = actor(&[Role::Member]);
+        let organizer = actor(&[Role::Organizer]); // organizers don't need 2FA
+        let other = ObjectId::new();
+
+        // join_event: POST /event/<id>/join with an optional user_id
+        let own: UserJoinRequest = serde_json::from_str("{}").unwrap();
+        assert_eq!(member.resolve_target(own.user_id.as_deref(), policy::JOIN_EVENT), Ok(member.id()));
+        let for_other: UserJoinRequest = serde_json::from_value(serde_json::json!({"user_id": other.to_hex()})).unwrap();
+        assert_eq!(member.resolve_target(for_other.user_id.as_deref(), policy::JOIN_EVENT), Err(Status::Forbidden));
+        assert_eq!(organizer.resolve_target(for_other.user_id.as_deref(), policy::JOIN_EVENT), Ok(other));
+
+        // leave_event: the user id is a path segment
+        let path_id = other.to_hex();
+        assert_eq!(member.resolve_target(Some(path_id.as_str()), policy::LEAVE_EVENT), Err(Status::Forbidden));
+        assert_eq!(organizer.resolve_target(Some(path_id.as_str()), policy::LEAVE_EVENT), Ok(other));
+
+        // apply_for_event: organizers register people but don't apply for them
+        let apply: ApplyRequest = serde_json::from_value(serde_json::json!({"user_id": other.to_hex(), "event_id": ObjectId::new().to_hex()})).unwrap();
+        assert_eq!(organizer.resolve_target(apply.user_id.as_deref(), policy::APPLY_FOR_EVENT), Err(Status::Forbidden));
+        let reviewer = actor(&[Role::Reviewer]);
+        assert_eq!(reviewer.resolve_target(apply.user_id.as_deref(), policy::APPLY_FOR_EVENT), Ok(other));
+
+        // drop_user and update_user parse the path id and then authorize against it
+        let target = ObjectId::parse_str(other.to_hex()).unwrap();
+        let mut admin = actor(&[Role::SuperAdmin]);
+        assert_eq!(admin.authorize(&target, policy::DROP_USER), Err(Status::Forbidden));
+        admin.user.two_factor_enabled = true;
+        assert_eq!(admin.authorize(&target, policy::DROP_USER), Ok(()));
+        assert_eq!(admin.authorize(&target, policy::UPDATE_USER), Ok(()));
+    }
+
+    #[test]
+    fn other_roles_do_not_carry_over() {
+        // an organizer can register people for events but not apply for them or manage accounts
+        let organizer = actor(&[Role::Organizer]);
+        let other = ObjectId::new();
+        assert_eq!(organizer.authorize(&other, policy::APPLY_FOR_EVENT), Err(Status::Forbidden));
+        assert_eq!(organizer.authorize(&other, policy::DROP_USER), Err(Status::Forbidden));
+        assert_eq!(organizer.authorize(&other, policy::UPDATE_USER), Err(Status::Forbidden));
+    }
+
+    #[test]
+    fn invalid_target_ids_are_bad_requests() {
+        let member = actor(&[Role::Member]);
+        assert_eq!(member.resolve_target(Some("not-an-id"), policy::JOIN_EVENT), Err(Status::BadRequest));
+    }
+
+    // update_user_rank has no self service, it goes through Authorized<perm::RoleManage>
+    #[test]
+    fn update_user_rank_needs_role_manage() {
+        assert_eq!(check_permission(&user(&[Role::Member]), Permission::RoleManage), Err(Status::Forbidden));
+        assert_eq!(check_permission(&user(&[Role::CoreTeam]), Permission::RoleManage), Err(Status::Forbidden));
+
+        // super admins need 2FA turned on first
+        let mut admin = user(&[Role::SuperAdmin]);
+        assert_eq!(check_permission(&admin, Permission::RoleManage), Err(Status::Forbidden));
+        admin.two_factor_enabled = true;
+        assert_eq!(check_permission(&admin, Permission::RoleManage), Ok(()));
+    }
+}
@@ -0,0 +1,281 @@
+use std::marker::PhantomData;
+
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, Bson, DateTime as BsonDateTime};
+use mongodb::Collection;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket::{post, State};
+use serde::{Deserialize, Serialize};
+
+use crate::models::{ApiKey, ApiScope, Permission, User};
+use super::auth::{check_permission, hash_token, perm, random_token, AuthContext, Authorized};
+use super::audit::{snapshot, Audit};
+
+const KEY_PREFIX: &str = "soc_";
+
+// scope a route requires from api keys, declared through the marker types in `scope`,
+// and the permission a signed in user needs in its place (none: any user will do)
+pub trait RequiredScope {
+    const SCOPE: ApiScope;
+    const PERMISSION: Option<Permission>;
+}
+
+macro_rules! scope_markers {
+    ($($name:ident => $permission:expr),*) => {
+        $(
+            pub struct $name;
+            impl RequiredScope for $name {
+                const SCOPE: ApiScope = ApiScope::$name;
+                const PERMISSION: Option<Permission> = $permission;
+            }
+        )*
+    };
+}
+
+pub mod scope {
+    use super::RequiredScope;
+    use crate::models::{ApiScope, Permission};
+
+    scope_markers!(
+        EventsRead => None,
+        AttendeesCheckIn => Some(Permission::EventUpdate),
+        Export => Some(Permission::EventUpdate)
+    );
+}
+
+pub enum Caller {
+    User { email: String },
+    ApiKey { id: ObjectId, name: String },
+}
+
+// request guard accepting either a user's bearer token or an `x-api-key` holding the scope,
+// e.g. `_caller: ScopedCaller<scope::EventsRead>`
+pub struct ScopedCaller<S: RequiredScope> {
+    pub caller: Caller,
+    _scope: PhantomData<S>,
+}
+
+// a signed in user stands in for an api key only when they hold the scope's permission
+fn user_has_scope<S: RequiredScope>(user: &User) -> Result<(), Status> {
+    match S::PERMISSION {
+        Some(permission) => check_permission(user, permission),
+        None => Ok(()),
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, S: RequiredScope + Send + Sync> FromRequest<'r> for ScopedCaller<S> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let key = match req.headers().get_one("x-api-key") {
+            Some(key) => key,
+            None => {
+                let user = match req.guard::<&AuthContext>().await {
+                    Outcome::Success(context) => &context.user,
+                    Outcome::Error(e) => return Outcome::Error(e),
+                    Outcome::Forward(f) => return Outcome::Forward(f),
+                };
+                if let Err(status) = user_has_scope::<S>(user) {
+                    return Outcome::Error((status, ()));
+                }
+                return Outcome::Success(ScopedCaller {
+                    caller: Caller::User { email: user.email.clone() },
+                    _scope: PhantomData,
+                });
+            }
+        };
+
+        let db = match req.rocket().state::<Collection<ApiKey>>() {
+            Some(db) => db,
+            None => return Outcome::Error((Status::InternalServerError, ())),
+        };
+
+        let now = BsonDateTime::now();
+        let filter = doc! {
+            "key_hash": hash_token(key),
+            "revoked_at": Bson::Null,
+            "$or": [{"expires_at": Bson::Null}, {"expires_at": {"$gt": now}}],
+        };
+        // finding and stamping last use in one round trip
+        let api_key = match db.find_one_and_update(filter, doc! {"$set": {"last_used_at": now}}).await {
+            Ok(Some(api_key)) => api_key,
+            Ok(None) => return Outcome::Error((Status::Unauthorized, ())),
+            Err(_) => return Outcome::Error((Status::InternalServerError, ())),
+        };
+
+        if !api_key.scopes.contains(&S::SCOPE) {
+            return Outcome::Error((Status::Forbidden, ()));
+        }
+        Outcome::Success(ScopedCaller {
+            caller: Caller::ApiKey { id: api_key.id.unwrap_or_default(), name: api_key.name },
+            _scope: PhantomData,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedApiKey {
+    pub id: String,
+    pub key: String, // only returned here, it can't be recovered later
+}
+
+#[derive(Serialize)]
+pub struct ApiKeyView {
+    pub id: String,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<ApiScope>,
+    pub created_by: String,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub last_used_at: Option<String>,
+    pub revoked: bool,
+}
+
+fn rfc3339(date: BsonDateTime) -> String {
+    date.try_to_rfc3339_string().unwrap_or_default()
+}
+
+#[post("/api-keys", format = "json", data = "<key_req>")]
+pub async fn create_api_key(
+    key_req: Json<CreateApiKeyRequest>,
+    db: &State<Collection<ApiKey>>,
+    admin: Authorized<perm::ApiKeyManage>, // only super admins can call this
+    audit: Audit<'_>,
+) -> Result<Json<CreatedApiKey>, Status> {
+    if key_req.name.trim().is_empty() || key_req.scopes.is_empty() {
+        return Err(Status::BadRequest);
+    }
+
+    let key = format!("{}{}", KEY_PREFIX, random_token());
+    let api_key = ApiKey {
+        id: None,
+        name: key_req.name.trim().to_string(),
+        prefix: key[..KEY_PREFIX.len() + 8].to_string(),
+        key_hash: hash_token(&key),
+        scopes: key_req.scopes.clone(),
+        created_by: admin.email.clone(),
+        created_at: BsonDateTime::now(),
+        expires_at: key_req.expires_at.map(|at| BsonDateTime::from_millis(at.timestamp_millis())),
+        last_used_at: None,
+        revoked_at: None,
+    };
+
+    let result = db.insert_one(&api_key).await.map_err(|_| Status::InternalServerError)?;
+    let id = result.inserted_id.as_object_id().map(|id| id.to_hex()).unwrap_or_default();
+    audit.record(&admin.email, "api_key.create", vec![id.clone()], None, snapshot(&api_key)).await;
+    Ok(Json(CreatedApiKey { id, key }))
+}
+
+#[get("/api-keys")]
+pub async fn read_api_keys(
+    db: &State<Collection<ApiKey>>,
+    _admin: Authorized<perm::ApiKeyManage>, // only super admins can call this
+) -> Result<Json<Vec<ApiKeyView>>, Status> {
+    let mut cursor = db.find(doc! {}).sort(doc! {"created_at": -1}).await.map_err(|_| Status::InternalServerError)?;
+    let mut keys = Vec::new();
+    while let Some(api_key) = cursor.try_next().await.map_err(|_| Status::InternalServerError)? {
+        keys.push(ApiKeyView {
+            id: api_key.id.map(|id| id.to_hex()).unwrap_or_default(),
+            name: api_key.name,
+            prefix: api_key.prefix,
+            scopes: api_key.scopes,
+            created_by: api_key.created_by,
+            created_at: rfc3339(api_key.created_at),
+            expires_at: api_key.expires_at.map(rfc3339),
+            last_used_at: api_key.last_used_at.map(rfc3339),
+            revoked: api_key.revoked_at.is_some(),
+        });
+    }
+    Ok(Json(keys))
+}
+
+#[delete("/api-keys/<id>")]
+pub async fn revoke_api_key(
+    id: &str,
+    db: &State<Collection<ApiKey>>,
+    admin: Authorized<perm::ApiKeyManage>, // only super admins can call this
+    audit: Audit<'_>,
+) -> Result<Json<String>, Status> {
+    let object_id = ObjectId::parse_str(id).map_err(|_| Status::BadRequest)?;
+    let filter = doc! {"_id": object_id, "revoked_at": Bson::Null};
+    match db.update_one(filter, doc! {"$set": {"revoked_at": BsonDateTime::now()}}).await {
+        Ok(res) if res.matched_count > 0 => {
+            audit.record(&admin.email, "api_key.revoke", vec![id.to_string()], None, None).await;
+            Ok(Json("API key revoked".to_string()))
+        },
+        Ok(_) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::models::user::UserType;
+    use crate::models::Role;
+
+    fn user(roles: &[Role], two_factor_enabled: bool) -> User {
+        User {
+            id: Some(ObjectId::new()),
+            name: "Ada".to_string(),
+            email: "ada@example.org".to_string(),
+            tel: String::new(),
+            password: String::new(),
+            wallet: String::new(),
+            wallet_linked_at: None,
+            roles: roles.to_vec(),
+            user_type: UserType::RANDOM,
+            role: String::new(),
+            stack: vec![],
+            graduate: false,
+            level: 0,
+            department: String::new(),
+            university: String::new(),
+            student: String::new(),
+            attending_events: vec![],
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            email_verified_at: Some(Utc::now()),
+            two_factor_enabled,
+            identities: vec![],
+        }
+    }
+
+    #[test]
+    fn any_user_reads_events() {
+        assert!(user_has_scope::<scope::EventsRead>(&user(&[Role::Member], false)).is_ok());
+    }
+
+    #[test]
+    fn members_cannot_check_in_or_export() {
+        let member = user(&[Role::Member], false);
+        assert_eq!(user_has_scope::<scope::AttendeesCheckIn>(&member), Err(Status::Forbidden));
+        assert_eq!(user_has_scope::<scope::Export>(&member), Err(Status::Forbidden));
+    }
+
+    #[test]
+    fn organizers_check_in_and_export() {
+        let organizer = user(&[Role::Organizer], false);
+        assert!(user_has_scope::<scope::AttendeesCheckIn>(&organizer).is_ok());
+        assert!(user_has_scope::<scope::Export>(&organizer).is_ok());
+    }
+
+    #[test]
+    fn privileged_users_need_two_factor_for_scopes() {
+        assert_eq!(user_has_scope::<scope::Export>(&user(&[Role::CoreTeam], false)), Err(Status::Forbidden));
+        assert!(user_has_scope::<scope::Export>(&user(&[Role::CoreTeam], true)).is_ok());
+    }
+}
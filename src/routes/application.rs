@@ -5,22 +5,40 @@ use rocket::serde::json::Json;
 use mongodb::bson::{doc, oid::ObjectId};
 use mongodb::Collection;
 use serde::{Serialize, Deserialize};
-use crate::models::{ Application, ApplicationStatus};
+use chrono::Utc;
+use crate::models::{ Application, ApplicationStatus, Event};
+use super::auth::{perm, Authorized};
+use super::verification::VerifiedEmail;
+use super::access::{policy, Actor};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApplyRequest {
-    pub user_id: String,
+    #[serde(default)]
+    pub user_id: Option<String>, // someone else to apply for, defaults to the caller
     pub event_id: String,
 }
 
 #[post("/apply", format="json", data="<apply_req>")]
 pub async fn apply_for_event(
     db: &State<Collection<Application>>,
-    apply_req: Json<ApplyRequest>
+    event_db: &State<Collection<Event>>,
+    apply_req: Json<ApplyRequest>,
+    actor: Actor,
+    _verified: VerifiedEmail, // verified email if the policy asks for it
 ) -> Result<Status, Status> {
-    let user_id = ObjectId::parse_str(&apply_req.user_id).map_err(|_| Status::BadRequest)?;
+    // applying for someone else is for reviewers
+    let user_id = actor.resolve_target(apply_req.user_id.as_deref(), policy::APPLY_FOR_EVENT)?;
     let event_id = ObjectId::parse_str(&apply_req.event_id).map_err(|_| Status::BadRequest)?;
 
+    let event = event_db
+        .find_one(doc! {"_id": &event_id})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+    if !event.registration_open(Utc::now()) {
+        return Err(Status::Forbidden); // registration closed or not open yet
+    }
+
     let existing = db
     .find_one(doc! 
         {
@@ -49,7 +67,10 @@ pub async fn apply_for_event(
 }
 
 #[get("/applicants")]
-pub async  fn read_applicants(db: &State<Collection<Application>>) -> Json<Vec<Application>> {
+pub async  fn read_applicants(
+    db: &State<Collection<Application>>,
+    _reviewer: Authorized<perm::ApplicationReview>, // only reviewers can see applicants
+) -> Json<Vec<Application>> {
     let mut cursor: Cursor<Application> = db.find(doc! {})
     .await
     .expect("failed to find user");
@@ -0,0 +1,152 @@
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, to_document, DateTime as BsonDateTime, Document};
+use mongodb::Collection;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::Serialize;
+
+use crate::models::AuditEntry;
+use super::auth::{perm, Authorized};
+
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const MAX_PAGE_SIZE: u64 = 200;
+
+// fields that never belong in the audit trail
+const REDACTED_FIELDS: [&str; 3] = ["password", "key_hash", "token_hash"];
+
+// request guard handing routes a way to record what they did, with the request metadata filled in
+pub struct Audit<'r> {
+    db: &'r Collection<AuditEntry>,
+    method: String,
+    path: String,
+    ip: Option<String>,
+    user_agent: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Audit<'r> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let db = match req.rocket().state::<Collection<AuditEntry>>() {
+            Some(db) => db,
+            None => return Outcome::Error((Status::InternalServerError, ())),
+        };
+        Outcome::Success(Audit {
+            db,
+            method: req.method().as_str().to_string(),
+            path: req.uri().path().to_string(),
+            ip: req.client_ip().map(|ip| ip.to_string()),
+            user_agent: req.headers().get_one("User-Agent").map(|ua| ua.to_string()),
+        })
+    }
+}
+
+impl Audit<'_> {
+    // a failed write is logged rather than failing an action that already happened
+    pub async fn record(
+        &self,
+        actor: &str,
+        action: &str,
+        target_ids: Vec<String>,
+        before: Option<Document>,
+        after: Option<Document>,
+    ) {
+        let entry = AuditEntry {
+            id: None,
+            actor: actor.to_string(),
+            action: action.to_string(),
+            target_ids,
+            before,
+            after,
+            method: self.method.clone(),
+            path: self.path.clone(),
+            ip: self.ip.clone(),
+            user_agent: self.user_agent.clone(),
+            at: BsonDateTime::now(),
+        };
+        if let Err(e) = self.db.insert_one(entry).await {
+            eprintln!("Failed to write audit entry for {}: {:?}", action, e);
+        }
+    }
+}
+
+// document form of a record for before/after, minus secrets
+pub fn snapshot<T: Serialize>(value: &T) -> Option<Document> {
+    let mut document = to_document(value).ok()?;
+    for field in REDACTED_FIELDS {
+        document.remove(field);
+    }
+    Some(document)
+}
+
+#[derive(Serialize)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub page: u64,
+    pub per_page: u64,
+    pub total: u64,
+}
+
+fn parse_time(value: &str) -> Result<BsonDateTime, Status> {
+    let parsed = DateTime::parse_from_rfc3339(value).map_err(|_| Status::BadRequest)?;
+    Ok(BsonDateTime::from_millis(parsed.with_timezone(&Utc).timestamp_millis()))
+}
+
+// newest first, filter by actor, action, target and time range (RFC 3339)
+#[get("/audit?<actor>&<action>&<target>&<since>&<until>&<page>&<per_page>")]
+pub async fn read_audit_log(
+    actor: Option<&str>,
+    action: Option<&str>,
+    target: Option<&str>,
+    since: Option<&str>,
+    until: Option<&str>,
+    page: Option<u64>,
+    per_page: Option<u64>,
+    db: &State<Collection<AuditEntry>>,
+    _admin: Authorized<perm::AuditRead>, // only super admins can call this
+) -> Result<Json<AuditPage>, Status> {
+    let mut filter = doc! {};
+    if let Some(actor) = actor {
+        filter.insert("actor", actor);
+    }
+    if let Some(action) = action {
+        filter.insert("action", action);
+    }
+    if let Some(target) = target {
+        filter.insert("target_ids", target);
+    }
+    let mut range = doc! {};
+    if let Some(since) = since {
+        range.insert("$gte", parse_time(since)?);
+    }
+    if let Some(until) = until {
+        range.insert("$lt", parse_time(until)?);
+    }
+    if !range.is_empty() {
+        filter.insert("at", range);
+    }
+
+    let page = page.unwrap_or(1).max(1);
+    let per_page = per_page.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    let total = db
+        .count_documents(filter.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let entries: Vec<AuditEntry> = db
+        .find(filter)
+        .sort(doc! {"at": -1})
+        .skip((page - 1) * per_page)
+        .limit(per_page as i64)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .try_collect()
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(AuditPage { entries, page, per_page, total }))
+}
@@ -1,6 +1,7 @@
 
+use std::marker::PhantomData;
 use std::time::SystemTime;
-use std::{clone, env};
+use std::clone;
 
 
 use futures::TryStreamExt;
@@ -10,28 +11,42 @@ use mongodb::bson::{doc, Bson, DateTime, Uuid, DateTime as BsonDateTime};
 use mongodb::options::FindOptions;
 use mongodb::Cursor;
 use mongodb::Collection;
-use rocket::http::{HeaderMap, Status};
-use rocket::response::status;
+use rocket::http::{CookieJar, Status};
+use rocket::response::{status, Redirect};
 use rocket::serde::json::Json;
 use rocket::request::{self, Outcome, Request, FromRequest};
 use chrono::{Utc, Duration};
 use rand::Rng;
 use hmac::{Hmac};
-use dotenv::dotenv;
-use sha2::Sha256;
+use sha2::{Digest, Sha256};
 use serde_json::json;
 use serde_json::value::Value;
 use serde::{Serialize, Deserialize};
-use jsonwebtoken::{encode, decode, Header, Algorithm, Validation, EncodingKey, DecodingKey};
 use rocket::{post, State};
-use std::collections::HashMap;
-use std::sync::Mutex;
-use lazy_static::lazy_static;
 use crate::db;
-use crate::models::{BlackListedToken, User};
+use crate::models::{BlackListedToken, LoginThrottle, MfaChallenge, OidcIdentity, OidcLogin, Permission, RefreshToken, Role, Session, User, WalletChallenge};
+use crate::models::user::UserType;
+use crate::keys::KeyRing;
+use crate::revocation::RevocationCache;
+use crate::oidc;
+use crate::sui::{self, SignatureError};
+use lazy_static::lazy_static;
+use super::access::Actor;
+use super::lockout;
+use super::two_factor;
+use super::session::{revoke_sessions, ClientInfo};
+use super::cookie_session::{self, RefreshCookie, Transport};
+
 
+pub(crate) const ACCESS_TOKEN_TTL: usize = 2 * 60 * 60; // 2 hours
+pub(crate) const REFRESH_TOKEN_TTL: i64 = 7 * 24 * 60 * 60; // 7 days
+const WALLET_CHALLENGE_TTL: i64 = 5 * 60; // 5 minutes
+const OIDC_LOGIN_TTL: i64 = 10 * 60; // 10 minutes
 
-const RANK: Option<bool> = Some(true);
+lazy_static! {
+    // compared against when the email is unknown
+    static ref DUMMY_HASH: String = bcrypt::hash("not a password", bcrypt::DEFAULT_COST).expect("failed to hash dummy password");
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
@@ -41,14 +56,31 @@ struct Claims {
     iat: usize,
     iss: String,
     nbf: usize,
+    jti: String, // unique per token, used as the blacklist key
+    sid: String, // refresh token family the token was issued with
 }
 
 type HmacSha256 = Hmac<Sha256>;
 
 #[derive(Serialize)]
-struct TokenResponse {
-    access_token: String,
-    refresh_token: String
+pub struct TokenResponse {
+    pub(crate) access_token: String,
+    pub(crate) refresh_token: String
+}
+
+// login answers with tokens, or with an mfa_token when the account has 2FA;
+// cookie sessions only get the CSRF token, the tokens themselves go into cookies
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Tokens(TokenResponse),
+    Session {
+        csrf_token: String
+    },
+    TwoFactorRequired {
+        two_factor_required: bool,
+        mfa_token: String
+    }
 }
 
 
@@ -58,41 +90,131 @@ struct  LoginRequest {
     password: String
 }
 
-lazy_static! {
-    static ref REFRESH_TOKENS: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+#[derive(Deserialize)]
+struct WalletChallengeRequest {
+    wallet: String
+}
+
+#[derive(Serialize)]
+struct WalletChallengeResponse {
+    nonce: String,
+    message: String
+}
+
+#[derive(Deserialize)]
+pub struct WalletLoginRequest {
+    wallet: String,
+    nonce: String,
+    signature: String // base64 serialized Sui signature over `message`
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct  RefreshRequest {
-    pub refresh_token: String,
+    #[serde(default)]
+    pub refresh_token: Option<String>, // cookie sessions send it as a cookie instead
 }
 
 // add token to blacklist
-lazy_static! {
-    static ref BLACKLIST: Mutex<Vec<String>> = Mutex::new(Vec::new());
+// the entry expires together with the token, after that the signature check rejects it anyway
+async fn blacklist_token(
+    claims: &Claims, 
+    db: &State<Collection<BlackListedToken>>,
+    revocations: &RevocationCache) -> Result<(), mongodb::error::Error> {
+    blacklist_jti(&claims.jti, BsonDateTime::from_millis(claims.exp as i64 * 1000), db, revocations).await
 }
 
-pub async fn blacklist_token(
-    token: &str, 
-    db: &State<Collection<BlackListedToken>>) -> Result<(), mongodb::error::Error> {
+// upsert so revoking an already blacklisted token is not an error
+// the local cache is updated right away, other instances pick the entry up from the collection
+pub(crate) async fn blacklist_jti(
+    jti: &str,
+    expires_at: BsonDateTime,
+    db: &Collection<BlackListedToken>,
+    revocations: &RevocationCache) -> Result<(), mongodb::error::Error> {
+    let blacklist_token = BlackListedToken {
+        jti: Some(jti.to_string()),
+        sid: None,
+        expires_at,
+        blacklist_at: BsonDateTime::from(SystemTime::from(Utc::now()))
+    };
+    let entry = mongodb::bson::to_document(&blacklist_token)?;
+    db.update_one(doc! {"jti": jti}, doc! {"$setOnInsert": entry})
+        .upsert(true)
+        .await?;
+    revocations.insert(jti, expires_at);
+    Ok(())
+}
 
-    let mut blacklist_token = BlackListedToken {
-        token: token.to_string(),
+// revokes every access token of a session at once, including ones issued before the last refresh.
+// `expires_at` is the exp of the session's newest access token, older ones expire before it.
+pub(crate) async fn blacklist_session(
+    sid: &str,
+    expires_at: BsonDateTime,
+    db: &Collection<BlackListedToken>,
+    revocations: &RevocationCache) -> Result<(), mongodb::error::Error> {
+    let blacklist_session = BlackListedToken {
+        jti: None,
+        sid: Some(sid.to_string()),
+        expires_at,
         blacklist_at: BsonDateTime::from(SystemTime::from(Utc::now()))
     };
-    db.insert_one(blacklist_token).await?;
+    let entry = mongodb::bson::to_document(&blacklist_session)?;
+    db.update_one(doc! {"sid": sid}, doc! {"$setOnInsert": entry})
+        .upsert(true)
+        .await?;
+    revocations.insert_session(sid, expires_at);
     Ok(())
 }
 
-pub async fn is_blacklisted(token: &str, db: &State<Collection<BlackListedToken>>) -> Result<bool, mongodb::error::Error>{
-    let filter = doc! {"token": token};
-    let result  = db.find_one(filter).await?;
-    Ok(result.is_some())
+// the caller of a request: the token is checked and the user loaded once, then cached for the
+// rest of the request so stacked guards don't repeat the work. Take `caller: &AuthContext` in a handler.
+pub struct AuthContext {
+    pub user: User,
+    pub session_id: String, // refresh token family the token was issued with
+    pub token: String,
+}
+
+async fn resolve_auth_context(req: &Request<'_>) -> Result<AuthContext, Status> {
+    // bearer header or session cookie
+    let token = cookie_session::request_token(req)?;
+
+    let (revocations, user_db, keys) = match (
+        req.rocket().state::<RevocationCache>(),
+        req.rocket().state::<Collection<User>>(),
+        req.rocket().state::<KeyRing>(),
+    ) {
+        (Some(revocations), Some(user_db), Some(keys)) => (revocations, user_db, keys),
+        _ => return Err(Status::InternalServerError),
+    };
+
+    let claims = decode_jwt(&token, keys).map_err(|_| Status::Unauthorized)?;
+    if revocations.is_revoked(&claims.jti) || revocations.is_session_revoked(&claims.sid) {
+        return Err(Status::Unauthorized);
+    }
+
+    match user_db.find_one(doc! {"email": &claims.sub}).await {
+        Ok(Some(user)) if user.id.is_some() => Ok(AuthContext { user, session_id: claims.sid, token }),
+        Ok(_) => Err(Status::Unauthorized), // token for a deleted user
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'r AuthContext {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let context = req.local_cache_async(async { resolve_auth_context(req).await }).await;
+        match context {
+            Ok(context) => Outcome::Success(context),
+            Err(status) => Outcome::Error((*status, ())),
+        }
+    }
 }
 
-// request guard for jwt
+// request guard for jwt, the caller's email and session
 pub struct AuthenticatedUser {
-    pub email: String
+    pub email: String,
+    pub session_id: String // refresh token family of the session the token belongs to
 }
 
 #[rocket::async_trait]
@@ -100,36 +222,14 @@ impl <'r> FromRequest<'r> for AuthenticatedUser {
     type Error = ();
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let headers: &HeaderMap<'_> = req.headers();
-        let auth_header = headers.get_one("Authorization");
-    
-        if let Some(token) = auth_header {
-            if let Some(jwt) = token.strip_prefix("Bearer ") {
-                // Get the MongoDB collection from Rocket's managed state
-                let db = match req.rocket().state::<Collection<BlackListedToken>>() {
-                    Some(db) => db,
-                    None => return Outcome::Error((Status::InternalServerError, ())),
-                };
-    
-                // Check if the token is blacklisted
-                match is_blacklisted(jwt, db.into()).await {
-                    Ok(true) => return Outcome::Error((Status::Unauthorized, ())), // Token is blacklisted
-                    Ok(false) => (), // Token is valid
-                    Err(_) => return Outcome::Error((Status::InternalServerError, ())), // Database error
-                }
-    
-                // Validate the token
-                match validate_jwt(jwt) {
-                    Ok(email) => return Outcome::Success(AuthenticatedUser { email }),
-                    Err(_) => return Outcome::Error((Status::Unauthorized, ())),
-                }
-            }
-        }
-        Outcome::Error((Status::Unauthorized, ()))
+        req.guard::<&AuthContext>().await.map(|context| AuthenticatedUser {
+            email: context.user.email.clone(),
+            session_id: context.session_id.clone(),
+        })
     }
-    
 }
 
+// the raw access token of the caller
 #[derive(Debug)]
 pub struct AuthToken(pub String);
 
@@ -138,75 +238,70 @@ impl<'r> FromRequest<'r> for AuthToken {
     type Error = ();
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let headers = req.headers();
-        let auth_header = headers.get_one("Authorization");
-
-        if let Some(token) = auth_header {
-            if let Some(jwt) = token.strip_prefix("Bearer ") {
-                // get the mongoDB collection from rocket's state
-                let db = match req.rocket().state::<Collection<BlackListedToken>>() {
-                    Some(db) => db,
-                    None => return Outcome::Error((Status::InternalServerError, ())),
-                };
-
-                match validate_token(jwt, db.into()).await {
-                    Ok(true) => Outcome::Success(AuthToken(jwt.to_string())), // token is valid
-                    Ok(false) => Outcome::Error((Status::Unauthorized, ())),
-                    Err(status) => Outcome::Error((status, ())), // unauthorized token
-                }
-
-            }else {
-                Outcome::Error((Status::Unauthorized, ()))
-            }
-        }else {
-            Outcome::Error((Status::Unauthorized, ()))
-        }
+        req.guard::<&AuthContext>().await.map(|context| AuthToken(context.token.clone()))
     }
 }
 
-#[derive(Debug)]
-pub struct AdminUser {
+// permission a route requires, declared through the marker types in `perm`
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+macro_rules! permission_markers {
+    ($($name:ident),*) => {
+        $(
+            pub struct $name;
+            impl RequiredPermission for $name {
+                const PERMISSION: Permission = Permission::$name;
+            }
+        )*
+    };
+}
+
+pub mod perm {
+    use super::RequiredPermission;
+    use crate::models::Permission;
+
+    permission_markers!(EventCreate, EventUpdate, EventDelete, ApplicationReview, UserManage, RoleManage, ApiKeyManage, AuditRead, EventFeature);
+}
+
+// request guard for routes that need a permission, e.g. `_auth: Authorized<perm::EventCreate>`
+pub struct Authorized<P: RequiredPermission> {
     pub email: String,
+    _permission: PhantomData<P>,
 }
 
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for AdminUser {
+impl<'r, P: RequiredPermission + Send + Sync> FromRequest<'r> for Authorized<P> {
     type Error = ();
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let user_db = match req.rocket().state::<Collection<User>>() {
-            Some(user_db) => user_db,
-            None => return Outcome::Error((Status::InternalServerError, ())),
-        };
-
-        // Extract JWT token from Authorization header
-        let auth_header = req.headers().get_one("Authorization");
-        let token = match auth_header.and_then(|h| h.strip_prefix("Bearer ")) {
-            Some(t) => t,
-            None => return Outcome::Error((Status::Unauthorized, ())),
-        };
-
-        // Decode JWT to extract email
-        let email = match validate_jwt(token) {
-            Ok(email) => email,
-            Err(_) => return Outcome::Error((Status::Unauthorized, ())),
+        let user = match req.guard::<&AuthContext>().await {
+            Outcome::Success(context) => &context.user,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(f) => return Outcome::Forward(f),
         };
 
-        // Fetch user from MongoDB and check ranking
-        let filter = doc! { "email": &email };
-        match user_db.find_one(filter).await {
-            Ok(Some(user)) if user.admin == RANK => {
-                Outcome::Success(AdminUser { email })
-            }
-            _ => Outcome::Error((Status::Forbidden, ())), // User is not an admin or does not exist
+        match check_permission(user, P::PERMISSION) {
+            Ok(()) => Outcome::Success(Authorized { email: user.email.clone(), _permission: PhantomData }),
+            Err(status) => Outcome::Error((status, ())),
         }
     }
 }
 
+pub(crate) fn check_permission(user: &User, permission: Permission) -> Result<(), Status> {
+    if !user.has_permission(permission) {
+        return Err(Status::Forbidden);
+    }
+    // privileged accounts have to turn on 2FA before they can use their permissions
+    if user.requires_two_factor() && !user.two_factor_enabled {
+        return Err(Status::Forbidden);
+    }
+    Ok(())
+}
 
-fn generate_jwt(addr: &str, exp_seconds: usize) -> &'static str {
-    dotenv().ok();
-    let secret = env::var("SECRET_KEY").expect("Secret key not set");
+
+fn generate_jwt(keys: &KeyRing, addr: &str, session_id: &str, exp_seconds: usize) -> (String, Claims) {
     let expiration = (Utc::now() + Duration::seconds(exp_seconds as i64)).timestamp(); // token valid for 2 hours
     let current_time = Utc::now().timestamp() as usize;
     let my_claim = Claims {
@@ -214,138 +309,537 @@ fn generate_jwt(addr: &str, exp_seconds: usize) -> &'static str {
         company: "SOC".to_owned(),
         exp: expiration as usize,
         iat: current_time, // UNIX timestamp
-        iss: "CxL".to_owned(),
-        nbf: current_time
+        iss: keys.issuer.clone(),
+        nbf: current_time,
+        jti: Uuid::new().to_string(),
+        sid: session_id.to_owned(),
     };
-    let encoded = encode(&Header::default(), &my_claim, &EncodingKey::from_secret(secret.as_bytes())).expect("JWT encoding failed");
-    let static_encoded: &'static str = Box::leak(encoded.into_boxed_str());
-    static_encoded
+    let encoded = keys.sign(&my_claim).expect("JWT encoding failed");
+    (encoded, my_claim)
+}
+
+fn decode_jwt(token: &str, keys: &KeyRing) -> Result<Claims, jsonwebtoken::errors::Error> {
+    keys.verify::<Claims>(token)
+}
+
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
 
-fn validate_jwt(token: &str) -> Result<String, jsonwebtoken::errors::Error> {
-    dotenv().ok();
-    let secret = env::var("SECRET_KEY").expect("Jwt must be set");
+// 32 random bytes, hex encoded
+pub(crate) fn random_token() -> String {
+    let bytes: [u8; 32] = rand::rng().random();
+    hex::encode(bytes)
+}
 
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::default()
-    )?;
-    Ok(token_data.claims.sub)
+// issues an access token and a new refresh token. Only the hash of the refresh token is stored.
+// pass the family_id of the rotated token when refreshing, None starts a new family (login)
+// the session for the family is created or bumped along the way
+pub(crate) async fn issue_tokens(
+    email: &str,
+    family_id: Option<String>,
+    client: &ClientInfo,
+    keys: &KeyRing,
+    db: &Collection<RefreshToken>,
+    sessions: &Collection<Session>) -> Result<TokenResponse, mongodb::error::Error> {
+    let family_id = family_id.unwrap_or_else(|| Uuid::new().to_string());
+    let (access_token, access_claims) = generate_jwt(keys, email, &family_id, ACCESS_TOKEN_TTL);
+    let refresh_token = random_token();
+
+    let now = Utc::now();
+    let expires_at = BsonDateTime::from(SystemTime::from(now + Duration::seconds(REFRESH_TOKEN_TTL)));
+    let stored = RefreshToken {
+        id: None,
+        token_hash: hash_token(&refresh_token),
+        email: email.to_string(),
+        family_id: family_id.clone(),
+        expires_at,
+        created_at: BsonDateTime::from(SystemTime::from(now)),
+        used_at: None,
+        revoked: false,
+    };
+    db.insert_one(stored).await?;
+
+    let session_update = doc! {
+        "$setOnInsert": {
+            "email": email,
+            "user_agent": client.user_agent.clone(),
+            "created_at": BsonDateTime::from(SystemTime::from(now)),
+        },
+        "$set": {
+            "ip": client.ip.map(|ip| ip.to_string()),
+            "last_used_at": BsonDateTime::from(SystemTime::from(now)),
+            "expires_at": expires_at,
+            "access_jti": &access_claims.jti,
+            "access_expires_at": BsonDateTime::from_millis(access_claims.exp as i64 * 1000),
+        }
+    };
+    sessions
+        .update_one(doc! {"family_id": &family_id}, session_update)
+        .upsert(true)
+        .await?;
+
+    Ok(TokenResponse {
+        access_token,
+        refresh_token
+    })
+}
+
+// signs the user out everywhere
+pub async fn revoke_user_tokens(email: &str, db: &Collection<RefreshToken>) -> Result<(), mongodb::error::Error> {
+    db.update_many(doc! {"email": email}, doc! {"$set": {"revoked": true}}).await?;
+    Ok(())
+}
+
+pub async fn revoke_token_family(family_id: &str, db: &Collection<RefreshToken>) -> Result<(), mongodb::error::Error> {
+    db.update_many(doc! {"family_id": family_id}, doc! {"$set": {"revoked": true}}).await?;
+    Ok(())
 }
 
 #[post("/auth/login", format="json", data = "<credentials>")]
 pub async fn login(
     credentials: Json<LoginRequest>, 
-    db: &State<Collection<User>>) -> Result<Json<TokenResponse>, Status> {
+    client: ClientInfo,
+    db: &State<Collection<User>>,
+    refresh_db: &State<Collection<RefreshToken>>,
+    sessions: &State<Collection<Session>>,
+    throttle_db: &State<Collection<LoginThrottle>>,
+    mfa_db: &State<Collection<MfaChallenge>>,
+    keys: &State<KeyRing>,
+    transport: Transport,
+    cookies: &CookieJar<'_>) -> Result<Json<LoginResponse>, Status> {
     let email = credentials.email.clone();
     let password = credentials.password.clone();
+
+    let mut throttle_keys = vec![lockout::account_key(&email)];
+    if let Some(ip) = &client.lockout_ip {
+        throttle_keys.push(lockout::ip_key(ip));
+    }
+    match lockout::locked_until(&throttle_keys, throttle_db).await {
+        Ok(Some(_)) => return Err(Status::TooManyRequests),
+        Ok(None) => {},
+        Err(_) => return Err(Status::InternalServerError),
+    }
+
     let filter  = doc! {"email": &email};
     // check if user exist
-    match db.find_one(filter.clone()).await {
-        Ok(Some(user)) => {
-            // verify password
-            if bcrypt::verify(password, &user.password).unwrap_or(false) {
-                let access_token = generate_jwt(&email, 2 * 60 * 60); // expires in 2 hours
-                let refresh_token = generate_jwt(&email, 7 * 24 * 60 * 60); // expire in 7 days
-
-                // store the refresh token (in-memory Hashmap for demo)
-                REFRESH_TOKENS
-                .lock()
-                .unwrap()
-                .insert(refresh_token.to_string(), email.clone());
-                Ok(Json(TokenResponse {
-                    access_token: access_token.to_string(),
-                    refresh_token: refresh_token.to_string()
-                }))
-            } else {
-                Err(Status::Unauthorized)
-            }
-        }
-        Ok(None) => Err(Status::NotFound),
+    let user = match db.find_one(filter.clone()).await {
+        Ok(user) => user,
         Err(e)=> {
             rocket::log::private::warn!("User not found: {}", e);
-            Err(Status::InternalServerError)
+            return Err(Status::InternalServerError)
         },
+    };
+
+    // verify password, unknown emails still pay for a bcrypt check so timing doesn't tell them apart
+    let valid = match &user {
+        Some(user) => bcrypt::verify(password, &user.password).unwrap_or(false),
+        None => {
+            let _ = bcrypt::verify(password, &DUMMY_HASH);
+            false
+        }
+    };
+
+    if !valid {
+        if lockout::record_login_failure(&email, client.lockout_ip, throttle_db).await.is_err() {
+            return Err(Status::InternalServerError);
+        }
+        return Err(Status::Unauthorized);
+    }
+
+    if lockout::clear(&lockout::account_key(&email), throttle_db).await.is_err() {
+        return Err(Status::InternalServerError);
+    }
+    if user.map(|user| user.two_factor_enabled).unwrap_or(false) {
+        return match two_factor::begin_challenge(&email, mfa_db).await {
+            Ok(mfa_token) => Ok(Json(LoginResponse::TwoFactorRequired { two_factor_required: true, mfa_token })),
+            Err(_) => Err(Status::InternalServerError),
+        };
+    }
+    match issue_tokens(&email, None, &client, keys, refresh_db, sessions).await {
+        Ok(tokens) => Ok(Json(cookie_session::respond(tokens, transport, cookies))),
+        Err(e) => {
+            rocket::log::private::warn!("Failed to store refresh token: {}", e);
+            Err(Status::InternalServerError)
+        }
     }
-    
 }
 
-// refresh token endpoint (post/refresh)
-#[post("/refresh", format = "json", data = "<refresh_request>")]
-pub fn refresh(refresh_request: Json<RefreshRequest>) -> Result<Json<TokenResponse>, Status> {
-    dotenv().ok();
-    let refresh_token = refresh_request.refresh_token.clone();
-    let secret_key = env::var("SECRET_KEY").expect("secret must be set");
-    let decoding_key: DecodingKey = DecodingKey::from_secret(secret_key.as_bytes());
+// Sign-In with Sui, step 1: hand out a single use message for the wallet to sign
+#[post("/auth/wallet/challenge", format = "json", data = "<challenge_req>")]
+pub async fn wallet_challenge(
+    challenge_req: Json<WalletChallengeRequest>,
+    db: &State<Collection<WalletChallenge>>) -> Result<Json<WalletChallengeResponse>, Status> {
+    let wallet = sui::normalize_address(&challenge_req.wallet).ok_or(Status::BadRequest)?;
+    let nonce = random_token();
+    let now = Utc::now();
+    let expires_at = now + Duration::seconds(WALLET_CHALLENGE_TTL);
+
+    let message = format!(
+        "SOC wants you to sign in with your Sui account:\n{}\n\nNonce: {}\nIssued At: {}\nExpiration Time: {}",
+        wallet,
+        nonce,
+        now.to_rfc3339(),
+        expires_at.to_rfc3339()
+    );
+
+    let challenge = WalletChallenge {
+        id: None,
+        wallet,
+        nonce: nonce.clone(),
+        message: message.clone(),
+        expires_at: BsonDateTime::from(SystemTime::from(expires_at)),
+    };
+    db.insert_one(challenge).await.map_err(|_| Status::InternalServerError)?;
 
-    match decode::<Claims>(&refresh_token, &decoding_key, &Validation::default()) {
-    Ok(token_data) => {
-        let email = token_data.claims.sub;
+    Ok(Json(WalletChallengeResponse { nonce, message }))
+}
 
-        // Verify that the refresh token is still valid
-        if let Some(_) = REFRESH_TOKENS.lock().unwrap().get(&refresh_token) {
-            let new_access_token = generate_jwt(&email, 2 * 60 * 60); // grant new access for 2 hrs
+// Sign-In with Sui, step 2: verify the signed challenge and issue the same tokens as login
+#[post("/auth/wallet/login", format = "json", data = "<login_req>")]
+pub async fn wallet_login(
+    login_req: Json<WalletLoginRequest>,
+    client: ClientInfo,
+    challenge_db: &State<Collection<WalletChallenge>>,
+    user_db: &State<Collection<User>>,
+    refresh_db: &State<Collection<RefreshToken>>,
+    sessions: &State<Collection<Session>>,
+    mfa_db: &State<Collection<MfaChallenge>>,
+    keys: &State<KeyRing>,
+    transport: Transport,
+    cookies: &CookieJar<'_>) -> Result<Json<LoginResponse>, Status> {
+    let wallet = verify_wallet_challenge(&login_req, challenge_db).await?;
+
+    // wallets are stored normalized and only after a signed challenge, see link_wallet
+    let user = user_db
+        .find_one(doc! {"wallet": &wallet})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Unauthorized)?;
+
+    if user.two_factor_enabled {
+        return match two_factor::begin_challenge(&user.email, mfa_db).await {
+            Ok(mfa_token) => Ok(Json(LoginResponse::TwoFactorRequired { two_factor_required: true, mfa_token })),
+            Err(_) => Err(Status::InternalServerError),
+        };
+    }
+    match issue_tokens(&user.email, None, &client, keys, refresh_db, sessions).await {
+        Ok(tokens) => Ok(Json(cookie_session::respond(tokens, transport, cookies))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
 
-            Ok(Json(TokenResponse {
-                access_token: new_access_token.to_string(),
-                refresh_token
-            }))
-        }else {
-            Err(Status::Unauthorized)
-        }
+// consumes the challenge and returns the normalized address once the signature checks out
+async fn verify_wallet_challenge(
+    login_req: &WalletLoginRequest,
+    challenge_db: &Collection<WalletChallenge>) -> Result<String, Status> {
+    let wallet = sui::normalize_address(&login_req.wallet).ok_or(Status::BadRequest)?;
+
+    // deleting the challenge makes it single use even if the signature turns out to be wrong
+    let challenge = challenge_db
+        .find_one_and_delete(doc! {"wallet": &wallet, "nonce": &login_req.nonce})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Unauthorized)?;
+
+    if challenge.expires_at < BsonDateTime::now() {
+        return Err(Status::Unauthorized);
     }
-    Err(_) => Err(Status::Unauthorized),
+
+    let signer = match sui::verify_personal_message(challenge.message.as_bytes(), &login_req.signature) {
+        Ok(address) => address,
+        Err(SignatureError::Malformed) => return Err(Status::BadRequest),
+        Err(SignatureError::UnsupportedScheme) => return Err(Status::NotImplemented),
+        Err(SignatureError::Invalid) => return Err(Status::Unauthorized),
+    };
+    if signer != wallet {
+        return Err(Status::Unauthorized);
     }
-    
+    Ok(wallet)
 }
 
-// Logout endpoint (POST /logout)
-#[post("/auth/logout", format = "json", data = "<token>")]
-pub async fn loqout(token: String, db: &State<Collection<BlackListedToken>>) -> Result<Json<String>, Status> {
-    match blacklist_token(&token, db).await {
-        Ok(_) => Ok(Json("Token blacklisted successfully!".to_string())),
-        Err(_) => Err(Status::InternalServerError)
+// binds a wallet to the signed in account, proven with a signed challenge like wallet_login
+#[post("/auth/wallet/link", format = "json", data = "<link_req>")]
+pub async fn link_wallet(
+    link_req: Json<WalletLoginRequest>,
+    actor: Actor,
+    challenge_db: &State<Collection<WalletChallenge>>,
+    user_db: &State<Collection<User>>) -> Result<Json<String>, Status> {
+    let wallet = verify_wallet_challenge(&link_req, challenge_db).await?;
+
+    // the unique index settles two accounts linking the same wallet at once
+    let now = Utc::now().timestamp();
+    let update = doc! {"$set": {"wallet": &wallet, "wallet_linked_at": now, "updated_at": now}};
+    match user_db
+        .update_one(doc! {"_id": actor.id()}, update)
+        .await
+    {
+        Ok(_) => Ok(Json("Wallet linked".to_string())),
+        Err(e) if is_duplicate_key(&e) => Err(Status::Conflict),
+        Err(_) => Err(Status::InternalServerError),
     }
 }
 
-// before processing requests check if token is blacklisted?
-pub  async fn validate_token(token: &str, db: &State<Collection<BlackListedToken>>) -> Result<bool, Status> {
-    match is_blacklisted(token, db).await {
-        Ok(true) => Err(Status::Unauthorized), // token is blacklisted
-        Ok(false) => Ok(true),
-        Err(_) => Err(Status::InternalServerError)
+fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    use mongodb::error::{ErrorKind, WriteFailure};
+    match &*error.kind {
+        ErrorKind::Write(WriteFailure::WriteError(e)) => e.code == 11000,
+        ErrorKind::Command(e) => e.code == 11000,
+        _ => false,
     }
 }
 
+// OpenID Connect, step 1: send the browser to the provider with a fresh state, nonce and PKCE challenge
+// `?mode=cookie` asks for a cookie session, the callback is a browser redirect that can't carry headers
+#[get("/auth/oidc/<provider>?<mode>")]
+pub async fn oidc_start(
+    provider: &str,
+    mode: Option<&str>,
+    providers: &State<oidc::Providers>,
+    db: &State<Collection<OidcLogin>>,
+    cookies: &CookieJar<'_>) -> Result<Redirect, Status> {
+    let provider = providers.get(provider).map_err(oidc_status)?;
+    let state = random_token();
+    let login = OidcLogin {
+        id: None,
+        provider: provider.name.clone(),
+        state_hash: hash_token(&state),
+        code_verifier: random_token(),
+        nonce: random_token(),
+        cookie_session: mode == Some("cookie"),
+        expires_at: BsonDateTime::from(SystemTime::from(Utc::now() + Duration::seconds(OIDC_LOGIN_TTL))),
+    };
+    let url = providers
+        .authorization_url(provider, &state, &login.nonce, &login.code_verifier)
+        .await
+        .map_err(oidc_status)?;
+    db.insert_one(login).await.map_err(|_| Status::InternalServerError)?;
+    cookie_session::set_oidc_state(&state, OIDC_LOGIN_TTL, cookies);
+    Ok(Redirect::to(url))
+}
 
-// struct ApiKey<'r>(&'r str);
+// OpenID Connect, step 2: the provider redirects back here with the authorization code
+#[get("/auth/oidc/<provider>/callback?<code>&<state>")]
+pub async fn oidc_callback(
+    provider: &str,
+    code: &str,
+    state: &str,
+    client: ClientInfo,
+    providers: &State<oidc::Providers>,
+    login_db: &State<Collection<OidcLogin>>,
+    user_db: &State<Collection<User>>,
+    refresh_db: &State<Collection<RefreshToken>>,
+    sessions: &State<Collection<Session>>,
+    mfa_db: &State<Collection<MfaChallenge>>,
+    keys: &State<KeyRing>,
+    cookies: &CookieJar<'_>) -> Result<Json<LoginResponse>, Status> {
+    let provider = providers.get(provider).map_err(oidc_status)?;
+
+    // the callback has to come back to the browser that started the login (login CSRF)
+    let started_with = cookie_session::take_oidc_state(cookies).ok_or(Status::Unauthorized)?;
+    if started_with != state {
+        return Err(Status::Unauthorized);
+    }
+
+    // single use, like the wallet challenge
+    let login = login_db
+        .find_one_and_delete(doc! {"state_hash": hash_token(state), "provider": &provider.name})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Unauthorized)?;
+    if login.expires_at < BsonDateTime::now() {
+        return Err(Status::Unauthorized);
+    }
+
+    let identity = providers
+        .complete(provider, code, &login.code_verifier, &login.nonce)
+        .await
+        .map_err(oidc_status)?;
+    let user = find_or_create_oidc_user(&provider.name, identity, user_db).await?;
+
+    if user.two_factor_enabled {
+        return match two_factor::begin_challenge(&user.email, mfa_db).await {
+            Ok(mfa_token) => Ok(Json(LoginResponse::TwoFactorRequired { two_factor_required: true, mfa_token })),
+            Err(_) => Err(Status::InternalServerError),
+        };
+    }
+    let transport = if login.cookie_session { Transport::Cookie } else { Transport::Bearer };
+    match issue_tokens(&user.email, None, &client, keys, refresh_db, sessions).await {
+        Ok(tokens) => Ok(Json(cookie_session::respond(tokens, transport, cookies))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+fn oidc_status(error: oidc::OidcError) -> Status {
+    match error {
+        oidc::OidcError::UnknownProvider => Status::NotFound,
+        oidc::OidcError::Upstream(e) => {
+            rocket::log::private::warn!("OIDC provider error: {}", e);
+            Status::BadGateway
+        }
+        oidc::OidcError::InvalidToken => Status::Unauthorized,
+        oidc::OidcError::EmailNotVerified | oidc::OidcError::DomainNotAllowed => Status::Forbidden,
+    }
+}
+
+// linked accounts are found by provider subject; otherwise the verified email links an existing
+// user, and a first login creates a member account
+async fn find_or_create_oidc_user(
+    provider: &str,
+    identity: oidc::VerifiedIdentity,
+    db: &Collection<User>) -> Result<User, Status> {
+    let linked = OidcIdentity { provider: provider.to_string(), subject: identity.subject };
+    let filter = doc! {"identities": {"$elemMatch": {"provider": &linked.provider, "subject": &linked.subject}}};
+    if let Some(user) = db.find_one(filter).await.map_err(|_| Status::InternalServerError)? {
+        return Ok(user);
+    }
+
+    let email_filter = doc! {"email": {"$regex": format!("^{}$", regex_escape(&identity.email)), "$options": "i"}};
+    if let Some(user) = db.find_one(email_filter).await.map_err(|_| Status::InternalServerError)? {
+        // anyone can sign up with someone else's address, and whoever did still knows the password.
+        // Only accounts that proved they own the address get linked, and only when the provider
+        // vouches for the address too; trust_email is not enough to take over an account.
+        if user.email_verified_at.is_none() || !identity.email_verified {
+            return Err(Status::Forbidden);
+        }
+        let update = doc! {"$addToSet": {"identities": {"provider": &linked.provider, "subject": &linked.subject}}};
+        db.update_one(doc! {"_id": user.id}, update)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+        return Ok(user);
+    }
 
-// #[derive(Debug)]
-// enum ApiKeyError {
-//     Missing,
-//     Invalid,
-// }
+    let now = Utc::now();
+    // nobody knows this password, the account signs in through the provider or a password reset
+    let password = bcrypt::hash(random_token(), bcrypt::DEFAULT_COST).map_err(|_| Status::InternalServerError)?;
+    let user = User {
+        id: None,
+        name: identity.name.unwrap_or_else(|| identity.email.clone()),
+        email: identity.email,
+        tel: String::new(),
+        password,
+        wallet: String::new(),
+        wallet_linked_at: None,
+        roles: vec![Role::Member],
+        user_type: UserType::RANDOM,
+        role: String::new(),
+        stack: vec![],
+        graduate: false,
+        level: 0,
+        department: String::new(),
+        university: String::new(),
+        student: String::new(),
+        attending_events: vec![],
+        created_at: now,
+        updated_at: now,
+        email_verified_at: identity.email_verified.then_some(now), // trusted but unverified addresses confirm by mail
+        two_factor_enabled: false,
+        identities: vec![linked],
+    };
+    let result = db.insert_one(&user).await.map_err(|_| Status::InternalServerError)?;
+    Ok(User { id: result.inserted_id.as_object_id(), ..user })
+}
+
+pub(crate) fn regex_escape(value: &str) -> String {
+    value
+        .chars()
+        .flat_map(|c| {
+            let escape = "\\.+*?()|[]{}^$".contains(c);
+            escape.then_some('\\').into_iter().chain(std::iter::once(c))
+        })
+        .collect()
+}
+
+// refresh token endpoint (post/auth/refresh)
+// every call rotates the refresh token; presenting one that was already rotated
+// means it leaked, so the whole family is revoked and the user has to log in again
+// cookie sessions post without a body, the refresh token comes from the cookie
+#[post("/auth/refresh", data = "<refresh_request>")]
+pub async fn refresh(
+    refresh_request: Option<Json<RefreshRequest>>,
+    refresh_cookie: RefreshCookie,
+    client: ClientInfo,
+    db: &State<Collection<RefreshToken>>,
+    sessions: &State<Collection<Session>>,
+    blacklist_db: &State<Collection<BlackListedToken>>,
+    revocations: &State<RevocationCache>,
+    keys: &State<KeyRing>,
+    cookies: &CookieJar<'_>) -> Result<Json<LoginResponse>, Status> {
+    let body_token = refresh_request.and_then(|req| req.into_inner().refresh_token);
+    let (refresh_token, transport) = match (body_token, refresh_cookie.0) {
+        (Some(token), _) => (token, Transport::Bearer),
+        (None, Some(token)) => (token, Transport::Cookie),
+        (None, None) => return Err(Status::Unauthorized),
+    };
+    let token_hash = hash_token(&refresh_token);
+    let now = BsonDateTime::now();
+
+    // claim the token in the same operation that reads it so two concurrent refreshes can't both win
+    let claim_filter = doc! {"token_hash": &token_hash, "used_at": Bson::Null, "revoked": false};
+    let claimed = db
+        .find_one_and_update(claim_filter, doc! {"$set": {"used_at": now}})
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let stored = match claimed {
+        Some(stored) => stored,
+        None => {
+            // unknown token, or one that was already used/revoked
+            let existing = db
+                .find_one(doc! {"token_hash": &token_hash})
+                .await
+                .map_err(|_| Status::InternalServerError)?;
+            if let Some(reused) = existing {
+                rocket::log::private::warn!("Refresh token reuse detected for {}, revoking family", reused.email);
+                revoke_sessions(doc! {"family_id": &reused.family_id}, sessions, db, blacklist_db, revocations)
+                    .await
+                    .map_err(|_| Status::InternalServerError)?;
+                revoke_token_family(&reused.family_id, db)
+                    .await
+                    .map_err(|_| Status::InternalServerError)?;
+            }
+            return Err(Status::Unauthorized);
+        }
+    };
 
-// #[rocket::async_trait]
-// impl<'r> FromRequest<'r> for ApiKey<'r> {
-//     type Error = ApiKeyError;
+    if stored.expires_at < now {
+        return Err(Status::Unauthorized);
+    }
 
-//     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-//         /// return true if key is a valid api key
-//         fn is_valid(key: &str) -> bool {
-//             key == "valid_api_key"
-//         }
+    match issue_tokens(&stored.email, Some(stored.family_id), &client, keys, db, sessions).await {
+        Ok(tokens) => Ok(Json(cookie_session::respond(tokens, transport, cookies))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
 
-//         match req.headers().get_one("x-api-key") {
-//             None => Outcome::Error((Status::BadRequest, ApiKeyError::Missing)),
-//             Some(key) if is_valid(key) => Outcome::Success(ApiKey(key)),
-//             Some(_) => Outcome::Error((Status::BadRequest, ApiKeyError::Invalid)),
-//         }
-//     }
-// }
+// Logout endpoint (POST /auth/logout)
+// revokes the bearer token and the refresh token family it was issued with
+#[post("/auth/logout")]
+pub async fn logout(
+    token: AuthToken,
+    db: &State<Collection<BlackListedToken>>,
+    refresh_db: &State<Collection<RefreshToken>>,
+    sessions: &State<Collection<Session>>,
+    revocations: &State<RevocationCache>,
+    keys: &State<KeyRing>,
+    cookies: &CookieJar<'_>) -> Result<Json<String>, Status> {
+    let claims = decode_jwt(&token.0, keys).map_err(|_| Status::Unauthorized)?;
+    cookie_session::clear_session_cookies(cookies);
+
+    if blacklist_token(&claims, db, revocations).await.is_err() {
+        return Err(Status::InternalServerError);
+    }
+    if revoke_sessions(doc! {"family_id": &claims.sid}, sessions, refresh_db, db, revocations).await.is_err() {
+        return Err(Status::InternalServerError);
+    }
+    match revoke_token_family(&claims.sid, refresh_db).await {
+        Ok(_) => Ok(Json("Logged out successfully!".to_string())),
+        Err(_) => Err(Status::InternalServerError)
+    }
+}
 
-// #[get("/sensitive")]
-// pub fn sensitive(key: ApiKey<'_>) -> &'static str {
-//     "Sensitive data"
-// }
+// public keys used to sign access tokens (GET /.well-known/jwks.json)
+#[get("/.well-known/jwks.json")]
+pub fn jwks(keys: &State<KeyRing>) -> Json<Value> {
+    Json(keys.jwks())
+}
@@ -0,0 +1,131 @@
+use chrono::{Duration, Utc};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, DateTime as BsonDateTime};
+use mongodb::Collection;
+use rocket::http::{ContentType, Status};
+use rocket::serde::json::Json;
+use rocket::{delete, get, post, State};
+use serde::Serialize;
+
+use crate::ical;
+use crate::mail;
+use crate::models::event::stored_date;
+use crate::models::{CalendarFeed, Event, User};
+use super::access::Actor;
+use super::auth::{hash_token, random_token};
+
+// past events stay in the feeds for a while, calendar apps drop whatever leaves a feed
+const FEED_HISTORY_DAYS: i64 = 90;
+
+#[derive(Serialize)]
+pub struct CreatedCalendarFeed {
+    pub token: String, // only returned here, rotate to get a new one
+    pub path: String,
+}
+
+// host of the web frontend, makes event UIDs unique across deployments
+fn uid_domain() -> String {
+    let url = mail::app_url();
+    let host = url.split("://").nth(1).unwrap_or(&url);
+    host.split(['/', ':']).next().unwrap_or("localhost").to_string()
+}
+
+fn calendar(name: &str, events: &[Event]) -> (ContentType, String) {
+    (ContentType::Calendar, ical::render(name, &uid_domain(), events))
+}
+
+// single event, for "add to calendar" buttons
+#[get("/event/<event_id>/calendar.ics")]
+pub async fn read_event_calendar(
+    event_id: &str,
+    db: &State<Collection<Event>>,
+) -> Result<(ContentType, String), Status> {
+    let event_oid = ObjectId::parse_str(event_id).map_err(|_| Status::BadRequest)?;
+    match db.find_one(doc! {"_id": event_oid}).await {
+        Ok(Some(event)) => Ok(calendar(&event.name.clone(), &[event])),
+        Ok(None) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+// every public event from the last few months on, public like GET /events/upcoming
+#[get("/events/calendar.ics")]
+pub async fn read_events_calendar(db: &State<Collection<Event>>) -> Result<(ContentType, String), Status> {
+    // dates are stored as RFC 3339 strings, see date_bound in event.rs
+    let since = stored_date(&(Utc::now() - Duration::days(FEED_HISTORY_DAYS)));
+    let events: Vec<Event> = db
+        .find(doc! {"date": {"$gte": since}})
+        .sort(doc! {"date": 1})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .try_collect()
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(calendar("SOC events", &events))
+}
+
+// the events a member is attending; calendar apps can't send headers, the token in the url is the credential
+#[get("/calendar/<token>/feed.ics")]
+pub async fn read_personal_calendar(
+    token: &str,
+    feeds: &State<Collection<CalendarFeed>>,
+    users: &State<Collection<User>>,
+    db: &State<Collection<Event>>,
+) -> Result<(ContentType, String), Status> {
+    let feed = match feeds.find_one(doc! {"token_hash": hash_token(token)}).await {
+        Ok(Some(feed)) => feed,
+        Ok(None) => return Err(Status::NotFound),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    let user = match users.find_one(doc! {"_id": feed.user_id}).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(Status::NotFound),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+
+    let events: Vec<Event> = db
+        .find(doc! {"_id": {"$in": &user.attending_events}})
+        .sort(doc! {"date": 1})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .try_collect()
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(calendar(&format!("SOC events for {}", user.name), &events))
+}
+
+// creates the caller's feed url, or replaces it so the old one stops working
+#[post("/calendar/feed")]
+pub async fn create_calendar_feed(
+    feeds: &State<Collection<CalendarFeed>>,
+    actor: Actor, // the signed in user
+) -> Result<Json<CreatedCalendarFeed>, Status> {
+    let token = random_token();
+    let update_doc = doc! {
+        "$set": {"token_hash": hash_token(&token), "created_at": BsonDateTime::now()},
+    };
+    feeds
+        .update_one(doc! {"user_id": actor.id()}, update_doc)
+        .upsert(true)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(CreatedCalendarFeed {
+        path: format!("/api/v1/calendar/{}/feed.ics", token),
+        token,
+    }))
+}
+
+#[delete("/calendar/feed")]
+pub async fn revoke_calendar_feed(
+    feeds: &State<Collection<CalendarFeed>>,
+    actor: Actor, // the signed in user
+) -> Result<Json<String>, Status> {
+    match feeds.delete_one(doc! {"user_id": actor.id()}).await {
+        Ok(result) if result.deleted_count > 0 => Ok(Json("Calendar feed revoked.".to_string())),
+        Ok(_) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
@@ -0,0 +1,222 @@
+use chrono::{Duration, Utc};
+use mongodb::bson::{doc, oid::ObjectId, DateTime as BsonDateTime};
+use mongodb::Collection;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::{get, post, State};
+use serde::{Deserialize, Serialize};
+
+use crate::keys::KeyRing;
+use crate::models::{CheckIn, Event};
+use super::access::Actor;
+use super::api_key::{scope, Caller, ScopedCaller};
+use super::audit::{snapshot, Audit};
+
+const TICKET_TYPE: &str = "ticket";
+const TICKET_GRACE: i64 = 24 * 60 * 60; // tickets stay valid a day past the end of the event
+
+// signed with the same keys as access tokens; `typ` keeps one from being used as the other
+#[derive(Debug, Serialize, Deserialize)]
+struct TicketClaims {
+    typ: String,
+    sub: String, // user id
+    event: String, // event id
+    iat: usize,
+    nbf: usize,
+    exp: usize,
+    iss: String,
+}
+
+#[derive(Serialize)]
+pub struct Ticket {
+    pub event_id: String,
+    pub ticket: String, // the payload to encode in the QR code
+    pub expires_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CheckInRequest {
+    pub ticket: String,
+}
+
+#[derive(Serialize)]
+pub struct CheckInResponse {
+    pub user_id: String,
+    pub name: String,
+    pub checked_in_at: String,
+}
+
+#[derive(Serialize)]
+pub struct CheckInStats {
+    pub event_id: String,
+    pub attending: usize,
+    pub checked_in: u64,
+    pub capacity: Option<i32>,
+}
+
+// who is scanning: an api key with attendees:check_in or a signed in organizer
+fn checker(caller: &ScopedCaller<scope::AttendeesCheckIn>) -> (String, Option<ObjectId>) {
+    match &caller.caller {
+        Caller::ApiKey { id, name } => (name.clone(), Some(*id)),
+        Caller::User { email } => (email.clone(), None),
+    }
+}
+
+async fn find_event(event_id: &str, db: &Collection<Event>) -> Result<Event, Status> {
+    let event_oid = ObjectId::parse_str(event_id).map_err(|_| Status::BadRequest)?;
+    match db.find_one(doc! {"_id": event_oid}).await {
+        Ok(Some(event)) => Ok(event),
+        Ok(None) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+// the caller's ticket for an event they hold a seat at
+#[get("/event/<event_id>/ticket")]
+pub async fn read_ticket(
+    event_id: &str,
+    db: &State<Collection<Event>>,
+    keys: &State<KeyRing>,
+    actor: Actor, // the signed in user
+) -> Result<Json<Ticket>, Status> {
+    let event = find_event(event_id, db).await?;
+    if event.cancelled_at.is_some() {
+        return Err(Status::Gone);
+    }
+    let user_id = actor.id();
+    // people on the waitlist get a ticket once they are promoted
+    if !event.attendees.iter().any(|attendee| attendee.user_id == user_id) {
+        return Err(Status::Forbidden);
+    }
+
+    let expires_at = event.ends_at.unwrap_or(event.date) + Duration::seconds(TICKET_GRACE);
+    if expires_at <= Utc::now() {
+        return Err(Status::Gone);
+    }
+    let now = Utc::now().timestamp() as usize;
+    let claims = TicketClaims {
+        typ: TICKET_TYPE.to_string(),
+        sub: user_id.to_hex(),
+        event: event.id.unwrap_or_default().to_hex(),
+        iat: now,
+        nbf: now,
+        exp: expires_at.timestamp() as usize,
+        iss: keys.issuer.clone(),
+    };
+    let ticket = keys.sign(&claims).map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(Ticket {
+        event_id: event_id.to_string(),
+        ticket,
+        expires_at: expires_at.to_rfc3339(),
+    }))
+}
+
+// verifies a scanned ticket and marks the holder as present, a second scan is a 409
+#[post("/event/<event_id>/check-in", format = "json", data = "<check_in_req>")]
+pub async fn check_in_attendee(
+    event_id: &str,
+    check_in_req: Json<CheckInRequest>,
+    db: &State<Collection<Event>>,
+    check_ins: &State<Collection<CheckIn>>,
+    keys: &State<KeyRing>,
+    caller: ScopedCaller<scope::AttendeesCheckIn>, // api key with attendees:check_in or a user with EventUpdate
+    audit: Audit<'_>,
+) -> Result<Json<CheckInResponse>, status::Custom<String>> {
+    let (checked_in_by, api_key_id) = checker(&caller);
+
+    let invalid = || status::Custom(Status::Unauthorized, "Invalid ticket.".to_string());
+    let claims = keys.verify::<TicketClaims>(&check_in_req.ticket).map_err(|_| invalid())?;
+    if claims.typ != TICKET_TYPE {
+        return Err(invalid());
+    }
+    let event = find_event(event_id, db).await.map_err(|status| status::Custom(status, String::new()))?;
+    if event.cancelled_at.is_some() {
+        return Err(status::Custom(Status::Gone, "The event was cancelled.".to_string()));
+    }
+    let event_oid = event.id.unwrap_or_default();
+    // a ticket for another event is valid, just not here
+    if claims.event != event_oid.to_hex() {
+        return Err(status::Custom(Status::BadRequest, "Ticket is for a different event.".to_string()));
+    }
+    let user_id = ObjectId::parse_str(&claims.sub).map_err(|_| invalid())?;
+
+    // tickets outlive leaving the event, the seat is what counts
+    let attendee = match event.attendees.into_iter().find(|attendee| attendee.user_id == user_id) {
+        Some(attendee) => attendee,
+        None => return Err(status::Custom(Status::Forbidden, "Ticket holder is no longer attending.".to_string())),
+    };
+
+    let now = BsonDateTime::now();
+    let record = doc! {
+        "$setOnInsert": {
+            "event_id": event_oid,
+            "user_id": user_id,
+            "checked_in_by": &checked_in_by,
+            "api_key_id": api_key_id,
+            "checked_in_at": now,
+        }
+    };
+    // the upsert only inserts on the first scan, the unique index settles concurrent ones
+    let result = check_ins
+        .update_one(doc! {"event_id": event_oid, "user_id": user_id}, record)
+        .upsert(true)
+        .await
+        .map_err(|_| status::Custom(Status::InternalServerError, String::new()))?;
+
+    if result.upserted_id.is_none() {
+        let existing = check_ins
+            .find_one(doc! {"event_id": event_oid, "user_id": user_id})
+            .await
+            .ok()
+            .flatten();
+        let at = existing
+            .and_then(|check_in| check_in.checked_in_at.try_to_rfc3339_string().ok())
+            .unwrap_or_default();
+        return Err(status::Custom(Status::Conflict, format!("Already checked in at {}.", at)));
+    }
+
+    // api keys are named by id in the log, names aren't unique
+    let audit_actor = match api_key_id {
+        Some(id) => format!("api_key:{}", id.to_hex()),
+        None => checked_in_by.clone(),
+    };
+    let check_in = CheckIn {
+        id: result.upserted_id.and_then(|id| id.as_object_id()),
+        event_id: event_oid,
+        user_id,
+        checked_in_by,
+        api_key_id,
+        checked_in_at: now,
+    };
+    audit.record(&audit_actor, "event.check_in", vec![event_oid.to_hex(), user_id.to_hex()], None, snapshot(&check_in)).await;
+
+    Ok(Json(CheckInResponse {
+        user_id: user_id.to_hex(),
+        name: attendee.name,
+        checked_in_at: now.try_to_rfc3339_string().unwrap_or_default(),
+    }))
+}
+
+// checked in against registered, polled by the door staff while the event runs
+#[get("/event/<event_id>/check-ins")]
+pub async fn read_check_in_stats(
+    event_id: &str,
+    db: &State<Collection<Event>>,
+    check_ins: &State<Collection<CheckIn>>,
+    _caller: ScopedCaller<scope::AttendeesCheckIn>, // api key with attendees:check_in or a user with EventUpdate
+) -> Result<Json<CheckInStats>, Status> {
+    let event = find_event(event_id, db).await?;
+    let checked_in = check_ins
+        .count_documents(doc! {"event_id": event.id.unwrap_or_default()})
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(CheckInStats {
+        event_id: event_id.to_string(),
+        attending: event.attendees.len(),
+        checked_in,
+        capacity: event.capacity,
+    }))
+}
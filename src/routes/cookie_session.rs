@@ -0,0 +1,153 @@
+use std::env;
+
+use dotenv::dotenv;
+use lazy_static::lazy_static;
+use rocket::http::{Cookie, CookieJar, Method, SameSite, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::time::Duration;
+
+use super::auth::{random_token, LoginResponse, TokenResponse, ACCESS_TOKEN_TTL, REFRESH_TOKEN_TTL};
+
+// browsers get their tokens in private (encrypted) HttpOnly cookies instead of the response body,
+// so scripts never see them. Mobile and API clients keep using bearer tokens.
+const ACCESS_COOKIE: &str = "soc_access";
+const REFRESH_COOKIE: &str = "soc_refresh";
+const REFRESH_COOKIE_PATH: &str = "/api/v1/auth"; // only sent to refresh and logout
+// double submit: readable by the frontend, echoed back in the header on state changing requests
+const CSRF_COOKIE: &str = "soc_csrf";
+const CSRF_HEADER: &str = "X-CSRF-Token";
+// clients ask for the cookie transport when logging in
+const TRANSPORT_HEADER: &str = "X-Auth-Transport";
+
+const OIDC_STATE_COOKIE: &str = "soc_oidc_state";
+const OIDC_STATE_COOKIE_PATH: &str = "/api/v1/auth/oidc"; // start and callback only
+
+lazy_static! {
+    // COOKIE_SECURE=false for local development over plain http
+    static ref COOKIE_SECURE: bool = {
+        dotenv().ok();
+        env::var("COOKIE_SECURE").map(|value| value != "false").unwrap_or(true)
+    };
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Bearer,
+    Cookie,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Transport {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match req.headers().get_one(TRANSPORT_HEADER) {
+            Some(transport) if transport.eq_ignore_ascii_case("cookie") => Outcome::Success(Transport::Cookie),
+            _ => Outcome::Success(Transport::Bearer),
+        }
+    }
+}
+
+// the refresh token from the cookie, CSRF checked; None when the client uses bearer tokens
+pub struct RefreshCookie(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RefreshCookie {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match req.cookies().get_private(REFRESH_COOKIE) {
+            Some(cookie) if csrf_matches(req) => Outcome::Success(RefreshCookie(Some(cookie.value().to_string()))),
+            Some(_) => Outcome::Error((Status::Forbidden, ())),
+            None => Outcome::Success(RefreshCookie(None)),
+        }
+    }
+}
+
+fn csrf_matches(req: &Request<'_>) -> bool {
+    match (req.cookies().get(CSRF_COOKIE), req.headers().get_one(CSRF_HEADER)) {
+        (Some(cookie), Some(header)) => !header.is_empty() && cookie.value() == header,
+        _ => false,
+    }
+}
+
+// the access token of a request: the Authorization header, else the session cookie.
+// Cookies are sent by the browser on their own, so unsafe methods also need the CSRF header.
+pub fn request_token(req: &Request<'_>) -> Result<String, Status> {
+    if let Some(header) = req.headers().get_one("Authorization") {
+        return header
+            .strip_prefix("Bearer ")
+            .map(|token| token.to_string())
+            .ok_or(Status::Unauthorized);
+    }
+    let cookie = req.cookies().get_private(ACCESS_COOKIE).ok_or(Status::Unauthorized)?;
+    let safe = matches!(req.method(), Method::Get | Method::Head | Method::Options);
+    if !safe && !csrf_matches(req) {
+        return Err(Status::Forbidden);
+    }
+    Ok(cookie.value().to_string())
+}
+
+// what a successful login returns for the transport the client asked for
+pub fn respond(tokens: TokenResponse, transport: Transport, cookies: &CookieJar<'_>) -> LoginResponse {
+    match transport {
+        Transport::Bearer => LoginResponse::Tokens(tokens),
+        Transport::Cookie => LoginResponse::Session { csrf_token: set_session_cookies(tokens, cookies) },
+    }
+}
+
+fn set_session_cookies(tokens: TokenResponse, cookies: &CookieJar<'_>) -> String {
+    let csrf_token = random_token();
+    cookies.add_private(
+        Cookie::build((ACCESS_COOKIE, tokens.access_token))
+            .path("/")
+            .http_only(true)
+            .secure(*COOKIE_SECURE)
+            .same_site(SameSite::Lax)
+            .max_age(Duration::seconds(ACCESS_TOKEN_TTL as i64)),
+    );
+    cookies.add_private(
+        Cookie::build((REFRESH_COOKIE, tokens.refresh_token))
+            .path(REFRESH_COOKIE_PATH)
+            .http_only(true)
+            .secure(*COOKIE_SECURE)
+            .same_site(SameSite::Strict)
+            .max_age(Duration::seconds(REFRESH_TOKEN_TTL)),
+    );
+    cookies.add(
+        Cookie::build((CSRF_COOKIE, csrf_token.clone()))
+            .path("/")
+            .http_only(false)
+            .secure(*COOKIE_SECURE)
+            .same_site(SameSite::Lax)
+            .max_age(Duration::seconds(REFRESH_TOKEN_TTL)),
+    );
+    csrf_token
+}
+
+pub fn clear_session_cookies(cookies: &CookieJar<'_>) {
+    cookies.remove_private(Cookie::build(ACCESS_COOKIE).path("/"));
+    cookies.remove_private(Cookie::build(REFRESH_COOKIE).path(REFRESH_COOKIE_PATH));
+    cookies.remove(Cookie::build(CSRF_COOKIE).path("/"));
+}
+
+// ties an OIDC login to the browser that started it, so a callback URL handed to someone else
+// can't sign them into the account that started the flow. Lax still sends it on the provider's
+// top level redirect back to the callback.
+pub fn set_oidc_state(state: &str, ttl_seconds: i64, cookies: &CookieJar<'_>) {
+    cookies.add_private(
+        Cookie::build((OIDC_STATE_COOKIE, state.to_string()))
+            .path(OIDC_STATE_COOKIE_PATH)
+            .http_only(true)
+            .secure(*COOKIE_SECURE)
+            .same_site(SameSite::Lax)
+            .max_age(Duration::seconds(ttl_seconds)),
+    );
+}
+
+// the state this browser started a login with, removed so it is only used once
+pub fn take_oidc_state(cookies: &CookieJar<'_>) -> Option<String> {
+    let state = cookies.get_private(OIDC_STATE_COOKIE).map(|cookie| cookie.value().to_string());
+    cookies.remove_private(Cookie::build(OIDC_STATE_COOKIE).path(OIDC_STATE_COOKIE_PATH));
+    state
+}
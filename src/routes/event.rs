@@ -1,48 +1,130 @@
 use std::sync::Arc;
 
-use crate::models::event::EventType;
-use crate::models::{Attendee, Event, User};
-use chrono::{format, DateTime, Utc};
+use crate::models::event::{default_time_zone, stored_date, EventImage, EventType};
+use crate::models::{Attendee, Event, FeaturedEvents, User};
+use chrono::{format, DateTime, LocalResult, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
 use futures::{StreamExt, TryStreamExt};
-use mongodb::bson::{doc, DateTime as BsonDateTime};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL, Engine};
+use mongodb::bson::{doc, Bson, Document};
 use mongodb::change_stream::{event, session};
 use mongodb::{Client, Cursor};
 use mongodb::{bson::{ oid::{self, ObjectId}}, options::FindOptions, Collection};
-use rocket::{post, serde::json::Json, State};
+use rocket::{post, response::status, serde::json::Json, State};
 use rocket::http::Status;
 use serde::{Deserialize, Serialize};
 
-use super::auth::{AdminUser, AuthToken};
-use super::AuthenticatedUser;
-
-const PINNED_EVENT :bool  = false;
+use super::auth::{perm, regex_escape, AuthContext, Authorized};
+use super::verification::VerifiedEmail;
+use super::api_key::{scope, ScopedCaller};
+use super::access::{policy, Actor};
+use super::audit::{snapshot, Audit};
+use super::media::delete_variants;
+use crate::storage::Storage;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserJoinRequest {
-    pub user_id: String,  // We'll parse this to ObjectId in the handler
-    pub name: String,
-    pub email: String,
+    #[serde(default)]
+    pub user_id: Option<String>,  // someone else to register, defaults to the caller
+}
+
+// times are RFC 3339, or local times like "2025-06-14T18:00" read in `time_zone`
+#[derive(Debug, Serialize, Deserialize)]
+struct ScheduleRequest {
+    date: String, // start
+    ends_at: String,
+    #[serde(default = "default_time_zone")]
+    time_zone: String,
+    #[serde(default)]
+    registration_opens_at: Option<String>,
+    #[serde(default)]
+    registration_closes_at: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct EventRequest {
     name: String,
     location: String,
-    date: String,
+    #[serde(flatten)]
+    schedule: ScheduleRequest,
+    #[serde(default)]
+    capacity: Option<i32>, // unlimited when unset
     host_id: Option<ObjectId>,
     description: String,
     event_type: EventType
 }
 
+// the editable details, the schedule goes through the same checks as on create
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventUpdateRequest {
+    name: String,
+    location: String,
+    description: String,
+    #[serde(flatten)]
+    schedule: ScheduleRequest,
+}
+
+struct Schedule {
+    starts_at: DateTime<Utc>,
+    ends_at: DateTime<Utc>,
+    time_zone: Tz,
+    registration_opens_at: Option<DateTime<Utc>>,
+    registration_closes_at: Option<DateTime<Utc>>,
+}
+
+fn parse_instant(value: &str, time_zone: Tz, field: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(instant) = DateTime::parse_from_rfc3339(value) {
+        return Ok(instant.with_timezone(&Utc));
+    }
+    let local = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M"))
+        .map_err(|_| format!("{} is not a valid date and time.", field))?;
+    // local times that fall into a DST gap or overlap don't name a single instant
+    match time_zone.from_local_datetime(&local) {
+        LocalResult::Single(instant) => Ok(instant.with_timezone(&Utc)),
+        _ => Err(format!("{} is ambiguous or does not exist in {}.", field, time_zone)),
+    }
+}
+
+fn parse_schedule(event: &ScheduleRequest) -> Result<Schedule, String> {
+    let time_zone: Tz = event.time_zone.parse().map_err(|_| format!("Unknown time zone {}.", event.time_zone))?;
+    let starts_at = parse_instant(&event.date, time_zone, "date")?;
+    let ends_at = parse_instant(&event.ends_at, time_zone, "ends_at")?;
+    let registration_opens_at = event
+        .registration_opens_at
+        .as_deref()
+        .map(|value| parse_instant(value, time_zone, "registration_opens_at"))
+        .transpose()?;
+    let registration_closes_at = event
+        .registration_closes_at
+        .as_deref()
+        .map(|value| parse_instant(value, time_zone, "registration_closes_at"))
+        .transpose()?;
+
+    if ends_at <= starts_at {
+        return Err("An event has to end after it starts.".to_string());
+    }
+    if registration_closes_at.map(|closes| closes > ends_at).unwrap_or(false) {
+        return Err("Registration can't close after the event ends.".to_string());
+    }
+    if registration_opens_at.map(|opens| opens >= registration_closes_at.unwrap_or(starts_at)).unwrap_or(false) {
+        return Err("Registration has to open before it closes.".to_string());
+    }
+
+    Ok(Schedule { starts_at, ends_at, time_zone, registration_opens_at, registration_closes_at })
+}
+
 #[post("/event", format = "json", data = "<new_event>")]
 pub async fn create_event(
     new_event: Json<EventRequest>,
     database: &State<Collection<Event>>,
-    _admin: AdminUser, // only admin can call this
-    _token: AuthToken,  // verify blacklisted tokens
-    _user: AuthenticatedUser, // verify authenticated user
-) -> Json<String> {
-    // Ensure the id is a valid object (if needed)
+    admin: Authorized<perm::EventCreate>, // only organizers and core team can call this
+    audit: Audit<'_>,
+) -> Result<Json<String>, status::Custom<String>> {
+    let schedule = parse_schedule(&new_event.schedule).map_err(|msg| status::Custom(Status::BadRequest, msg))?;
+    if new_event.capacity.map(|capacity| capacity < 1).unwrap_or(false) {
+        return Err(status::Custom(Status::BadRequest, "Capacity has to be at least 1.".to_string()));
+    }
     
     // Check if an event with the same name and location already exists
     let existing_event = database
@@ -57,7 +139,7 @@ pub async fn create_event(
 
     match existing_event {
         // If an event with the same name and location is found, return an error message
-        Ok(Some(_)) => Json("Event with the same name and location already exists.".to_string()),
+        Ok(Some(_)) => Ok(Json("Event with the same name and location already exists.".to_string())),
         
         // If no such event exists, proceed with creating the new event
         Ok(None) => {
@@ -65,24 +147,37 @@ pub async fn create_event(
                 id: None,
                 name: new_event.name.clone(),
                 location: new_event.location.clone(),
-                date: Utc::now(),
+                date: schedule.starts_at,
+                ends_at: Some(schedule.ends_at),
+                time_zone: schedule.time_zone.name().to_string(),
+                registration_opens_at: schedule.registration_opens_at,
+                registration_closes_at: schedule.registration_closes_at,
                 host_id: new_event.host_id,
                 event_type: new_event.event_type.clone(),
                 description: new_event.description.clone(),
                 attendees: vec![],
-                image_url: Some("".to_string()),
-                pinned: PINNED_EVENT,
+                capacity: new_event.capacity,
+                waitlist: vec![],
+                image_url: None, // set once an image is uploaded
+                image: None,
+                sequence: 0,
+                cancelled_at: None,
+                series_id: None,
             };
 
-            let result = database.insert_one(new_event).await;
+            let result = database.insert_one(&new_event).await;
 
             match result {
-                Ok(_) => Json("Event successfully created".to_string()),
-                Err(_) => Json("Failed to create event".to_string()),
+                Ok(inserted) => {
+                    let id = inserted.inserted_id.as_object_id().map(|id| id.to_hex()).unwrap_or_default();
+                    audit.record(&admin.email, "event.create", vec![id], None, snapshot(&new_event)).await;
+                    Ok(Json("Event successfully created".to_string()))
+                },
+                Err(_) => Ok(Json("Failed to create event".to_string())),
             }
         }
         // Handle the case where the `find_one` operation itself fails
-        Err(_) => Json("Error checking for existing events.".to_string()),
+        Err(_) => Ok(Json("Error checking for existing events.".to_string())),
     }
 }
 
@@ -90,9 +185,8 @@ pub async fn create_event(
 #[get("/event/<event_id>")]
 pub async  fn read_event(db: &State<Collection<Event>>, 
     event_id: &str,
-    _token: AuthToken, // verfiy blacklisted tokens
-    _user: AuthenticatedUser // verity authenticated user
-) -> Result<Json<Event>, Status> {
+    _caller: ScopedCaller<scope::EventsRead>, // signed in user or api key with events:read
+) -> Result<Json<PublicEvent>, Status> {
     let collection = db;
     let object_id = match ObjectId::parse_str(event_id) {
         Ok(oid) => oid,
@@ -104,7 +198,7 @@ pub async  fn read_event(db: &State<Collection<Event>>,
     match result {
         Ok(fetch_data) => {
             if let Some(data) = fetch_data {
-                Ok(Json(data))
+                Ok(Json(data.into()))
             } else {
                 Err(Status::NotFound)
             }
@@ -113,57 +207,238 @@ pub async  fn read_event(db: &State<Collection<Event>>,
     }
 }
 
-#[get("/events")]
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+// filters, sorting and paging for GET /events, all optional
+#[derive(FromForm)]
+pub struct EventQuery<'r> {
+    event_type: Option<&'r str>,
+    from: Option<&'r str>, // RFC 3339, inclusive
+    to: Option<&'r str>,   // RFC 3339, exclusive
+    location: Option<&'r str>, // case insensitive substring
+    host_id: Option<&'r str>,
+    pinned: Option<bool>, // currently featured or not, see featured.rs
+    sort: Option<&'r str>,  // date (default), created or popularity
+    order: Option<&'r str>, // asc or desc, date defaults to asc and the others to desc
+    limit: Option<i64>,
+    cursor: Option<&'r str>, // next_cursor of the previous page
+}
+
+#[derive(Serialize)]
+pub struct EventPage {
+    pub events: Vec<PublicEvent>,
+    pub total: u64, // events matching the filters, across all pages
+    pub next_cursor: Option<String>,
+}
+
+// position after the last event of a page, opaque to clients
+#[derive(Serialize, Deserialize)]
+struct EventCursor {
+    id: ObjectId,
+    #[serde(default)]
+    date: Option<String>,
+    #[serde(default)]
+    popularity: Option<i64>,
+}
+
+#[derive(Clone, Copy)]
+enum EventSort {
+    Date,
+    Created,
+    Popularity,
+}
+
+impl EventSort {
+    fn field(&self) -> &'static str {
+        match self {
+            EventSort::Date => "date",
+            EventSort::Created => "_id", // object ids grow with creation time
+            EventSort::Popularity => "attendee_count",
+        }
+    }
+}
+
+// bounds are compared as strings, so they take the fixed format event dates are stored in
+fn date_bound(value: &str) -> Result<String, Status> {
+    let date = DateTime::parse_from_rfc3339(value).map_err(|_| Status::BadRequest)?;
+    Ok(stored_date(&date.with_timezone(&Utc)))
+}
+
+fn event_filter(query: &EventQuery<'_>, featured_ids: &[ObjectId]) -> Result<Document, Status> {
+    let mut filter = doc! {};
+    if let Some(event_type) = query.event_type {
+        let event_type: EventType = serde_json::from_value(serde_json::Value::String(event_type.to_lowercase()))
+            .map_err(|_| Status::BadRequest)?;
+        filter.insert("event_type", mongodb::bson::to_bson(&event_type).map_err(|_| Status::BadRequest)?);
+    }
+    let mut date = doc! {};
+    if let Some(from) = query.from {
+        date.insert("$gte", date_bound(from)?);
+    }
+    if let Some(to) = query.to {
+        date.insert("$lt", date_bound(to)?);
+    }
+    if !date.is_empty() {
+        filter.insert("date", date);
+    }
+    if let Some(location) = query.location {
+        filter.insert("location", doc! {"$regex": regex_escape(location), "$options": "i"});
+    }
+    if let Some(host_id) = query.host_id {
+        filter.insert("host_id", ObjectId::parse_str(host_id).map_err(|_| Status::BadRequest)?);
+    }
+    if let Some(pinned) = query.pinned {
+        let op = if pinned { "$in" } else { "$nin" };
+        filter.insert("_id", doc! {op: featured_ids});
+    }
+    Ok(filter)
+}
+
+// events after the cursor in sort order, ties broken by id
+fn cursor_filter(cursor: &EventCursor, sort: EventSort, ascending: bool) -> Result<Document, Status> {
+    let op = if ascending { "$gt" } else { "$lt" };
+    let value: Bson = match sort {
+        EventSort::Created => return Ok(doc! {"_id": {op: cursor.id}}),
+        EventSort::Date => cursor.date.clone().ok_or(Status::BadRequest)?.into(),
+        EventSort::Popularity => cursor.popularity.ok_or(Status::BadRequest)?.into(),
+    };
+    let field = sort.field();
+    Ok(doc! {"$or": [
+        {field: {op: value.clone()}},
+        {field: value, "_id": {op: cursor.id}},
+    ]})
+}
+
+#[get("/events?<query..>")]
 pub async fn read_events(Database: &State<Collection<Event>>,
-    _token: AuthToken, // verfiy blacklisted tokens
-    _user: AuthenticatedUser // verity authenticated user
-) -> Json<Vec<Event>> {
-    let mut cursor :Cursor<Event> = Database
-        .find( doc! {})
+    featured: &State<Collection<FeaturedEvents>>,
+    query: EventQuery<'_>,
+    _caller: ScopedCaller<scope::EventsRead>, // signed in user or api key with events:read
+) -> Result<Json<EventPage>, Status> {
+    let sort = match query.sort.unwrap_or("date") {
+        "date" => EventSort::Date,
+        "created" => EventSort::Created,
+        "popularity" => EventSort::Popularity,
+        _ => return Err(Status::BadRequest),
+    };
+    let ascending = match query.order {
+        Some("asc") => true,
+        Some("desc") => false,
+        Some(_) => return Err(Status::BadRequest),
+        None => matches!(sort, EventSort::Date),
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let featured_ids = match query.pinned {
+        Some(_) => super::featured::active_ids(&super::featured::load(featured).await?),
+        None => vec![],
+    };
+    let filter = event_filter(&query, &featured_ids)?;
+
+    let total = Database
+        .count_documents(filter.clone())
         .await
-        .expect("Failed to find evets");
-    let mut events: Vec<Event> = Vec::new();
-    while let Some(event) = cursor.try_next().await.expect("Error iterating cursor") {
-        events.push(event);
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut pipeline = vec![
+        doc! {"$match": filter},
+        doc! {"$addFields": {"attendee_count": {"$size": {"$ifNull": ["$attendees", []]}}}},
+    ];
+    if let Some(cursor) = query.cursor {
+        let cursor: EventCursor = BASE64_URL
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or(Status::BadRequest)?;
+        pipeline.push(doc! {"$match": cursor_filter(&cursor, sort, ascending)?});
     }
-    Json(events)
+    let direction = if ascending { 1 } else { -1 };
+    pipeline.push(doc! {"$sort": {sort.field(): direction, "_id": direction}});
+    // one extra to know whether there is another page
+    pipeline.push(doc! {"$limit": limit + 1});
+
+    let mut documents: Vec<Document> = Database
+        .aggregate(pipeline)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .try_collect()
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let next_cursor = if documents.len() as i64 > limit {
+        documents.truncate(limit as usize);
+        documents.last().map(|last| {
+            let cursor = EventCursor {
+                id: last.get_object_id("_id").unwrap_or_default(),
+                date: last.get_str("date").ok().map(|date| date.to_string()),
+                popularity: last.get_i32("attendee_count").ok().map(i64::from),
+            };
+            BASE64_URL.encode(serde_json::to_vec(&cursor).unwrap_or_default())
+        })
+    } else {
+        None
+    };
+
+    let events = documents
+        .into_iter()
+        .map(mongodb::bson::from_document::<Event>)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(EventPage { events: public_events(events), total, next_cursor }))
 }
 
 #[put("/event/<event_id>", format = "json", data = "<updated_event>")]
 pub async fn update_event(
     event_id: &str,
-    updated_event: Json<Event>,
+    updated_event: Json<EventUpdateRequest>,
     db: &State<Collection<Event>>,
-    _admin: AdminUser, // only admin can call this
-    _token: AuthToken, // verfiy blacklisted tokens
-    _user: AuthenticatedUser // verity authenticated user
-) -> Result<Json<String>, Status> {
+    admin: Authorized<perm::EventUpdate>, // only organizers and core team can call this
+    audit: Audit<'_>,
+) -> Result<Json<String>, status::Custom<String>> {
     let collection = db;
 
     // Convert event_id to ObjectId
     let event_oid = match ObjectId::parse_str(event_id) {
         Ok(oid) => oid,
-        Err(_) => return Err(Status::BadRequest) // Return 400 if it's not a valid ObjectId
+        Err(_) => return Err(status::Custom(Status::BadRequest, String::new())) // Return 400 if it's not a valid ObjectId
     };
+    let schedule = parse_schedule(&updated_event.schedule).map_err(|msg| status::Custom(Status::BadRequest, msg))?;
 
-    let  update_doc = doc! {
-        "$set" : {
-            "name": &updated_event.name,
+    let changes = doc! {
+        "name": &updated_event.name,
         "location": &updated_event.location,
-        }
+        "description": &updated_event.description,
+        "date": stored_date(&schedule.starts_at),
+        "ends_at": stored_date(&schedule.ends_at),
+        "time_zone": schedule.time_zone.name(),
+        "registration_opens_at": schedule.registration_opens_at.as_ref().map(stored_date),
+        "registration_closes_at": schedule.registration_closes_at.as_ref().map(stored_date),
+    };
+    let  update_doc = doc! {
+        "$set" : changes.clone(),
+        "$inc": {"sequence": 1} // subscribed calendars replace their copy
     };
     
     let filter = doc! {"_id": event_oid};
     
     match collection
     .find_one_and_update(filter, update_doc).await {
-        Ok(Some(_)) => Ok(Json("User successfully  updated".to_string())),
+        Ok(Some(previous)) => {
+            let before = snapshot(&previous);
+            let after = before.clone().map(|mut after| {
+                after.extend(changes);
+                after
+            });
+            audit.record(&admin.email, "event.update", vec![event_oid.to_hex()], before, after).await;
+            Ok(Json("Event successfully updated".to_string()))
+        },
         Ok(None)=> {
             
-            Err(Status::NotFound)
+            Err(status::Custom(Status::NotFound, String::new()))
         },
         Err(_e)=> {
-            Err(Status::InternalServerError)
+            Err(status::Custom(Status::InternalServerError, String::new()))
         }
     }
     
@@ -172,9 +447,9 @@ pub async fn update_event(
 #[delete("/event/<event_id>")]
 pub async fn drop_event(event_id: &str, 
     db: &State<Collection<Event>>,
-    _admin: AdminUser, // only admin can call this
-    _token: AuthToken, // verfiy blacklisted tokens
-    _user: AuthenticatedUser // verity authenticated user           
+    storage: &State<Storage>,
+    admin: Authorized<perm::EventDelete>, // only core team can call this
+    audit: Audit<'_>,
 ) -> Result<Json<String>, Status> {
     let collection = db;
 
@@ -183,95 +458,236 @@ pub async fn drop_event(event_id: &str,
         Err(_) => return Err(Status::BadRequest)
     };
     let filter = doc! {"_id": object_id};
-    let result = collection.delete_one(filter).await;
+    let result = collection.find_one_and_delete(filter).await;
 
     match result {
-       Ok(deleted_result) => {
-        if deleted_result.deleted_count > 0 {
+       Ok(Some(deleted)) => {
+            if let Some(image) = &deleted.image {
+                delete_variants(image, storage).await;
+            }
+            audit.record(&admin.email, "event.delete", vec![object_id.to_hex()], snapshot(&deleted), None).await;
             Ok(Json("User deleted successfully".to_string()))
-        }else {
-            Err(Status::NotFound)
-        }
        }
+       Ok(None) => Err(Status::NotFound),
        Err(_)=> Err(Status::InternalServerError)
     }
 }
 
+// keeps the event around so attendees and calendars see it was called off
+#[put("/event/<event_id>/cancel")]
+pub async fn cancel_event(
+    event_id: &str,
+    db: &State<Collection<Event>>,
+    admin: Authorized<perm::EventUpdate>, // only organizers and core team can call this
+    audit: Audit<'_>,
+) -> Result<Json<String>, Status> {
+    let event_oid = ObjectId::parse_str(event_id).map_err(|_| Status::BadRequest)?;
+
+    // dates are stored as fixed format strings, see stored_date
+    let now = stored_date(&Utc::now());
+    let update_doc = doc! {
+        "$set": {"cancelled_at": &now},
+        "$inc": {"sequence": 1},
+    };
+    match db.find_one_and_update(doc! {"_id": event_oid, "cancelled_at": Bson::Null}, update_doc).await {
+        Ok(Some(previous)) => {
+            let before = snapshot(&previous);
+            let after = before.clone().map(|mut after| {
+                after.insert("cancelled_at", &now);
+                after
+            });
+            audit.record(&admin.email, "event.cancel", vec![event_oid.to_hex()], before, after).await;
+            Ok(Json("Event cancelled.".to_string()))
+        }
+        // either missing or cancelled already
+        Ok(None) => match db.count_documents(doc! {"_id": event_oid}).await {
+            Ok(0) => Err(Status::NotFound),
+            Ok(_) => Err(Status::Conflict),
+            Err(_) => Err(Status::InternalServerError),
+        },
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+// where a member stands for an event
+#[derive(Debug, Serialize)]
+pub struct RegistrationStatus {
+    pub status: &'static str, // "attending", "waitlisted" or "none"
+    pub waitlist_position: Option<usize>, // 1 is next in line
+}
+
+impl RegistrationStatus {
+    fn of(event: &Event, user_id: &ObjectId) -> Self {
+        if event.attendees.iter().any(|attendee| &attendee.user_id == user_id) {
+            return RegistrationStatus { status: "attending", waitlist_position: None };
+        }
+        match event.waitlist.iter().position(|waiting| &waiting.user_id == user_id) {
+            Some(index) => RegistrationStatus { status: "waitlisted", waitlist_position: Some(index + 1) },
+            None => RegistrationStatus { status: "none", waitlist_position: None },
+        }
+    }
+}
+
+// matches the event while it still has a free seat
+fn has_free_seat() -> Document {
+    doc! {"$or": [
+        {"capacity": Bson::Null},
+        {"$expr": {"$lt": [{"$size": {"$ifNull": ["$attendees", []]}}, "$capacity"]}},
+    ]}
+}
+
+// moves waitlisted members into free seats, first come first served
+pub(crate) async fn promote_waitlist(
+    event_oid: ObjectId,
+    db: &Collection<Event>,
+    user_collection: &Collection<User>,
+) -> Result<(), mongodb::error::Error> {
+    let mut filter = has_free_seat();
+    filter.insert("_id", event_oid);
+    filter.insert("waitlist.0", doc! {"$exists": true});
+    // one atomic step per seat so concurrent leaves can't hand out the same seat twice
+    let promote = vec![doc! {"$set": {
+        "attendees": {"$concatArrays": [{"$ifNull": ["$attendees", []]}, [{"$arrayElemAt": ["$waitlist", 0]}]]},
+        "waitlist": {"$slice": ["$waitlist", 1, {"$max": [{"$size": "$waitlist"}, 1]}]},
+    }}];
+    while let Some(before) = db.find_one_and_update(filter.clone(), promote.clone()).await? {
+        if let Some(promoted) = before.waitlist.first() {
+            user_collection
+                .update_one(doc! {"_id": promoted.user_id}, doc! {"$addToSet": {"attending_events": event_oid}})
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+// takes a seat when one is free, otherwise joins the end of the waitlist
 #[put("/event/join/<event_id>", format = "json", data = "<user_data>")]
 pub async fn join_event(
     event_id: &str,
     user_data: Json<UserJoinRequest>,
     db: &State<Collection<Event>>,
     user_collection: &State<Collection<User>>,
-    _token: AuthToken, // verfiy blacklisted tokens
-    _user: AuthenticatedUser // verity authenticated user     
-) -> Result<Json<String>, Status> {
+    actor: Actor, // the signed in user
+    _verified: VerifiedEmail, // verified email if the policy asks for it
+) -> Result<Json<RegistrationStatus>, Status> {
     // Convert event_id and user_id to ObjectId
     let event_oid = match ObjectId::parse_str(event_id) {
         Ok(oid) => oid,
         Err(_) => return Err(Status::BadRequest), // Invalid event ID
     };
 
-    let user_oid = match ObjectId::parse_str(&user_data.user_id) {
-        Ok(oid) => oid,
-        Err(_) => return Err(Status::BadRequest), // Invalid user ID
+    // registering someone else is for organizers
+    let user_oid = actor.resolve_target(user_data.user_id.as_deref(), policy::JOIN_EVENT)?;
+    let attendee_user = if user_oid == actor.id() {
+        actor.user
+    } else {
+        match user_collection.find_one(doc! {"_id": user_oid}).await {
+            Ok(Some(user)) => user,
+            Ok(None) => return Err(Status::NotFound),
+            Err(_) => return Err(Status::InternalServerError),
+        }
     };
 
-    // Check if event exists
+    // Check if event exists and takes registrations right now
     let event = db.find_one(doc! {"_id": event_oid}).await;
     match event {
-        Ok(Some(_))=>{},
+        Ok(Some(event)) if event.registration_open(Utc::now()) => {},
+        Ok(Some(_)) => return Err(Status::Forbidden), // registration closed or not open yet
         Ok(None)=>return Err(Status::NotFound),
-        Err(_) => todo!(),
+        Err(_) => return Err(Status::InternalServerError),
     }
 
     // Create the new Attendee object
     let new_attendee = Attendee {
         user_id: user_oid,
-        name: user_data.name.clone(),
-        email: user_data.email.clone(),
+        name: attendee_user.name,
+        email: attendee_user.email,
     };
 
-    // Update event by adding the new Attendee to the attendees list
-    let update_doc = doc! {
-        "$addToSet": { // $addToSet ensures no duplicates
-            "attendees": new_attendee
-        }
-    };
-
-    let filter = doc! {"_id": event_oid};
-
-    let event_result =  db.find_one_and_update(filter.clone(), update_doc).await;
-
-    if let Err(_) = event_result {
-        return Err(Status::InternalServerError);
-    }
+    reserve_seat(event_oid, new_attendee, db, user_collection).await.map(Json)
+}
 
-    let update_user = doc! {
-        "$addToSet": {"attending_events": event_oid} // ensure no duplicates
+// a seat when one is free, otherwise the end of the waitlist. Registration windows are up to the caller.
+pub(crate) async fn reserve_seat(
+    event_oid: ObjectId,
+    new_attendee: Attendee,
+    db: &Collection<Event>,
+    user_collection: &Collection<User>,
+) -> Result<RegistrationStatus, Status> {
+    let user_oid = new_attendee.user_id;
+
+    // not yet attending or waiting
+    let not_registered = doc! {
+        "_id": event_oid,
+        "attendees.user_id": {"$ne": user_oid},
+        "waitlist.user_id": {"$ne": user_oid},
     };
-    let user_filter = doc! {"_id": user_oid};
 
-    let user_result = user_collection.update_one(user_filter.clone(), update_user).await;
+    // reserve a seat, the capacity check and the push are one atomic update
+    let mut seat_filter = has_free_seat();
+    seat_filter.extend(not_registered.clone());
+    let seat = db
+        .update_one(seat_filter, doc! {"$push": {"attendees": new_attendee.clone()}})
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if seat.modified_count > 0 {
+        let update_user = doc! {
+            "$addToSet": {"attending_events": event_oid} // ensure no duplicates
+        };
+        let user_result = user_collection.update_one(doc! {"_id": user_oid}, update_user).await;
+        if user_result.is_err() {
+            // Rollback changes if failure, the seat goes to the waitlist
+            let rollback_event = doc! {"$pull": {"attendees": {"user_id": user_oid}}};
+            let _ = db.update_one(doc! {"_id": event_oid}, rollback_event).await;
+            let _ = promote_waitlist(event_oid, db, user_collection).await;
+            return Err(Status::InternalServerError);
+        }
+    } else {
+        // full, or already registered; only the first case adds to the waitlist
+        let queued = db
+            .update_one(not_registered, doc! {"$push": {"waitlist": new_attendee}})
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+        // a seat freed between the failed reservation and the push found the waitlist empty,
+        // so nobody would hand it out later
+        if queued.modified_count > 0 {
+            promote_waitlist(event_oid, db, user_collection)
+                .await
+                .map_err(|_| Status::InternalServerError)?;
+        }
+    }
 
-    if let Err(_) = user_result {
-        // Rollback changes if failure
-        let rollback_event = doc! {"$pull": { "attendees": user_oid }};
-        let _ = db.update_one(filter, rollback_event).await;
-        return Err(Status::InternalServerError);
+    match db.find_one(doc! {"_id": event_oid}).await {
+        Ok(Some(event)) => Ok(RegistrationStatus::of(&event, &user_oid)),
+        Ok(None) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
     }
+}
 
-    Ok(Json("Successfully Join the event".to_string()))
+// the caller's seat or waitlist position
+#[get("/event/<event_id>/registration")]
+pub async fn read_registration(
+    event_id: &str,
+    db: &State<Collection<Event>>,
+    actor: Actor, // the signed in user
+) -> Result<Json<RegistrationStatus>, Status> {
+    let event_oid = ObjectId::parse_str(event_id).map_err(|_| Status::BadRequest)?;
+    match db.find_one(doc! {"_id": event_oid}).await {
+        Ok(Some(event)) => Ok(Json(RegistrationStatus::of(&event, &actor.id()))),
+        Ok(None) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
 }
 
+// gives up a seat or a waitlist spot; a freed seat goes to the next member on the waitlist
 #[delete("/event/leave/<event_id>/<user_id>")]
 pub async fn leave_event(
     event_id: &str,
     user_id: &str,
     db: &State<Collection<Event>>,
     user_collection: &State<Collection<User>>,
-    _token: AuthToken, // verfiy blacklisted tokens
-    _user: AuthenticatedUser // verity authenticated user     
+    actor: Actor // the signed in user
 ) -> Result<Json<String>, Status> {
     // Convert event_id and user_id to ObjectId
     let event_oid = match ObjectId::parse_str(event_id) {
@@ -279,58 +695,66 @@ pub async fn leave_event(
         Err(_) => return Err(Status::BadRequest), // Invalid event ID
     };
 
-    let user_oid = match ObjectId::parse_str(user_id) {
-        Ok(oid) => oid,
-        Err(_) => return Err(Status::BadRequest), // Invalid user ID
-        
-    };
+    // removing someone else is for organizers
+    let user_oid = actor.resolve_target(Some(user_id), policy::LEAVE_EVENT)?;
 
-    // Check if event exists
-    let event = db.find_one(doc! {"_id": event_oid}).await;
-    match event {
-        Ok(Some(_))=>{},
-        Ok(None)=>return Err(Status::NotFound),
-        Err(_) => todo!(),
-    }
+    release_seat(event_oid, user_oid, db, user_collection).await?;
 
-    // Update event by removing the attendee with matching user_id
+    Ok(Json("Successfully left the event.".to_string()))
+}
+
+// drops the user's seat or waitlist spot, the seat goes to the next one waiting
+pub(crate) async fn release_seat(
+    event_oid: ObjectId,
+    user_oid: ObjectId,
+    db: &Collection<Event>,
+    user_collection: &Collection<User>,
+) -> Result<(), Status> {
+    // Update event by removing the attendee or waitlist entry with matching user_id
     let update_doc = doc! {
         "$pull": {
-            "attendees": {
-                "user_id": user_oid
-            }
+            "attendees": {"user_id": user_oid},
+            "waitlist": {"user_id": user_oid},
         }
     };
 
     let filter = doc! {"_id": event_oid};
 
-    let event_result = db.find_one_and_update(filter.clone(), update_doc).await;
-    if let Err(_) = event_result {
-        return Err(Status::InternalServerError);
-    }
+    let before = match db.find_one_and_update(filter.clone(), update_doc).await {
+        Ok(Some(before)) => before,
+        Ok(None) => return Err(Status::NotFound),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    let left_seat = before.attendees.iter().find(|attendee| attendee.user_id == user_oid).cloned();
 
     let update_user = doc ! {
         "$pull": {"attending_events": event_oid} // remove event from the user attending list
     };
     let user_filter = doc! {"_id": user_oid};
     let user_result = user_collection.update_one(user_filter.clone(), update_user).await;
-    if let Err(_) = user_result {
+    if user_result.is_err() {
         // rollback event incase of failure
-        let rollback_event = doc! {"$addToSet": {"attendees": user_oid}};
-        let _ = db.update_one(filter, rollback_event).await;
+        if let Some(attendee) = left_seat {
+            let rollback_event = doc! {"$push": {"attendees": attendee}};
+            let _ = db.update_one(filter, rollback_event).await;
+        }
         return Err(Status::InternalServerError);
     }
 
-    Ok(Json("Successfully left the event.".to_string()))
+    if left_seat.is_some() {
+        promote_waitlist(event_oid, db, user_collection)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+    }
+    Ok(())
 }
 
 #[post("/events/multiple", format = "json", data = "<event_ids>")]
 pub async fn get_multiple_events(
     event_ids: Json<Vec<String>>, // Accepts a JSON array of event IDs
     db: &State<Collection<Event>>,
-    _token: AuthToken, // verfiy blacklisted tokens
-    _user: AuthenticatedUser // verity authenticated user     
-) -> Result<Json<Vec<Event>>, Status> {
+    _caller: &AuthContext, // signed in users only
+) -> Result<Json<Vec<PublicEvent>>, Status> {
     // Convert string IDs to ObjectId
     let object_ids: Vec<ObjectId> = event_ids
         .iter()
@@ -352,7 +776,7 @@ pub async fn get_multiple_events(
     let mut events = Vec::new();
     while let Some(result) = cursor.next().await {
         match result {
-            Ok(event) => events.push(event),
+            Ok(event) => events.push(PublicEvent::from(event)),
             Err(_) => return Err(Status::InternalServerError),
         }
     }
@@ -360,118 +784,32 @@ pub async fn get_multiple_events(
     Ok(Json(events))
 }
 
-#[derive(Deserialize)]
-struct UpdatePinnedRequest {
-    pinned: bool
-}
-
-#[put("/events/<event_id>/pinned", format = "json", data = "<pinned_update>")]
-pub async fn update_pinned(
-    event_id: String,
-    pinned_update: Json<UpdatePinnedRequest>,
-    db: &State<Collection<Event>>, // Directly take Collection<Event> from state
-) -> Result<Json<&'static str>, rocket::response::status::Custom<String>> {
-    let event_id = match ObjectId::parse_str(&event_id) {
-        Ok(id) => id,
-        Err(_) => return Err(rocket::response::status::Custom(
-            rocket::http::Status::BadRequest,
-            "Invalid Event ID".to_string(),
-        )),
-    };
-
-    // Start an atomic update (if transactions are enabled in MongoDB)
-    let session = db.client().start_session().await.ok();
-
-    if let Some(mut session) = session {
-        session.start_transaction().await.ok();
-
-        // Step 1: Unpin all other events
-        let unpin_result = db
-            .update_many(doc! { "pinned": true }, doc! { "$set": { "pinned": false } })
-            .await;
-
-        if let Err(err) = unpin_result {
-            session.abort_transaction().await.ok();
-            return Err(rocket::response::status::Custom(
-                rocket::http::Status::InternalServerError,
-                format!("Error unpinning other events: {}", err),
-            ));
-        }
-
-        // Step 2: Pin the target event
-        let pin_result = db
-            .update_one(
-                doc! { "_id": event_id },
-                doc! { "$set": { "pinned": pinned_update.pinned } }
-            )
-            .await;
-
-        match pin_result {
-            Ok(res) => {
-                if res.matched_count == 0 {
-                    session.abort_transaction().await.ok();
-                    return Err(rocket::response::status::Custom(
-                        rocket::http::Status::NotFound,
-                        "Event not found".to_string(),
-                    ));
-                }
-
-                session.commit_transaction().await.ok();
-                Ok(Json("Pinned status updated successfully"))
-            }
-            Err(err) => {
-                session.abort_transaction().await.ok();
-                Err(rocket::response::status::Custom(
-                    rocket::http::Status::InternalServerError,
-                    format!("Database error: {}", err),
-                ))
-            }
-        }
-    } else {
-        // If transactions are not available, perform sequential updates
-        db.update_many(doc! { "pinned": true }, doc! { "$set": { "pinned": false } })
-            .await
-            .ok();
-
-        let update_result = db
-            .update_one(
-                doc! { "_id": event_id },
-                doc! { "$set": { "pinned": pinned_update.pinned } },
-            )
-            .await;
-
-        match update_result {
-            Ok(res) => {
-                if res.matched_count == 0 {
-                    Err(rocket::response::status::Custom(
-                        rocket::http::Status::NotFound,
-                        "Event not found".to_string(),
-                    ))
-                } else {
-                    Ok(Json("Pinned status updated successfully"))
-                }
-            }
-            Err(err) => Err(rocket::response::status::Custom(
-                rocket::http::Status::InternalServerError,
-                format!("Database error: {}", err),
-            )),
-        }
-    }
-}
-
-
 #[delete("/events")]
 pub async fn delete_all_events(
     database: &State<Collection<Event>>,
-    _admin: AdminUser, // only admin can call this
-    _token: AuthToken,  // verify blacklisted tokens
-    _user: AuthenticatedUser, // verify authenticated user
+    storage: &State<Storage>,
+    admin: Authorized<perm::EventDelete>, // only core team can call this
+    audit: Audit<'_>,
 ) -> Json<String> {
+    // the stored images go too, read them before the events are gone
+    let images: Result<Vec<Event>, _> = match database.find(doc! {"image": {"$exists": true}}).await {
+        Ok(cursor) => cursor.try_collect().await,
+        Err(e) => Err(e),
+    };
+    let Ok(images) = images else {
+        return Json("Failed to delete events.".to_string());
+    };
+
     // Delete all events in the collection
     let result = database.delete_many(doc! {},).await;
 
     match result {
         Ok(delete_result) => {
+            for image in images.iter().filter_map(|event| event.image.as_ref()) {
+                delete_variants(image, storage).await;
+            }
+            let after = doc! {"deleted_count": delete_result.deleted_count as i64};
+            audit.record(&admin.email, "event.delete_all", vec![], None, Some(after)).await;
             if delete_result.deleted_count > 0 {
                 Json("All events successfully deleted.".to_string())
             } else {
@@ -487,24 +825,127 @@ pub async fn read_upcoming_events(
     Database: &State<Collection<Event>>,
     // _token: AuthToken,
     // _user: AuthenticatedUser,
-) -> Json<Vec<Event>> {
-    let now_chrono = Utc::now();
-    let now_bson = BsonDateTime::from_system_time(now_chrono.into());
-
+) -> Result<Json<Vec<PublicEvent>>, Status> {
+    // dates are stored as fixed format strings, see stored_date
+    let now = stored_date(&Utc::now());
 
     let filter = doc! {
-        "date": { "$gte": now_bson }
+        "date": { "$gte": now }
     };
 
-    let mut cursor = Database
+    let events: Vec<Event> = Database
         .find(filter)
+        .sort(doc! {"date": 1})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .try_collect()
         .await
-        .expect("Failed to fetch upcoming events");
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(public_events(events)))
+}
+
+// an event as anyone allowed to read events sees it. Attendees and the waitlist carry names
+// and emails, so only their sizes are shown; organizers get the lists from read_event_attendees.
+#[derive(Serialize)]
+pub struct PublicEvent {
+    pub id: String,
+    pub name: String,
+    pub location: String,
+    pub description: String,
+    pub event_type: EventType,
+    #[serde(with = "crate::models::event::stored_date")]
+    pub date: DateTime<Utc>,
+    #[serde(with = "crate::models::event::stored_date::option")]
+    pub ends_at: Option<DateTime<Utc>>,
+    pub time_zone: String,
+    #[serde(with = "crate::models::event::stored_date::option")]
+    pub registration_opens_at: Option<DateTime<Utc>>,
+    #[serde(with = "crate::models::event::stored_date::option")]
+    pub registration_closes_at: Option<DateTime<Utc>>,
+    pub host_id: Option<String>,
+    pub capacity: Option<i32>,
+    pub attending: usize,
+    pub waitlisted: usize,
+    pub image_url: Option<String>,
+    pub image: Option<EventImage>,
+    pub sequence: i32,
+    pub series_id: Option<String>,
+    #[serde(with = "crate::models::event::stored_date::option")]
+    pub cancelled_at: Option<DateTime<Utc>>,
+}
 
-    let mut events: Vec<Event> = Vec::new();
-    while let Some(event) = cursor.try_next().await.expect("Cursor error") {
-        events.push(event);
+impl From<Event> for PublicEvent {
+    fn from(event: Event) -> Self {
+        PublicEvent {
+            id: event.id.map(|id| id.to_hex()).unwrap_or_default(),
+            name: event.name,
+            location: event.location,
+            description: event.description,
+            event_type: event.event_type,
+            date: event.date,
+            ends_at: event.ends_at,
+            time_zone: event.time_zone,
+            registration_opens_at: event.registration_opens_at,
+            registration_closes_at: event.registration_closes_at,
+            host_id: event.host_id.map(|id| id.to_hex()),
+            capacity: event.capacity,
+            attending: event.attendees.len(),
+            waitlisted: event.waitlist.len(),
+            image_url: event.image_url,
+            image: event.image,
+            sequence: event.sequence,
+            series_id: event.series_id.map(|id| id.to_hex()),
+            cancelled_at: event.cancelled_at,
+        }
     }
+}
 
-    Json(events)
-}
\ No newline at end of file
+pub fn public_events(events: Vec<Event>) -> Vec<PublicEvent> {
+    events.into_iter().map(PublicEvent::from).collect()
+}
+
+#[derive(Serialize)]
+pub struct EventAttendees {
+    pub event_id: String,
+    pub attendees: Vec<Attendee>,
+    pub waitlist: Vec<Attendee>, // in promotion order
+}
+
+// who holds a seat and who is waiting, with contact details, for the organizers
+#[get("/event/<event_id>/attendees")]
+pub async fn read_event_attendees(
+    event_id: &str,
+    db: &State<Collection<Event>>,
+    _admin: Authorized<perm::EventUpdate>, // only organizers and core team can call this
+) -> Result<Json<EventAttendees>, Status> {
+    let event_oid = ObjectId::parse_str(event_id).map_err(|_| Status::BadRequest)?;
+    let event = db
+        .find_one(doc! {"_id": event_oid})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+    Ok(Json(EventAttendees {
+        event_id: event_oid.to_hex(),
+        attendees: event.attendees,
+        waitlist: event.waitlist,
+    }))
+}
+
+// every event in one response for the website build, cancelled ones included so pages can say so
+#[get("/events/export")]
+pub async fn export_events(
+    db: &State<Collection<Event>>,
+    _caller: ScopedCaller<scope::Export>, // api key with export or a user with EventUpdate
+) -> Result<Json<Vec<PublicEvent>>, Status> {
+    let events: Vec<Event> = db
+        .find(doc! {})
+        .sort(doc! {"date": 1, "_id": 1})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .try_collect()
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(public_events(events)))
+}
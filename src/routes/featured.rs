@@ -0,0 +1,431 @@
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use mongodb::bson::{self, doc, oid::ObjectId};
+use mongodb::options::ReturnDocument;
+use mongodb::Collection;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, put, State};
+use serde::{Deserialize, Serialize};
+
+use crate::models::featured::FEATURED_ID;
+use crate::models::{Event, FeaturedEvents, FeaturedSlot};
+use super::audit::{snapshot, Audit};
+use super::auth::{perm, Authorized};
+use super::event::PublicEvent;
+
+pub const MAX_FEATURED: usize = 10;
+const MAX_ATTEMPTS: usize = 3; // for single event changes racing another writer
+
+#[derive(Serialize)]
+pub struct FeaturedEvent {
+    pub position: usize, // 1 based, in display order
+    pub until: Option<DateTime<Utc>>,
+    pub event: PublicEvent,
+}
+
+#[derive(Serialize)]
+pub struct FeaturedList {
+    pub events: Vec<FeaturedEvent>,
+    pub version: i64, // send back with PUT /events/featured
+}
+
+#[derive(Deserialize)]
+pub struct FeaturedSlotRequest {
+    pub event_id: String,
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+}
+
+// the whole list in display order, replaces whatever is featured now
+#[derive(Deserialize)]
+pub struct FeaturedListRequest {
+    pub slots: Vec<FeaturedSlotRequest>,
+    #[serde(default)]
+    pub version: Option<i64>, // from GET /events/featured, the change is refused if the list moved on since
+}
+
+#[derive(Deserialize)]
+pub struct FeatureEventRequest {
+    #[serde(default)]
+    pub position: Option<usize>, // 1 based, appended when unset
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+}
+
+fn bad_request(msg: impl Into<String>) -> status::Custom<String> {
+    status::Custom(Status::BadRequest, msg.into())
+}
+
+fn server_error() -> status::Custom<String> {
+    status::Custom(Status::InternalServerError, String::new())
+}
+
+fn conflict() -> status::Custom<String> {
+    status::Custom(Status::Conflict, "The featured events were changed by someone else, reload and try again.".to_string())
+}
+
+// the featured list, seeded by migrations::featured_events at startup
+pub(crate) async fn load(featured: &Collection<FeaturedEvents>) -> Result<FeaturedEvents, Status> {
+    match featured.find_one(doc! {"_id": FEATURED_ID}).await {
+        Ok(Some(list)) => Ok(list),
+        Ok(None) => Err(Status::InternalServerError),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+// ids of the events featured right now, in display order
+pub(crate) fn active_ids(list: &FeaturedEvents) -> Vec<ObjectId> {
+    let now = Utc::now();
+    list.slots.iter().filter(|slot| slot.active(now)).map(|slot| slot.event_id).collect()
+}
+
+fn active_slots(slots: Vec<FeaturedSlot>, now: DateTime<Utc>) -> Vec<FeaturedSlot> {
+    slots.into_iter().filter(|slot| slot.active(now)).collect()
+}
+
+// writes the new slots in one update, only if nobody else wrote since `expected` was read.
+// Expired slots are dropped on the way.
+async fn save(
+    featured: &Collection<FeaturedEvents>,
+    expected: i64,
+    slots: Vec<FeaturedSlot>,
+    by: &str,
+) -> Result<Option<FeaturedEvents>, status::Custom<String>> {
+    let now = Utc::now();
+    let slots = active_slots(slots, now);
+    let update_doc = doc! {
+        "$set": {
+            "slots": bson::to_bson(&slots).map_err(|_| server_error())?,
+            "updated_by": by,
+            "updated_at": bson::to_bson(&now).map_err(|_| server_error())?,
+        },
+        "$inc": {"version": 1_i64},
+    };
+    featured
+        .find_one_and_update(doc! {"_id": FEATURED_ID, "version": expected}, update_doc)
+        .return_document(ReturnDocument::After)
+        .await
+        .map_err(|_| server_error())
+}
+
+async fn existing_events(ids: &[ObjectId], db: &Collection<Event>) -> Result<Vec<Event>, Status> {
+    db.find(doc! {"_id": {"$in": ids}})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .try_collect()
+        .await
+        .map_err(|_| Status::InternalServerError)
+}
+
+// pairs the active slots with their events, slots of deleted events are skipped
+fn in_order(list: &FeaturedEvents, mut events: Vec<Event>) -> Vec<FeaturedEvent> {
+    let now = Utc::now();
+    let mut featured_events = Vec::new();
+    for slot in list.slots.iter().filter(|slot| slot.active(now)) {
+        if let Some(index) = events.iter().position(|event| event.id == Some(slot.event_id)) {
+            featured_events.push(FeaturedEvent {
+                position: featured_events.len() + 1,
+                until: slot.until,
+                event: events.swap_remove(index).into(),
+            });
+        }
+    }
+    featured_events
+}
+
+fn check_until(until: Option<DateTime<Utc>>) -> Result<(), status::Custom<String>> {
+    match until {
+        Some(until) if until <= Utc::now() => Err(bad_request("until has to be in the future.")),
+        _ => Ok(()),
+    }
+}
+
+// the slots with the event added at its position, or moved and given the new expiry when it's
+// already featured. Expired slots and slots of deleted events are dropped first.
+fn place(
+    slots: &[FeaturedSlot],
+    existing_ids: &[ObjectId],
+    event_oid: ObjectId,
+    request: &FeatureEventRequest,
+    by: &str,
+    now: DateTime<Utc>,
+) -> Result<Vec<FeaturedSlot>, status::Custom<String>> {
+    // slots of deleted events don't count towards the limit
+    let mut slots: Vec<FeaturedSlot> = slots
+        .iter()
+        .filter(|slot| slot.active(now) && existing_ids.contains(&slot.event_id))
+        .cloned()
+        .collect();
+    // an event that's already featured keeps its place unless a position is given
+    let (slot, current) = match slots.iter().position(|slot| slot.event_id == event_oid) {
+        Some(index) => (FeaturedSlot { until: request.until, ..slots.remove(index) }, index),
+        None => (FeaturedSlot { event_id: event_oid, until: request.until, featured_by: by.to_string(), featured_at: now }, slots.len()),
+    };
+    if slots.len() >= MAX_FEATURED {
+        return Err(status::Custom(Status::Conflict, format!("At most {} events can be featured at once.", MAX_FEATURED)));
+    }
+    let index = request.position.map(|position| position - 1).unwrap_or(current).min(slots.len());
+    slots.insert(index, slot);
+    Ok(slots)
+}
+
+// adds the event, or moves it and updates its expiry when it's already featured
+pub(crate) async fn feature(
+    event_oid: ObjectId,
+    request: &FeatureEventRequest,
+    featured: &Collection<FeaturedEvents>,
+    db: &Collection<Event>,
+    by: &str,
+) -> Result<(FeaturedEvents, FeaturedEvents), status::Custom<String>> {
+    check_until(request.until)?;
+    if request.position == Some(0) {
+        return Err(bad_request("position starts at 1."));
+    }
+    match db.count_documents(doc! {"_id": event_oid}).await {
+        Ok(0) => return Err(status::Custom(Status::NotFound, "Event not found.".to_string())),
+        Ok(_) => {}
+        Err(_) => return Err(server_error()),
+    }
+
+    for _ in 0..MAX_ATTEMPTS {
+        let before = load(featured).await.map_err(|status| status::Custom(status, String::new()))?;
+        let ids: Vec<ObjectId> = before.slots.iter().map(|slot| slot.event_id).collect();
+        let existing = existing_events(&ids, db).await.map_err(|status| status::Custom(status, String::new()))?;
+        let existing_ids: Vec<ObjectId> = existing.iter().filter_map(|event| event.id).collect();
+        let slots = place(&before.slots, &existing_ids, event_oid, request, by, Utc::now())?;
+
+        if let Some(after) = save(featured, before.version, slots, by).await? {
+            return Ok((before, after));
+        }
+    }
+    Err(conflict())
+}
+
+// takes the event off the list; a single $pull, nothing to race with
+pub(crate) async fn unfeature(
+    event_oid: ObjectId,
+    featured: &Collection<FeaturedEvents>,
+    by: &str,
+) -> Result<(FeaturedEvents, FeaturedEvents), status::Custom<String>> {
+    let update_doc = doc! {
+        "$pull": {"slots": {"event_id": event_oid}},
+        "$inc": {"version": 1_i64},
+        "$set": {"updated_by": by, "updated_at": bson::to_bson(&Utc::now()).map_err(|_| server_error())?},
+    };
+    let before = featured
+        .find_one_and_update(doc! {"_id": FEATURED_ID, "slots.event_id": event_oid}, update_doc)
+        .await
+        .map_err(|_| server_error())?
+        .ok_or_else(|| status::Custom(Status::NotFound, "The event isn't featured.".to_string()))?;
+    let mut after = before.clone();
+    after.slots.retain(|slot| slot.event_id != event_oid);
+    after.version += 1;
+    Ok((before, after))
+}
+
+// featured events in display order, without expired slots or deleted events. Public like GET /events/upcoming.
+#[get("/events/featured")]
+pub async fn read_featured_events(
+    featured: &State<Collection<FeaturedEvents>>,
+    db: &State<Collection<Event>>,
+) -> Result<Json<FeaturedList>, Status> {
+    let list = load(featured).await?;
+    let events = existing_events(&active_ids(&list), db).await?;
+    Ok(Json(FeaturedList { events: in_order(&list, events), version: list.version }))
+}
+
+#[put("/events/featured", format = "json", data = "<request>")]
+pub async fn update_featured_events(
+    request: Json<FeaturedListRequest>,
+    featured: &State<Collection<FeaturedEvents>>,
+    db: &State<Collection<Event>>,
+    admin: Authorized<perm::EventFeature>, // only super admins can call this
+    audit: Audit<'_>,
+) -> Result<Json<FeaturedList>, status::Custom<String>> {
+    if request.slots.len() > MAX_FEATURED {
+        return Err(bad_request(format!("At most {} events can be featured at once.", MAX_FEATURED)));
+    }
+    let mut ids = Vec::new();
+    for slot in &request.slots {
+        let event_oid = ObjectId::parse_str(&slot.event_id).map_err(|_| bad_request(format!("Invalid event id {}.", slot.event_id)))?;
+        if ids.contains(&event_oid) {
+            return Err(bad_request(format!("Event {} is listed twice.", slot.event_id)));
+        }
+        check_until(slot.until)?;
+        ids.push(event_oid);
+    }
+    let events = existing_events(&ids, db).await.map_err(|status| status::Custom(status, String::new()))?;
+    if let Some(missing) = ids.iter().find(|id| !events.iter().any(|event| event.id == Some(**id))) {
+        return Err(status::Custom(Status::NotFound, format!("Event {} not found.", missing.to_hex())));
+    }
+
+    let before = load(featured).await.map_err(|status| status::Custom(status, String::new()))?;
+    let expected = request.version.unwrap_or(before.version);
+    let now = Utc::now();
+    // events that stay featured keep who featured them and when
+    let slots = ids
+        .iter()
+        .zip(&request.slots)
+        .map(|(event_oid, slot)| match before.slots.iter().find(|old| old.event_id == *event_oid) {
+            Some(old) => FeaturedSlot { until: slot.until, ..old.clone() },
+            None => FeaturedSlot { event_id: *event_oid, until: slot.until, featured_by: admin.email.clone(), featured_at: now },
+        })
+        .collect();
+    let after = save(featured, expected, slots, &admin.email).await?.ok_or_else(conflict)?;
+
+    audit.record(&admin.email, "event.featured_update", ids.iter().map(|id| id.to_hex()).collect(), snapshot(&before), snapshot(&after)).await;
+    Ok(Json(FeaturedList { events: in_order(&after, events), version: after.version }))
+}
+
+#[put("/events/<event_id>/featured", format = "json", data = "<request>")]
+pub async fn feature_event(
+    event_id: &str,
+    request: Json<FeatureEventRequest>,
+    featured: &State<Collection<FeaturedEvents>>,
+    db: &State<Collection<Event>>,
+    admin: Authorized<perm::EventFeature>, // only super admins can call this
+    audit: Audit<'_>,
+) -> Result<Json<String>, status::Custom<String>> {
+    let event_oid = ObjectId::parse_str(event_id).map_err(|_| bad_request("Invalid event id."))?;
+    let (before, after) = feature(event_oid, &request, featured, db, &admin.email).await?;
+    audit.record(&admin.email, "event.feature", vec![event_oid.to_hex()], snapshot(&before), snapshot(&after)).await;
+    Ok(Json("Event featured.".to_string()))
+}
+
+#[delete("/events/<event_id>/featured")]
+pub async fn unfeature_event(
+    event_id: &str,
+    featured: &State<Collection<FeaturedEvents>>,
+    admin: Authorized<perm::EventFeature>, // only super admins can call this
+    audit: Audit<'_>,
+) -> Result<Json<String>, status::Custom<String>> {
+    let event_oid = ObjectId::parse_str(event_id).map_err(|_| bad_request("Invalid event id."))?;
+    let (before, after) = unfeature(event_oid, featured, &admin.email).await?;
+    audit.record(&admin.email, "event.unfeature", vec![event_oid.to_hex()], snapshot(&before), snapshot(&after)).await;
+    Ok(Json("Event no longer featured.".to_string()))
+}
+
+#[derive(Deserialize)]
+pub struct UpdatePinnedRequest {
+    pinned: bool,
+}
+
+// the old single pin, kept for older clients: pinning appends to the featured events, unpinning removes it
+#[put("/events/<event_id>/pinned", format = "json", data = "<pinned_update>")]
+pub async fn update_pinned(
+    event_id: &str,
+    pinned_update: Json<UpdatePinnedRequest>,
+    featured: &State<Collection<FeaturedEvents>>,
+    db: &State<Collection<Event>>,
+    admin: Authorized<perm::EventFeature>, // only super admins can call this
+    audit: Audit<'_>,
+) -> Result<Json<&'static str>, status::Custom<String>> {
+    let event_oid = ObjectId::parse_str(event_id).map_err(|_| bad_request("Invalid Event ID"))?;
+    let result = if pinned_update.pinned {
+        let request = FeatureEventRequest { position: None, until: None };
+        feature(event_oid, &request, featured, db, &admin.email).await
+    } else {
+        unfeature(event_oid, featured, &admin.email).await
+    };
+    let (before, after) = match result {
+        Ok(changed) => changed,
+        // unpinning something that isn't pinned is fine
+        Err(error) if !pinned_update.pinned && error.0 == Status::NotFound => return Ok(Json("Pinned status updated successfully")),
+        Err(error) => return Err(error),
+    };
+    audit.record(&admin.email, "event.pin", vec![event_oid.to_hex()], snapshot(&before), snapshot(&after)).await;
+    Ok(Json("Pinned status updated successfully"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn slot(event_id: ObjectId, until: Option<DateTime<Utc>>) -> FeaturedSlot {
+        FeaturedSlot { event_id, until, featured_by: "admin@example.org".to_string(), featured_at: Utc::now() - Duration::days(1) }
+    }
+
+    fn request(position: Option<usize>, until: Option<DateTime<Utc>>) -> FeatureEventRequest {
+        FeatureEventRequest { position, until }
+    }
+
+    fn order(slots: &[FeaturedSlot]) -> Vec<ObjectId> {
+        slots.iter().map(|slot| slot.event_id).collect()
+    }
+
+    #[test]
+    fn inserts_at_the_requested_position() {
+        let now = Utc::now();
+        let (a, b, new) = (ObjectId::new(), ObjectId::new(), ObjectId::new());
+        let slots = vec![slot(a, None), slot(b, None)];
+
+        let appended = place(&slots, &[a, b], new, &request(None, None), "me", now).unwrap();
+        assert_eq!(order(&appended), vec![a, b, new]);
+        assert_eq!(appended[2].featured_by, "me");
+
+        let first = place(&slots, &[a, b], new, &request(Some(1), None), "me", now).unwrap();
+        assert_eq!(order(&first), vec![new, a, b]);
+
+        // positions past the end append
+        let far = place(&slots, &[a, b], new, &request(Some(9), None), "me", now).unwrap();
+        assert_eq!(order(&far), vec![a, b, new]);
+    }
+
+    #[test]
+    fn moves_an_event_that_is_already_featured() {
+        let now = Utc::now();
+        let (a, b, c) = (ObjectId::new(), ObjectId::new(), ObjectId::new());
+        let slots = vec![slot(a, None), slot(b, None), slot(c, None)];
+
+        let moved = place(&slots, &[a, b, c], c, &request(Some(1), None), "me", now).unwrap();
+        assert_eq!(order(&moved), vec![c, a, b]);
+        // it stays the same slot, only the expiry follows the request
+        assert_eq!(moved[0].featured_by, "admin@example.org");
+
+        let until = now + Duration::days(3);
+        let kept = place(&slots, &[a, b, c], b, &request(None, Some(until)), "me", now).unwrap();
+        assert_eq!(order(&kept), vec![a, b, c]);
+        assert_eq!(kept[1].until, Some(until));
+    }
+
+    #[test]
+    fn drops_expired_slots_and_deleted_events() {
+        let now = Utc::now();
+        let (expired, deleted, live, new) = (ObjectId::new(), ObjectId::new(), ObjectId::new(), ObjectId::new());
+        let slots = vec![
+            slot(expired, Some(now - Duration::minutes(1))),
+            slot(deleted, None),
+            slot(live, Some(now + Duration::days(1))),
+        ];
+
+        let placed = place(&slots, &[expired, live], new, &request(None, None), "me", now).unwrap();
+        assert_eq!(order(&placed), vec![live, new]);
+
+        let saved = active_slots(slots, now);
+        assert_eq!(order(&saved), vec![deleted, live]);
+    }
+
+    #[test]
+    fn refuses_more_than_max_featured() {
+        let now = Utc::now();
+        let ids: Vec<ObjectId> = (0..MAX_FEATURED).map(|_| ObjectId::new()).collect();
+        let slots: Vec<FeaturedSlot> = ids.iter().map(|id| slot(*id, None)).collect();
+
+        let full = place(&slots, &ids, ObjectId::new(), &request(None, None), "me", now);
+        assert_eq!(full.unwrap_err().0, Status::Conflict);
+
+        // moving one of them around is still fine
+        let moved = place(&slots, &ids, ids[MAX_FEATURED - 1], &request(Some(1), None), "me", now).unwrap();
+        assert_eq!(moved[0].event_id, ids[MAX_FEATURED - 1]);
+
+        // an expired slot frees its place
+        let mut with_expired = slots.clone();
+        with_expired[0].until = Some(now - Duration::minutes(1));
+        let placed = place(&with_expired, &ids, ObjectId::new(), &request(None, None), "me", now).unwrap();
+        assert_eq!(placed.len(), MAX_FEATURED);
+    }
+}
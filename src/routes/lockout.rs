@@ -0,0 +1,189 @@
+use std::env;
+use std::net::IpAddr;
+
+use dotenv::dotenv;
+
+use mongodb::bson::{doc, oid::ObjectId, DateTime as BsonDateTime};
+use mongodb::options::ReturnDocument;
+use mongodb::Collection;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+
+use crate::models::{LoginThrottle, User};
+use super::auth::{perm, Authorized};
+use super::audit::Audit;
+
+const FAILURE_WINDOW_MS: i64 = 60 * 60 * 1000; // counters reset after an hour without failures
+const MAX_LOCKOUT_MS: i64 = 15 * 60 * 1000;
+const ACCOUNT_FREE_ATTEMPTS: i32 = 3;
+const IP_FREE_ATTEMPTS: i32 = 20; // shared networks (campus wifi) produce a lot of failures per ip
+
+pub fn account_key(email: &str) -> String {
+    format!("email:{}", email.trim().to_lowercase())
+}
+
+pub fn ip_key(ip: &IpAddr) -> String {
+    format!("ip:{}", ip)
+}
+
+// where failures are counted per ip from. Behind a reverse proxy the peer is the proxy, so
+// without knowing which peers are proxies every client would share one counter.
+pub struct TrustedProxies {
+    proxies: Option<Vec<IpAddr>>, // None: not configured, failures aren't counted per ip
+}
+
+impl TrustedProxies {
+    // TRUSTED_PROXIES is a comma separated list of proxy addresses, or "none" when clients
+    // connect directly. Unset, only the per account lockout applies.
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let proxies = env::var("TRUSTED_PROXIES").ok().map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|proxy| !proxy.is_empty() && !proxy.eq_ignore_ascii_case("none"))
+                .map(|proxy| proxy.parse().expect("TRUSTED_PROXIES entries must be ip addresses"))
+                .collect()
+        });
+        TrustedProxies { proxies }
+    }
+
+    // the peer, or if that is a trusted proxy the last X-Forwarded-For hop that isn't one.
+    // Hops left of it were added by the client and can say anything.
+    pub fn client_ip(&self, peer: Option<IpAddr>, forwarded_for: Option<&str>) -> Option<IpAddr> {
+        let proxies = self.proxies.as_ref()?;
+        let mut ip = peer?;
+        let mut hops = forwarded_for.unwrap_or_default().rsplit(',').map(str::trim);
+        while proxies.contains(&ip) {
+            ip = hops.next()?.parse().ok()?;
+        }
+        Some(ip)
+    }
+}
+
+// doubles from one second after the free attempts are used up, capped at MAX_LOCKOUT_MS
+fn lockout_ms(failures: i32, free_attempts: i32) -> i64 {
+    if failures < free_attempts {
+        return 0;
+    }
+    let exponent = (failures - free_attempts).min(20) as u32;
+    (1000_i64 << exponent).min(MAX_LOCKOUT_MS)
+}
+
+// returns the end of the lock if any of the keys is currently locked
+pub async fn locked_until(keys: &[String], db: &Collection<LoginThrottle>) -> Result<Option<BsonDateTime>, mongodb::error::Error> {
+    let now = BsonDateTime::now();
+    let filter = doc! {"key": {"$in": keys}, "locked_until": {"$gt": now}};
+    Ok(db.find_one(filter).await?.and_then(|throttle| throttle.locked_until))
+}
+
+pub async fn record_failure(key: &str, free_attempts: i32, db: &Collection<LoginThrottle>) -> Result<(), mongodb::error::Error> {
+    let now = BsonDateTime::now();
+    let update = doc! {
+        "$inc": {"failures": 1},
+        "$set": {
+            "last_failure_at": now,
+            "expires_at": BsonDateTime::from_millis(now.timestamp_millis() + FAILURE_WINDOW_MS),
+        }
+    };
+    let throttle = db
+        .find_one_and_update(doc! {"key": key}, update)
+        .upsert(true)
+        .return_document(ReturnDocument::After)
+        .await?;
+
+    if let Some(throttle) = throttle {
+        let lock = lockout_ms(throttle.failures, free_attempts);
+        if lock > 0 {
+            let locked_until = BsonDateTime::from_millis(now.timestamp_millis() + lock);
+            db.update_one(doc! {"key": key}, doc! {"$set": {"locked_until": locked_until}}).await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn record_login_failure(email: &str, ip: Option<IpAddr>, db: &Collection<LoginThrottle>) -> Result<(), mongodb::error::Error> {
+    record_failure(&account_key(email), ACCOUNT_FREE_ATTEMPTS, db).await?;
+    if let Some(ip) = ip {
+        record_failure(&ip_key(&ip), IP_FREE_ATTEMPTS, db).await?;
+    }
+    Ok(())
+}
+
+pub async fn clear(key: &str, db: &Collection<LoginThrottle>) -> Result<(), mongodb::error::Error> {
+    db.delete_many(doc! {"key": key}).await?;
+    Ok(())
+}
+
+#[delete("/user/<id>/lockout")]
+pub async fn unlock_user(
+    id: &str,
+    user_db: &State<Collection<User>>,
+    throttle_db: &State<Collection<LoginThrottle>>,
+    admin: Authorized<perm::UserManage>, // only super admins can call this
+    audit: Audit<'_>,
+) -> Result<Json<String>, Status> {
+    let object_id = ObjectId::parse_str(id).map_err(|_| Status::BadRequest)?;
+    let user = user_db
+        .find_one(doc! {"_id": object_id})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    clear(&account_key(&user.email), throttle_db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    audit.record(&admin.email, "user.unlock", vec![object_id.to_hex()], None, None).await;
+    Ok(Json("User successfully unlocked".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies(proxies: &[&str]) -> TrustedProxies {
+        TrustedProxies { proxies: Some(proxies.iter().map(|proxy| proxy.parse().unwrap()).collect()) }
+    }
+
+    fn ip(value: &str) -> Option<IpAddr> {
+        Some(value.parse().unwrap())
+    }
+
+    #[test]
+    fn unconfigured_proxies_disable_ip_keys() {
+        let unset = TrustedProxies { proxies: None };
+        assert_eq!(unset.client_ip(ip("10.0.0.1"), Some("203.0.113.7")), None);
+    }
+
+    #[test]
+    fn direct_clients_are_their_peer() {
+        let direct = proxies(&[]);
+        assert_eq!(direct.client_ip(ip("203.0.113.7"), Some("198.51.100.1")), ip("203.0.113.7"));
+        // a client claiming to be forwarded isn't believed unless it connects from a proxy
+        assert_eq!(proxies(&["10.0.0.1"]).client_ip(ip("203.0.113.7"), Some("198.51.100.1")), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn takes_the_hop_the_proxy_added() {
+        let proxy = proxies(&["10.0.0.1"]);
+        assert_eq!(proxy.client_ip(ip("10.0.0.1"), Some("203.0.113.7")), ip("203.0.113.7"));
+        // the client sent its own X-Forwarded-For, the proxy appended the real address
+        assert_eq!(proxy.client_ip(ip("10.0.0.1"), Some("198.51.100.1, 203.0.113.7")), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn skips_every_trusted_hop() {
+        let chain = proxies(&["10.0.0.1", "10.0.0.2"]);
+        assert_eq!(chain.client_ip(ip("10.0.0.1"), Some("198.51.100.1, 203.0.113.7, 10.0.0.2")), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn missing_or_malformed_hops_give_no_ip() {
+        let proxy = proxies(&["10.0.0.1"]);
+        assert_eq!(proxy.client_ip(ip("10.0.0.1"), None), None);
+        assert_eq!(proxy.client_ip(ip("10.0.0.1"), Some("unknown")), None);
+        assert_eq!(proxy.client_ip(None, Some("203.0.113.7")), None);
+    }
+}
@@ -0,0 +1,277 @@
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use chrono::Duration;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageReader};
+use mongodb::bson::{self, doc, oid::ObjectId, Bson};
+use mongodb::Collection;
+use rocket::data::ToByteUnit;
+use rocket::form::Form;
+use rocket::fs::TempFile;
+use rocket::http::{ContentType, Status};
+use rocket::response::{status, Redirect};
+use rocket::serde::json::Json;
+use rocket::tokio::io::AsyncReadExt;
+use rocket::{delete, get, post, State};
+
+use crate::models::event::{EventImage, ImageVariant};
+use crate::models::Event;
+use crate::storage::Storage;
+use super::audit::{snapshot, Audit};
+use super::auth::{perm, random_token, Authorized};
+
+pub const MAX_IMAGE_BYTES: u64 = 5 * 1024 * 1024;
+const MAX_DIMENSION: u32 = 8000; // pixels per side, keeps decoding memory bounded
+const SIGNED_URL_TTL: i64 = 60 * 60; // 1 hour
+const JPEG_QUALITY: u8 = 85;
+
+// longest side of each rendered size, thumb is cropped to a square
+const VARIANTS: [(&str, u32); 3] = [("large", 1600), ("medium", 800), ("thumb", 320)];
+
+#[derive(FromForm)]
+pub struct ImageUpload<'r> {
+    file: TempFile<'r>,
+}
+
+// the limits rocket applies while reading the form, a bit of headroom for the other parts
+pub fn upload_limits() -> rocket::data::Limits {
+    rocket::data::Limits::default()
+        .limit("file", MAX_IMAGE_BYTES.bytes())
+        .limit("data-form", (MAX_IMAGE_BYTES + 64 * 1024).bytes())
+}
+
+fn unsupported() -> status::Custom<String> {
+    status::Custom(Status::UnsupportedMediaType, "Only JPEG, PNG and WebP images are accepted.".to_string())
+}
+
+fn server_error() -> status::Custom<String> {
+    status::Custom(Status::InternalServerError, String::new())
+}
+
+fn image_format(content_type: &ContentType) -> Option<ImageFormat> {
+    match (content_type.top().as_str(), content_type.sub().as_str()) {
+        ("image", "jpeg") => Some(ImageFormat::Jpeg),
+        ("image", "png") => Some(ImageFormat::Png),
+        ("image", "webp") => Some(ImageFormat::WebP),
+        _ => None,
+    }
+}
+
+struct Rendered {
+    name: &'static str,
+    bytes: Vec<u8>,
+    content_type: &'static str,
+    extension: &'static str,
+    width: u32,
+    height: u32,
+}
+
+// decodes the upload and renders the smaller sizes. Images with transparency stay PNG,
+// everything else becomes JPEG. CPU bound, runs on the blocking pool.
+fn render_variants(bytes: &[u8], format: ImageFormat) -> Result<(DynamicImage, Vec<Rendered>), String> {
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    let mut limits = image::Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    reader.limits(limits);
+    let original = reader.decode().map_err(|_| "The image could not be read.".to_string())?;
+
+    let mut rendered = Vec::new();
+    for (name, size) in VARIANTS {
+        let image = if name == "thumb" {
+            original.resize_to_fill(size, size, FilterType::Triangle)
+        } else if original.width() > size || original.height() > size {
+            original.resize(size, size, FilterType::Lanczos3)
+        } else {
+            original.clone()
+        };
+
+        let mut out = Vec::new();
+        let (content_type, extension) = if image.color().has_alpha() {
+            image
+                .write_with_encoder(PngEncoder::new(&mut out))
+                .map_err(|e| e.to_string())?;
+            ("image/png", "png")
+        } else {
+            image
+                .to_rgb8()
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY))
+                .map_err(|e| e.to_string())?;
+            ("image/jpeg", "jpg")
+        };
+        rendered.push(Rendered { name, bytes: out, content_type, extension, width: image.width(), height: image.height() });
+    }
+    Ok((original, rendered))
+}
+
+pub(crate) async fn delete_variants(image: &EventImage, storage: &Storage) {
+    for variant in &image.variants {
+        if let Err(e) = storage.delete(&variant.key).await {
+            eprintln!("failed to delete {}: {}", variant.key, e);
+        }
+    }
+}
+
+// replaces the event's cover image; the old files are removed once the new ones are in place
+#[post("/event/<event_id>/image", format = "multipart/form-data", data = "<upload>")]
+pub async fn upload_event_image(
+    event_id: &str,
+    upload: Form<ImageUpload<'_>>,
+    db: &State<Collection<Event>>,
+    storage: &State<Storage>,
+    admin: Authorized<perm::EventUpdate>, // only organizers and core team can call this
+    audit: Audit<'_>,
+) -> Result<Json<EventImage>, status::Custom<String>> {
+    let event_oid = ObjectId::parse_str(event_id).map_err(|_| status::Custom(Status::BadRequest, "Invalid event id.".to_string()))?;
+
+    let file = &upload.file;
+    if file.len() > MAX_IMAGE_BYTES {
+        return Err(status::Custom(Status::PayloadTooLarge, format!("Images can be at most {} MiB.", MAX_IMAGE_BYTES / 1024 / 1024)));
+    }
+    let declared = file.content_type().and_then(image_format).ok_or_else(unsupported)?;
+    let mut bytes = Vec::with_capacity(file.len() as usize);
+    file.open()
+        .await
+        .map_err(|_| server_error())?
+        .read_to_end(&mut bytes)
+        .await
+        .map_err(|_| server_error())?;
+    // the declared type has to match what the bytes actually are
+    match image::guess_format(&bytes) {
+        Ok(sniffed) if sniffed == declared => {}
+        _ => return Err(unsupported()),
+    }
+
+    match db.count_documents(doc! {"_id": event_oid}).await {
+        Ok(0) => return Err(status::Custom(Status::NotFound, String::new())),
+        Ok(_) => {}
+        Err(_) => return Err(server_error()),
+    }
+
+    let (original, rendered) = {
+        let bytes = bytes.clone();
+        rocket::tokio::task::spawn_blocking(move || render_variants(&bytes, declared))
+            .await
+            .map_err(|_| server_error())?
+            .map_err(|msg| status::Custom(Status::UnprocessableEntity, msg))?
+    };
+
+    let upload_id = random_token()[..16].to_string();
+    let prefix = format!("events/{}/{}", event_oid.to_hex(), upload_id);
+    let original_extension = declared.extensions_str().first().copied().unwrap_or("img");
+    let original_type = file.content_type().map(|content_type| content_type.to_string()).unwrap_or_default();
+
+    let mut image = EventImage { upload_id, variants: Vec::new() };
+    let files = std::iter::once(("original", bytes, original_type.as_str(), original_extension, original.width(), original.height()))
+        .chain(rendered.iter().map(|r| (r.name, r.bytes.clone(), r.content_type, r.extension, r.width, r.height)));
+    for (name, bytes, content_type, extension, width, height) in files {
+        let key = format!("{}/{}.{}", prefix, name, extension);
+        let size = bytes.len() as u64;
+        if let Err(e) = storage.put(&key, bytes, content_type).await {
+            eprintln!("failed to store {}: {}", key, e);
+            delete_variants(&image, storage).await;
+            return Err(server_error());
+        }
+        image.variants.push(ImageVariant { name: name.to_string(), key, content_type: content_type.to_string(), width, height, size });
+    }
+
+    let image_doc = bson::to_bson(&image).map_err(|_| server_error())?;
+    let update_doc = doc! {
+        "$set": {"image": image_doc, "image_url": format!("/api/v1/event/{}/image", event_oid.to_hex())},
+    };
+    let previous = match db.find_one_and_update(doc! {"_id": event_oid}, update_doc).await {
+        Ok(Some(previous)) => previous,
+        Ok(None) => {
+            delete_variants(&image, storage).await;
+            return Err(status::Custom(Status::NotFound, String::new()));
+        }
+        Err(_) => {
+            delete_variants(&image, storage).await;
+            return Err(server_error());
+        }
+    };
+    if let Some(old) = &previous.image {
+        delete_variants(old, storage).await;
+    }
+
+    let before = snapshot(&previous);
+    let after = before.clone().map(|mut after| {
+        after.insert("image", bson::to_bson(&image).unwrap_or(Bson::Null));
+        after
+    });
+    audit.record(&admin.email, "event.image_upload", vec![event_oid.to_hex()], before, after).await;
+    Ok(Json(image))
+}
+
+#[delete("/event/<event_id>/image")]
+pub async fn delete_event_image(
+    event_id: &str,
+    db: &State<Collection<Event>>,
+    storage: &State<Storage>,
+    admin: Authorized<perm::EventUpdate>, // only organizers and core team can call this
+    audit: Audit<'_>,
+) -> Result<Json<String>, Status> {
+    let event_oid = ObjectId::parse_str(event_id).map_err(|_| Status::BadRequest)?;
+    let update_doc = doc! {"$unset": {"image": "", "image_url": ""}};
+    let previous = match db.find_one_and_update(doc! {"_id": event_oid}, update_doc).await {
+        Ok(Some(previous)) => previous,
+        Ok(None) => return Err(Status::NotFound),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    let image = previous.image.clone().ok_or(Status::NotFound)?;
+    delete_variants(&image, storage).await;
+
+    audit.record(&admin.email, "event.image_delete", vec![event_oid.to_hex()], snapshot(&previous), None).await;
+    Ok(Json("Image deleted.".to_string()))
+}
+
+// the stable url stored in image_url; sends the client on to a short lived signed url.
+// Public like the events themselves. variant defaults to "large".
+#[get("/event/<event_id>/image?<variant>")]
+pub async fn read_event_image(
+    event_id: &str,
+    variant: Option<&str>,
+    db: &State<Collection<Event>>,
+    storage: &State<Storage>,
+) -> Result<Redirect, Status> {
+    let event_oid = ObjectId::parse_str(event_id).map_err(|_| Status::BadRequest)?;
+    let event = match db.find_one(doc! {"_id": event_oid}).await {
+        Ok(Some(event)) => event,
+        Ok(None) => return Err(Status::NotFound),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    let name = variant.unwrap_or("large");
+    let variant = event
+        .image
+        .as_ref()
+        .and_then(|image| image.variants.iter().find(|variant| variant.name == name))
+        .ok_or(Status::NotFound)?;
+
+    Ok(Redirect::to(storage.signed_url(&variant.key, Duration::seconds(SIGNED_URL_TTL))))
+}
+
+// files of the local storage backend, only with a valid signature from signed_url
+#[get("/media/<key..>?<expires>&<signature>")]
+pub async fn read_media(
+    key: PathBuf,
+    expires: i64,
+    signature: &str,
+    storage: &State<Storage>,
+) -> Result<(ContentType, Vec<u8>), Status> {
+    let key = key.to_str().ok_or(Status::NotFound)?;
+    if !storage.verify_signature(key, expires, signature) {
+        return Err(Status::Forbidden);
+    }
+    let content_type = key
+        .rsplit_once('.')
+        .and_then(|(_, extension)| ContentType::from_extension(extension))
+        .unwrap_or(ContentType::Binary);
+    match storage.get(key).await {
+        Ok(Some(bytes)) => Ok((content_type, bytes)),
+        Ok(None) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
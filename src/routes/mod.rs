@@ -2,12 +2,40 @@ pub mod auth;
 pub mod event;
 pub mod application;
 pub mod user;
-pub use auth::{ login, AuthenticatedUser};
-pub use event::{create_event, read_event, read_events, update_event, drop_event, join_event, leave_event, 
+pub mod password;
+pub mod verification;
+pub mod lockout;
+pub mod session;
+pub mod api_key;
+pub mod access;
+pub mod two_factor;
+pub mod audit;
+pub mod cookie_session;
+pub mod check_in;
+pub mod calendar;
+pub mod series;
+pub mod media;
+pub mod featured;
+pub use auth::{ login, refresh, logout, wallet_challenge, wallet_login, link_wallet, oidc_start, oidc_callback, jwks};
+pub use event::{create_event, read_event, read_events, update_event, drop_event, join_event, leave_event, read_registration, cancel_event,
     get_multiple_events,
-    update_pinned,
     delete_all_events,
-    read_upcoming_events
+    read_upcoming_events,
+    export_events,
+    read_event_attendees
 };
+pub use password::{forgot_password, reset_password};
+pub use verification::{verify_email, resend_verification};
+pub use lockout::unlock_user;
+pub use session::{list_sessions, revoke_session, revoke_other_sessions, revoke_user_sessions};
+pub use api_key::{create_api_key, read_api_keys, revoke_api_key};
+pub use two_factor::{enroll_two_factor, confirm_two_factor, disable_two_factor, verify_two_factor};
+pub use audit::read_audit_log;
+pub use check_in::{read_ticket, check_in_attendee, read_check_in_stats};
+pub use series::{create_series, read_series, update_occurrence, join_series, leave_series};
+pub use featured::{read_featured_events, update_featured_events, feature_event, unfeature_event, update_pinned};
+pub use media::{upload_event_image, delete_event_image, read_event_image, read_media};
+pub use calendar::{read_event_calendar, read_events_calendar, read_personal_calendar, create_calendar_feed, revoke_calendar_feed};
 pub use application::{apply_for_event, read_applicants};
-pub use user::{profile, drop_user, read_users, sign_up, update_user, read_user, update_user_rank, delete_all_users};
+pub use user::{profile, drop_user, read_users, sign_up, update_user, read_user, update_user_rank, delete_all_users,
+    grant_role, revoke_role};
@@ -0,0 +1,136 @@
+use std::time::SystemTime;
+
+use bcrypt::{hash, DEFAULT_COST};
+use chrono::{Duration, Utc};
+use mongodb::bson::{doc, Bson, DateTime as BsonDateTime};
+use mongodb::Collection;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{post, State};
+use serde::Deserialize;
+
+use crate::mail::{self, Mail, Mailer};
+use crate::models::{BlackListedToken, PasswordResetToken, RefreshToken, Session, User};
+use crate::revocation::RevocationCache;
+use super::auth::{hash_token, random_token, revoke_user_tokens};
+use super::session::revoke_sessions;
+
+const RESET_TOKEN_TTL: i64 = 60 * 60; // 1 hour
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+// always answers the same way so the endpoint can't be used to find registered emails
+#[post("/auth/password/forgot", format = "json", data = "<forgot_req>")]
+pub async fn forgot_password(
+    forgot_req: Json<ForgotPasswordRequest>,
+    user_db: &State<Collection<User>>,
+    reset_db: &State<Collection<PasswordResetToken>>,
+    mailer: &State<Mailer>,
+) -> Result<Json<String>, Status> {
+    let response = Json("If the account exists, a reset link has been sent.".to_string());
+
+    let user = user_db
+        .find_one(doc! {"email": &forgot_req.email})
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let user = match user {
+        Some(user) => user,
+        None => return Ok(response),
+    };
+
+    // only the latest link works
+    reset_db
+        .delete_many(doc! {"email": &user.email})
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let token = random_token();
+    let reset = PasswordResetToken {
+        id: None,
+        token_hash: hash_token(&token),
+        email: user.email.clone(),
+        expires_at: BsonDateTime::from(SystemTime::from(Utc::now() + Duration::seconds(RESET_TOKEN_TTL))),
+        used_at: None,
+    };
+    reset_db.insert_one(reset).await.map_err(|_| Status::InternalServerError)?;
+
+    let mail = Mail {
+        to: user.email,
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Hi {},\n\nUse the link below to choose a new password. It expires in an hour.\n\n{}/reset-password?token={}\n\nIf you did not ask for this you can ignore this mail.",
+            user.name,
+            mail::app_url(),
+            token
+        ),
+    };
+    if let Err(e) = mailer.send(&mail).await {
+        eprintln!("Failed to send password reset mail: {:?}", e);
+    }
+
+    Ok(response)
+}
+
+#[post("/auth/password/reset", format = "json", data = "<reset_req>")]
+pub async fn reset_password(
+    reset_req: Json<ResetPasswordRequest>,
+    user_db: &State<Collection<User>>,
+    reset_db: &State<Collection<PasswordResetToken>>,
+    refresh_db: &State<Collection<RefreshToken>>,
+    sessions: &State<Collection<Session>>,
+    blacklist_db: &State<Collection<BlackListedToken>>,
+    revocations: &State<RevocationCache>,
+) -> Result<Json<String>, Status> {
+    if reset_req.password.is_empty() {
+        return Err(Status::BadRequest);
+    }
+    let now = BsonDateTime::now();
+
+    // mark the token used while reading it so it can only be redeemed once
+    let reset = reset_db
+        .find_one_and_update(
+            doc! {"token_hash": hash_token(&reset_req.token), "used_at": Bson::Null},
+            doc! {"$set": {"used_at": now}},
+        )
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Unauthorized)?;
+
+    if reset.expires_at < now {
+        return Err(Status::Unauthorized);
+    }
+
+    let hashed_password = hash(&reset_req.password, DEFAULT_COST).map_err(|_| Status::InternalServerError)?;
+    let update = doc! {
+        "$set": {
+            "password": hashed_password,
+            "updated_at": BsonDateTime::from(SystemTime::from(Utc::now())),
+        }
+    };
+    let result = user_db
+        .update_one(doc! {"email": &reset.email}, update)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    if result.matched_count == 0 {
+        return Err(Status::NotFound);
+    }
+
+    // whoever knew the old password shouldn't stay signed in
+    revoke_sessions(doc! {"email": &reset.email}, sessions, refresh_db, blacklist_db, revocations)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    revoke_user_tokens(&reset.email, refresh_db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json("Password successfully reset".to_string()))
+}
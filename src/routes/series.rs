@@ -0,0 +1,437 @@
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::Collection;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, post, put, State};
+use serde::{Deserialize, Serialize};
+
+use crate::models::event::{default_time_zone, stored_date, EventType};
+use crate::models::{Attendee, Event, EventSeries, User};
+use crate::recurrence::{resolve_local, Rule};
+use super::access::Actor;
+use super::api_key::{scope, ScopedCaller};
+use super::audit::{snapshot, Audit};
+use super::auth::{perm, Authorized};
+use super::event::{promote_waitlist, public_events, release_seat, reserve_seat, PublicEvent, RegistrationStatus};
+use super::verification::VerifiedEmail;
+
+const MAX_DURATION_MINUTES: i64 = 7 * 24 * 60;
+
+// starts_at is the local start of the first occurrence in `time_zone`, e.g. "2025-06-03T18:00"
+#[derive(Debug, Deserialize)]
+pub struct SeriesRequest {
+    name: String,
+    #[serde(default)]
+    location: String,
+    description: String,
+    event_type: EventType,
+    #[serde(default = "default_time_zone")]
+    time_zone: String,
+    starts_at: String,
+    duration_minutes: i64,
+    rrule: String,
+    #[serde(default)]
+    exceptions: Vec<String>, // local dates to skip, e.g. "2025-12-23"
+    #[serde(default)]
+    capacity: Option<i32>,
+    host_id: Option<ObjectId>,
+}
+
+// fields left out stay as they are; start_time is the local time of day, e.g. "19:30"
+#[derive(Debug, Deserialize)]
+pub struct OccurrenceEdit {
+    name: Option<String>,
+    location: Option<String>,
+    description: Option<String>,
+    start_time: Option<String>,
+    duration_minutes: Option<i64>,
+    capacity: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedSeries {
+    pub series_id: String,
+    pub occurrences: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct SeriesView {
+    pub series: EventSeries,
+    pub occurrences: Vec<PublicEvent>,
+}
+
+#[derive(Serialize)]
+pub struct OccurrenceRegistration {
+    pub event_id: String,
+    pub date: DateTime<Utc>,
+    pub registration: RegistrationStatus,
+}
+
+fn bad_request(msg: impl Into<String>) -> status::Custom<String> {
+    status::Custom(Status::BadRequest, msg.into())
+}
+
+fn server_error() -> status::Custom<String> {
+    status::Custom(Status::InternalServerError, String::new())
+}
+
+fn parse_local(value: &str) -> Result<NaiveDateTime, String> {
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M"))
+        .map_err(|_| "starts_at has to be a local date and time like 2025-06-03T18:00.".to_string())
+}
+
+fn parse_time(value: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(value, "%H:%M:%S"))
+        .map_err(|_| "start_time has to be a local time like 19:30.".to_string())
+}
+
+fn validate_duration(minutes: i64) -> Result<(), String> {
+    if !(1..=MAX_DURATION_MINUTES).contains(&minutes) {
+        return Err("duration_minutes has to be between 1 minute and a week.".to_string());
+    }
+    Ok(())
+}
+
+fn series_tz(time_zone: &str) -> Tz {
+    time_zone.parse().unwrap_or(Tz::UTC)
+}
+
+async fn find_series(series_id: &str, series_db: &Collection<EventSeries>) -> Result<EventSeries, Status> {
+    let series_oid = ObjectId::parse_str(series_id).map_err(|_| Status::BadRequest)?;
+    match series_db.find_one(doc! {"_id": series_oid}).await {
+        Ok(Some(series)) => Ok(series),
+        Ok(None) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+// occurrences that haven't started, oldest first
+async fn upcoming_occurrences(series_oid: ObjectId, db: &Collection<Event>) -> Result<Vec<Event>, Status> {
+    db.find(doc! {"series_id": series_oid, "date": {"$gt": stored_date(&Utc::now())}})
+        .sort(doc! {"date": 1})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .try_collect()
+        .await
+        .map_err(|_| Status::InternalServerError)
+}
+
+// creates the series and every occurrence its rule produces
+#[post("/series", format = "json", data = "<new_series>")]
+pub async fn create_series(
+    new_series: Json<SeriesRequest>,
+    series_db: &State<Collection<EventSeries>>,
+    db: &State<Collection<Event>>,
+    admin: Authorized<perm::EventCreate>, // only organizers and core team can call this
+    audit: Audit<'_>,
+) -> Result<Json<CreatedSeries>, status::Custom<String>> {
+    let time_zone: Tz = new_series
+        .time_zone
+        .parse()
+        .map_err(|_| bad_request(format!("Unknown time zone {}.", new_series.time_zone)))?;
+    let starts_at = parse_local(&new_series.starts_at).map_err(bad_request)?;
+    validate_duration(new_series.duration_minutes).map_err(bad_request)?;
+    if new_series.capacity.map(|capacity| capacity < 1).unwrap_or(false) {
+        return Err(bad_request("Capacity has to be at least 1."));
+    }
+    let exceptions = new_series
+        .exceptions
+        .iter()
+        .map(|day| NaiveDate::parse_from_str(day, "%Y-%m-%d").map_err(|_| bad_request(format!("{} is not a date like 2025-12-23.", day))))
+        .collect::<Result<Vec<_>, _>>()?;
+    let rule = Rule::parse(&new_series.rrule).map_err(bad_request)?;
+    let starts = rule.expand(starts_at, time_zone, &exceptions).map_err(bad_request)?;
+
+    let series = EventSeries {
+        id: None,
+        name: new_series.name.clone(),
+        location: new_series.location.clone(),
+        description: new_series.description.clone(),
+        event_type: new_series.event_type.clone(),
+        time_zone: time_zone.name().to_string(),
+        starts_at: new_series.starts_at.clone(),
+        duration_minutes: new_series.duration_minutes,
+        rrule: new_series.rrule.trim().to_string(),
+        exceptions: new_series.exceptions.clone(),
+        capacity: new_series.capacity,
+        host_id: new_series.host_id,
+        created_at: Utc::now(),
+    };
+    let series_oid = match series_db.insert_one(&series).await {
+        Ok(inserted) => inserted.inserted_id.as_object_id().ok_or_else(server_error)?,
+        Err(_) => return Err(server_error()),
+    };
+
+    let duration = Duration::minutes(new_series.duration_minutes);
+    let occurrences: Vec<Event> = starts
+        .into_iter()
+        .map(|start| Event {
+            id: None,
+            name: series.name.clone(),
+            location: series.location.clone(),
+            date: start,
+            ends_at: Some(start + duration),
+            time_zone: series.time_zone.clone(),
+            registration_opens_at: None,
+            registration_closes_at: None,
+            description: series.description.clone(),
+            event_type: series.event_type.clone(),
+            host_id: series.host_id,
+            attendees: vec![],
+            capacity: series.capacity,
+            waitlist: vec![],
+            image_url: None,
+            image: None,
+            sequence: 0,
+            cancelled_at: None,
+            series_id: Some(series_oid),
+        })
+        .collect();
+
+    let inserted = match db.insert_many(&occurrences).await {
+        Ok(inserted) => inserted,
+        Err(_) => {
+            // no series without its occurrences
+            let _ = db.delete_many(doc! {"series_id": series_oid}).await;
+            let _ = series_db.delete_one(doc! {"_id": series_oid}).await;
+            return Err(server_error());
+        }
+    };
+    let mut ids: Vec<(usize, String)> = inserted
+        .inserted_ids
+        .into_iter()
+        .filter_map(|(index, id)| id.as_object_id().map(|id| (index, id.to_hex())))
+        .collect();
+    ids.sort();
+
+    audit.record(&admin.email, "series.create", vec![series_oid.to_hex()], None, snapshot(&series)).await;
+    Ok(Json(CreatedSeries {
+        series_id: series_oid.to_hex(),
+        occurrences: ids.into_iter().map(|(_, id)| id).collect(),
+    }))
+}
+
+#[get("/series/<series_id>")]
+pub async fn read_series(
+    series_id: &str,
+    series_db: &State<Collection<EventSeries>>,
+    db: &State<Collection<Event>>,
+    _caller: ScopedCaller<scope::EventsRead>, // signed in user or api key with events:read
+) -> Result<Json<SeriesView>, Status> {
+    let series = find_series(series_id, series_db).await?;
+    let occurrences = db
+        .find(doc! {"series_id": series.id})
+        .sort(doc! {"date": 1})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .try_collect()
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(SeriesView { series, occurrences: public_events(occurrences) }))
+}
+
+// scope=this (default) changes one occurrence, scope=following that one and every later one.
+// Changing the start time keeps each occurrence on its own day in the series' time zone.
+#[put("/series/<series_id>/events/<event_id>?<scope>", format = "json", data = "<edit>")]
+pub async fn update_occurrence(
+    series_id: &str,
+    event_id: &str,
+    scope: Option<&str>,
+    edit: Json<OccurrenceEdit>,
+    series_db: &State<Collection<EventSeries>>,
+    db: &State<Collection<Event>>,
+    user_collection: &State<Collection<User>>,
+    admin: Authorized<perm::EventUpdate>, // only organizers and core team can call this
+    audit: Audit<'_>,
+) -> Result<Json<String>, status::Custom<String>> {
+    let following = match scope.unwrap_or("this") {
+        "this" => false,
+        "following" => true,
+        other => return Err(bad_request(format!("Unknown scope {}, use this or following.", other))),
+    };
+    let start_time = edit.start_time.as_deref().map(parse_time).transpose().map_err(bad_request)?;
+    if let Some(minutes) = edit.duration_minutes {
+        validate_duration(minutes).map_err(bad_request)?;
+    }
+    if edit.capacity.map(|capacity| capacity < 1).unwrap_or(false) {
+        return Err(bad_request("Capacity has to be at least 1."));
+    }
+
+    let series = find_series(series_id, series_db).await.map_err(|status| status::Custom(status, String::new()))?;
+    let series_oid = series.id.unwrap_or_default();
+    let event_oid = ObjectId::parse_str(event_id).map_err(|_| bad_request("Invalid event id."))?;
+    let target = match db.find_one(doc! {"_id": event_oid, "series_id": series_oid}).await {
+        Ok(Some(event)) => event,
+        Ok(None) => return Err(status::Custom(Status::NotFound, String::new())),
+        Err(_) => return Err(server_error()),
+    };
+
+    let targets = if following {
+        db.find(doc! {"series_id": series_oid, "date": {"$gte": stored_date(&target.date)}})
+            .await
+            .map_err(|_| server_error())?
+            .try_collect()
+            .await
+            .map_err(|_| server_error())?
+    } else {
+        vec![target]
+    };
+    // seats that are taken stay taken, capacity can't go below them
+    if let Some(capacity) = edit.capacity {
+        if let Some(full) = targets.iter().find(|event| event.attendees.len() > capacity as usize) {
+            return Err(status::Custom(
+                Status::Conflict,
+                format!("{} already has {} attendees, capacity can't be lower.", stored_date(&full.date), full.attendees.len()),
+            ));
+        }
+    }
+
+    let mut fields = Document::new();
+    if let Some(name) = &edit.name {
+        fields.insert("name", name);
+    }
+    if let Some(location) = &edit.location {
+        fields.insert("location", location);
+    }
+    if let Some(description) = &edit.description {
+        fields.insert("description", description);
+    }
+    if let Some(capacity) = edit.capacity {
+        fields.insert("capacity", capacity);
+    }
+
+    let mut updated = Vec::new();
+    for event in &targets {
+        let mut set = fields.clone();
+        if start_time.is_some() || edit.duration_minutes.is_some() {
+            let time_zone = series_tz(&event.time_zone);
+            let local = event.date.with_timezone(&time_zone).naive_local();
+            let starts_at = resolve_local(time_zone, local.date().and_time(start_time.unwrap_or(local.time())));
+            let duration = edit
+                .duration_minutes
+                .map(Duration::minutes)
+                .unwrap_or_else(|| event.ends_at.map(|ends_at| ends_at - event.date).unwrap_or_default());
+            set.insert("date", stored_date(&starts_at));
+            set.insert("ends_at", stored_date(&(starts_at + duration)));
+        }
+        if set.is_empty() {
+            continue;
+        }
+        let event_oid = event.id.unwrap_or_default();
+        let mut filter = doc! {"_id": event_oid};
+        if let Some(capacity) = edit.capacity {
+            // attendees may have joined since the check above
+            filter.insert("$expr", doc! {"$lte": [{"$size": {"$ifNull": ["$attendees", []]}}, capacity]});
+        }
+        let result = db
+            .update_one(filter, doc! {"$set": set, "$inc": {"sequence": 1}})
+            .await
+            .map_err(|_| server_error())?;
+        if result.matched_count == 0 {
+            return Err(status::Custom(
+                Status::Conflict,
+                format!("{} filled up meanwhile, {} occurrence(s) were updated before it.", stored_date(&event.date), updated.len()),
+            ));
+        }
+        if edit.capacity.is_some() {
+            // new seats go to whoever is waiting
+            promote_waitlist(event_oid, db, user_collection)
+                .await
+                .map_err(|_| server_error())?;
+        }
+        updated.push(event_oid.to_hex());
+    }
+
+    // the template follows along so the series reads like what it now produces
+    if following {
+        let mut template = fields.clone();
+        if let Some(start_time) = start_time {
+            if let Ok(starts_at) = parse_local(&series.starts_at) {
+                template.insert("starts_at", starts_at.date().and_time(start_time).format("%Y-%m-%dT%H:%M").to_string());
+            }
+        }
+        if let Some(minutes) = edit.duration_minutes {
+            template.insert("duration_minutes", minutes);
+        }
+        if !template.is_empty() {
+            series_db
+                .update_one(doc! {"_id": series_oid}, doc! {"$set": template})
+                .await
+                .map_err(|_| server_error())?;
+        }
+    }
+
+    let action = if following { "series.update_following" } else { "series.update_occurrence" };
+    let mut target_ids = vec![series_oid.to_hex()];
+    target_ids.extend(updated.iter().cloned());
+    let before = snapshot(&series);
+    let after = Some(doc! {"scope": scope.unwrap_or("this"), "changes": fields, "events": Bson::from(updated.clone())});
+    audit.record(&admin.email, action, target_ids, before, after).await;
+
+    Ok(Json(format!("{} occurrence(s) updated.", updated.len())))
+}
+
+// registers the caller for every upcoming occurrence that takes registrations,
+// with a seat or a waitlist spot depending on what is free
+#[put("/series/<series_id>/join")]
+pub async fn join_series(
+    series_id: &str,
+    series_db: &State<Collection<EventSeries>>,
+    db: &State<Collection<Event>>,
+    user_collection: &State<Collection<User>>,
+    actor: Actor, // the signed in user
+    _verified: VerifiedEmail, // verified email if the policy asks for it
+) -> Result<Json<Vec<OccurrenceRegistration>>, Status> {
+    let series = find_series(series_id, series_db).await?;
+    let series_oid = series.id.unwrap_or_default();
+    let member = Attendee {
+        user_id: actor.id(),
+        name: actor.user.name.clone(),
+        email: actor.user.email.clone(),
+    };
+
+    let now = Utc::now();
+    let mut registrations = Vec::new();
+    for event in upcoming_occurrences(series_oid, db).await? {
+        if !event.registration_open(now) {
+            continue;
+        }
+        let event_oid = event.id.unwrap_or_default();
+        let registration = reserve_seat(event_oid, member.clone(), db, user_collection).await?;
+        registrations.push(OccurrenceRegistration { event_id: event_oid.to_hex(), date: event.date, registration });
+    }
+
+    Ok(Json(registrations))
+}
+
+// gives up the caller's seats and waitlist spots in every upcoming occurrence
+#[delete("/series/<series_id>/leave")]
+pub async fn leave_series(
+    series_id: &str,
+    series_db: &State<Collection<EventSeries>>,
+    db: &State<Collection<Event>>,
+    user_collection: &State<Collection<User>>,
+    actor: Actor, // the signed in user
+) -> Result<Json<String>, Status> {
+    let series = find_series(series_id, series_db).await?;
+    let series_oid = series.id.unwrap_or_default();
+    let user_oid = actor.id();
+
+    let mut left = 0;
+    for event in upcoming_occurrences(series_oid, db).await? {
+        let registered = event.attendees.iter().chain(event.waitlist.iter()).any(|attendee| attendee.user_id == user_oid);
+        if registered {
+            release_seat(event.id.unwrap_or_default(), user_oid, db, user_collection).await?;
+            left += 1;
+        }
+    }
+
+    Ok(Json(format!("Left {} upcoming occurrence(s).", left)))
+}
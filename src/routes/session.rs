@@ -0,0 +1,174 @@
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, Bson, DateTime as BsonDateTime, Document};
+use mongodb::Collection;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::Serialize;
+
+use crate::models::{BlackListedToken, RefreshToken, Session, User};
+use crate::revocation::RevocationCache;
+use super::auth::{blacklist_session, perm, revoke_token_family, AuthenticatedUser, Authorized};
+use super::audit::Audit;
+use super::lockout::TrustedProxies;
+
+// where a login comes from, never fails
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    pub lockout_ip: Option<IpAddr>, // set only when it can be trusted, see `TrustedProxies`
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientInfo {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let lockout_ip = req
+            .rocket()
+            .state::<TrustedProxies>()
+            .and_then(|proxies| proxies.client_ip(req.remote().map(|remote| remote.ip()), req.headers().get_one("X-Forwarded-For")));
+        Outcome::Success(ClientInfo {
+            ip: req.client_ip(),
+            user_agent: req.headers().get_one("User-Agent").map(|ua| ua.to_string()),
+            lockout_ip,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct SessionView {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    pub current: bool,
+}
+
+fn to_chrono(date: BsonDateTime) -> DateTime<Utc> {
+    DateTime::<Utc>::from(SystemTime::from(date))
+}
+
+// marks the matching sessions revoked, kills their refresh tokens and blacklists the sessions, which
+// rejects every access token they handed out
+pub async fn revoke_sessions(
+    filter: Document,
+    sessions: &Collection<Session>,
+    refresh_db: &Collection<RefreshToken>,
+    blacklist_db: &Collection<BlackListedToken>,
+    revocations: &RevocationCache,
+) -> Result<u64, mongodb::error::Error> {
+    let mut filter = filter;
+    filter.insert("revoked_at", Bson::Null);
+
+    let mut cursor = sessions.find(filter).await?;
+    let now = BsonDateTime::now();
+    let mut revoked = 0;
+    while let Some(session) = cursor.try_next().await? {
+        revoke_token_family(&session.family_id, refresh_db).await?;
+        if session.access_expires_at > now {
+            blacklist_session(&session.family_id, session.access_expires_at, blacklist_db, revocations).await?;
+        }
+        sessions
+            .update_one(doc! {"_id": session.id}, doc! {"$set": {"revoked_at": now}})
+            .await?;
+        revoked += 1;
+    }
+    Ok(revoked)
+}
+
+#[get("/auth/sessions")]
+pub async fn list_sessions(
+    user: AuthenticatedUser,
+    sessions: &State<Collection<Session>>,
+) -> Result<Json<Vec<SessionView>>, Status> {
+    let filter = doc! {
+        "email": &user.email,
+        "revoked_at": Bson::Null,
+        "expires_at": {"$gt": BsonDateTime::now()},
+    };
+    let mut cursor = sessions.find(filter).sort(doc! {"last_used_at": -1}).await.map_err(|_| Status::InternalServerError)?;
+
+    let mut views = Vec::new();
+    while let Some(session) = cursor.try_next().await.map_err(|_| Status::InternalServerError)? {
+        views.push(SessionView {
+            id: session.id.map(|id| id.to_hex()).unwrap_or_default(),
+            user_agent: session.user_agent,
+            ip: session.ip,
+            created_at: to_chrono(session.created_at),
+            last_used_at: to_chrono(session.last_used_at),
+            current: session.family_id == user.session_id,
+        });
+    }
+    Ok(Json(views))
+}
+
+#[delete("/auth/sessions/<id>")]
+pub async fn revoke_session(
+    id: &str,
+    user: AuthenticatedUser,
+    sessions: &State<Collection<Session>>,
+    refresh_db: &State<Collection<RefreshToken>>,
+    blacklist_db: &State<Collection<BlackListedToken>>,
+    revocations: &State<RevocationCache>,
+) -> Result<Json<String>, Status> {
+    let object_id = ObjectId::parse_str(id).map_err(|_| Status::BadRequest)?;
+
+    // the email in the filter keeps users from revoking someone else's session
+    let filter = doc! {"_id": object_id, "email": &user.email};
+    match revoke_sessions(filter, sessions, refresh_db, blacklist_db, revocations).await {
+        Ok(0) => Err(Status::NotFound),
+        Ok(_) => Ok(Json("Session revoked".to_string())),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[delete("/auth/sessions")]
+pub async fn revoke_other_sessions(
+    user: AuthenticatedUser,
+    sessions: &State<Collection<Session>>,
+    refresh_db: &State<Collection<RefreshToken>>,
+    blacklist_db: &State<Collection<BlackListedToken>>,
+    revocations: &State<RevocationCache>,
+) -> Result<Json<String>, Status> {
+    let filter = doc! {"email": &user.email, "family_id": {"$ne": &user.session_id}};
+    match revoke_sessions(filter, sessions, refresh_db, blacklist_db, revocations).await {
+        Ok(count) => Ok(Json(format!("{} sessions revoked", count))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+// force logout of every session of a user
+#[delete("/user/<id>/sessions")]
+pub async fn revoke_user_sessions(
+    id: &str,
+    user_db: &State<Collection<User>>,
+    sessions: &State<Collection<Session>>,
+    refresh_db: &State<Collection<RefreshToken>>,
+    blacklist_db: &State<Collection<BlackListedToken>>,
+    revocations: &State<RevocationCache>,
+    admin: Authorized<perm::UserManage>, // only super admins can call this
+    audit: Audit<'_>,
+) -> Result<Json<String>, Status> {
+    let object_id = ObjectId::parse_str(id).map_err(|_| Status::BadRequest)?;
+    let user = user_db
+        .find_one(doc! {"_id": object_id})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    match revoke_sessions(doc! {"email": &user.email}, sessions, refresh_db, blacklist_db, revocations).await {
+        Ok(count) => {
+            let after = doc! {"revoked_sessions": count as i64};
+            audit.record(&admin.email, "user.force_logout", vec![object_id.to_hex()], None, Some(after)).await;
+            Ok(Json(format!("{} sessions revoked", count)))
+        },
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
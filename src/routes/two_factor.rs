@@ -0,0 +1,225 @@
+use chrono::Utc;
+use mongodb::bson::{doc, Bson, DateTime as BsonDateTime};
+use mongodb::Collection;
+use rocket::http::{CookieJar, Status};
+use rocket::serde::json::Json;
+use rocket::{post, State};
+use serde::{Deserialize, Serialize};
+
+use crate::keys::KeyRing;
+use crate::models::{MfaChallenge, RefreshToken, Session, TwoFactor, User};
+use crate::totp;
+use super::auth::{hash_token, issue_tokens, random_token, AuthenticatedUser, LoginResponse};
+use super::cookie_session::{self, Transport};
+use super::session::ClientInfo;
+
+const MFA_CHALLENGE_TTL_MS: i64 = 5 * 60 * 1000; // 5 minutes
+const MFA_MAX_ATTEMPTS: i32 = 5;
+const RECOVERY_CODE_COUNT: usize = 10;
+const TOTP_ISSUER: &str = "SOC";
+
+#[derive(Serialize)]
+pub struct EnrollResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Deserialize)]
+pub struct CodeRequest {
+    pub code: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>, // shown once
+}
+
+#[derive(Deserialize)]
+pub struct MfaVerifyRequest {
+    pub mfa_token: String,
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub recovery_code: Option<String>,
+}
+
+// called by the login handlers when the account has 2FA, returns the token for /auth/2fa/verify
+pub async fn begin_challenge(email: &str, db: &Collection<MfaChallenge>) -> Result<String, mongodb::error::Error> {
+    let token = random_token();
+    let challenge = MfaChallenge {
+        id: None,
+        token_hash: hash_token(&token),
+        email: email.to_string(),
+        attempts: 0,
+        expires_at: BsonDateTime::from_millis(BsonDateTime::now().timestamp_millis() + MFA_CHALLENGE_TTL_MS),
+    };
+    db.insert_one(challenge).await?;
+    Ok(token)
+}
+
+// checks a TOTP code and burns its time step so it can't be used twice
+async fn check_code(two_factor: &TwoFactor, code: &str, db: &Collection<TwoFactor>) -> Result<bool, mongodb::error::Error> {
+    let step = match totp::verify(&two_factor.secret, code, Utc::now().timestamp(), two_factor.last_used_step) {
+        Some(step) => step,
+        None => return Ok(false),
+    };
+    let result = db
+        .update_one(
+            doc! {"email": &two_factor.email, "last_used_step": {"$lt": step}},
+            doc! {"$set": {"last_used_step": step}},
+        )
+        .await?;
+    Ok(result.modified_count > 0)
+}
+
+#[post("/auth/2fa/enroll")]
+pub async fn enroll_two_factor(
+    user: AuthenticatedUser,
+    db: &State<Collection<TwoFactor>>,
+) -> Result<Json<EnrollResponse>, Status> {
+    let existing = db
+        .find_one(doc! {"email": &user.email})
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    if existing.map(|two_factor| two_factor.enabled_at.is_some()).unwrap_or(false) {
+        return Err(Status::Conflict);
+    }
+
+    // starting over replaces an unconfirmed secret
+    let secret = totp::generate_secret();
+    let update = doc! {"$set": {
+        "secret": &secret,
+        "enabled_at": Bson::Null,
+        "recovery_codes": [],
+        "last_used_step": 0_i64,
+    }};
+    db.update_one(doc! {"email": &user.email}, update)
+        .upsert(true)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(EnrollResponse {
+        otpauth_uri: totp::otpauth_uri(&secret, &user.email, TOTP_ISSUER),
+        secret,
+    }))
+}
+
+#[post("/auth/2fa/confirm", format = "json", data = "<code_req>")]
+pub async fn confirm_two_factor(
+    user: AuthenticatedUser,
+    code_req: Json<CodeRequest>,
+    db: &State<Collection<TwoFactor>>,
+    user_db: &State<Collection<User>>,
+) -> Result<Json<RecoveryCodesResponse>, Status> {
+    let two_factor = db
+        .find_one(doc! {"email": &user.email, "enabled_at": Bson::Null})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    if !check_code(&two_factor, &code_req.code, db).await.map_err(|_| Status::InternalServerError)? {
+        return Err(Status::Unauthorized);
+    }
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| random_token()[..10].to_string()).collect();
+    let hashes: Vec<String> = recovery_codes.iter().map(|code| hash_token(code)).collect();
+    db.update_one(
+        doc! {"email": &user.email},
+        doc! {"$set": {"enabled_at": BsonDateTime::now(), "recovery_codes": hashes}},
+    )
+    .await
+    .map_err(|_| Status::InternalServerError)?;
+    user_db
+        .update_one(doc! {"email": &user.email}, doc! {"$set": {"two_factor_enabled": true}})
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+#[post("/auth/2fa/disable", format = "json", data = "<code_req>")]
+pub async fn disable_two_factor(
+    user: AuthenticatedUser,
+    code_req: Json<CodeRequest>,
+    db: &State<Collection<TwoFactor>>,
+    user_db: &State<Collection<User>>,
+) -> Result<Json<String>, Status> {
+    let two_factor = db
+        .find_one(doc! {"email": &user.email})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    if !check_code(&two_factor, &code_req.code, db).await.map_err(|_| Status::InternalServerError)? {
+        return Err(Status::Unauthorized);
+    }
+
+    db.delete_one(doc! {"email": &user.email})
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    user_db
+        .update_one(doc! {"email": &user.email}, doc! {"$set": {"two_factor_enabled": false}})
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json("Two-factor authentication disabled".to_string()))
+}
+
+// second login step: trade the mfa_token and a TOTP or recovery code for tokens
+#[post("/auth/2fa/verify", format = "json", data = "<verify_req>")]
+pub async fn verify_two_factor(
+    verify_req: Json<MfaVerifyRequest>,
+    client: ClientInfo,
+    challenge_db: &State<Collection<MfaChallenge>>,
+    db: &State<Collection<TwoFactor>>,
+    refresh_db: &State<Collection<RefreshToken>>,
+    sessions: &State<Collection<Session>>,
+    keys: &State<KeyRing>,
+    transport: Transport,
+    cookies: &CookieJar<'_>,
+) -> Result<Json<LoginResponse>, Status> {
+    let token_hash = hash_token(&verify_req.mfa_token);
+    let challenge = challenge_db
+        .find_one_and_update(
+            doc! {"token_hash": &token_hash, "attempts": {"$lt": MFA_MAX_ATTEMPTS}, "expires_at": {"$gt": BsonDateTime::now()}},
+            doc! {"$inc": {"attempts": 1}},
+        )
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Unauthorized)?;
+
+    let two_factor = db
+        .find_one(doc! {"email": &challenge.email, "enabled_at": {"$ne": Bson::Null}})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Unauthorized)?;
+
+    let valid = match (&verify_req.code, &verify_req.recovery_code) {
+        (Some(code), _) => check_code(&two_factor, code, db).await.map_err(|_| Status::InternalServerError)?,
+        (None, Some(recovery_code)) => {
+            // recovery codes are single use
+            let result = db
+                .update_one(
+                    doc! {"email": &challenge.email, "recovery_codes": hash_token(recovery_code.trim())},
+                    doc! {"$pull": {"recovery_codes": hash_token(recovery_code.trim())}},
+                )
+                .await
+                .map_err(|_| Status::InternalServerError)?;
+            result.modified_count > 0
+        }
+        (None, None) => return Err(Status::BadRequest),
+    };
+    if !valid {
+        return Err(Status::Unauthorized);
+    }
+
+    challenge_db
+        .delete_one(doc! {"token_hash": &token_hash})
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    match issue_tokens(&challenge.email, None, &client, keys, refresh_db, sessions).await {
+        Ok(tokens) => Ok(Json(cookie_session::respond(tokens, transport, cookies))),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
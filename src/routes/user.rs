@@ -2,17 +2,24 @@ use std::time::SystemTime;
 
 use chrono::{format, DateTime, Utc};
 use mongodb::Collection;
-use rocket::{response::status, serde::json::Json, State};
-use crate::{models::{user::{self, UserType}, BlackListedToken, User}, routes::auth::AuthenticatedUser};
+use rocket::{serde::json::Json, State};
+use crate::{mail::Mailer, models::{user::{self, UserType}, BlackListedToken, EmailVerificationToken, LoginThrottle, MfaChallenge, PasswordResetToken, RefreshToken, Role, Session, TwoFactor, User}};
+use crate::revocation::RevocationCache;
 use mongodb::bson::{doc, Bson, Uuid, DateTime as BsonDateTime};
 use mongodb::Cursor;
 use futures::TryStreamExt;
 use mongodb::bson::oid::ObjectId;
 use rocket::http::{HeaderMap, Status};
+use rocket::request::{FromRequest, Outcome, Request};
 use bcrypt::{hash, DEFAULT_COST};
 use serde::{Deserialize, Serialize};
 
-use super::auth::{validate_token, AdminUser, AuthToken};
+use super::auth::{perm, AuthContext, Authorized};
+use super::verification::send_verification_mail;
+use super::access::{policy, Actor};
+use super::audit::{snapshot, Audit};
+use super::lockout::account_key;
+use super::session::revoke_sessions;
 
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -23,8 +30,10 @@ pub struct Profile {
     pub email: String,
     pub tel: String,
     pub wallet: String,
-    pub admin: Option<bool>,
+    pub roles: Vec<Role>,
     pub user_type: UserType,
+    pub email_verified: bool,
+    pub two_factor_enabled: bool,
     pub role: String,
     pub stack: Vec<String>,
     pub graduate: bool,
@@ -53,8 +62,8 @@ pub struct SignUPDto {
     pub email: String,
     pub tel: String,
     pub password: String,
-    pub wallet: String,
-    pub admin: Option<bool>,
+    #[serde(default)]
+    pub wallet: String, // ignored, wallets are linked with a signed challenge, see auth::link_wallet
     pub user_type: UserType,
     pub role: String,
     pub stack: Vec<String>,
@@ -74,39 +83,39 @@ pub struct SignUPDto {
 
 
 #[get("/profile")]
-pub async fn profile(user: AuthenticatedUser, db: &State<Collection<User>>) -> Result<Json<Profile>, status::Custom<String>> {
-    let result = db.find_one(doc! {"email": &user.email}).await;
-
-    match result {
-        Ok(Some(user_data)) => {
-            let return_user = Profile {
-                id: user_data.id,
-                name: user_data.name,
-                email: user_data.email,
-                wallet: user_data.wallet,
-                admin: user_data.admin,
-                attending_events: user_data.attending_events,
-                created_at: user_data.created_at,
-                updated_at: user_data.updated_at,
-                tel: user_data.tel,
-                role: user_data.role,
-                stack: user_data.stack,
-                graduate: user_data.graduate,
-                level: user_data.level,
-                department: user_data.department,
-                university: user_data.university,
-                student: user_data.student,
-                user_type: user_data.user_type,
-            };
-            return Ok(Json(return_user));
-        },
-        Ok(None) => Err(status::Custom(Status::NotFound, "User not found".to_string())), // if no user found
-        Err(e) => Err(status::Custom(Status::InternalServerError, format!("Database error: {}", e)))
-    }
+pub async fn profile(caller: &AuthContext) -> Json<Profile> {
+    // the auth context already loaded the caller
+    let user_data = caller.user.clone();
+    Json(Profile {
+        id: user_data.id,
+        name: user_data.name,
+        email: user_data.email,
+        wallet: user_data.wallet,
+        roles: user_data.roles,
+        email_verified: user_data.email_verified_at.is_some(),
+        two_factor_enabled: user_data.two_factor_enabled,
+        attending_events: user_data.attending_events,
+        created_at: user_data.created_at,
+        updated_at: user_data.updated_at,
+        tel: user_data.tel,
+        role: user_data.role,
+        stack: user_data.stack,
+        graduate: user_data.graduate,
+        level: user_data.level,
+        department: user_data.department,
+        university: user_data.university,
+        student: user_data.student,
+        user_type: user_data.user_type,
+    })
 }
 
 #[post("/user", format = "json", data = "<user>")]
-pub async fn sign_up(mut user: Json<SignUPDto>, db: &State<Collection<User>>) -> Result<Json<String>, rocket::response::status::Custom<String>> {
+pub async fn sign_up(
+    mut user: Json<SignUPDto>,
+    db: &State<Collection<User>>,
+    verification_db: &State<Collection<EmailVerificationToken>>,
+    mailer: &State<Mailer>,
+) -> Result<Json<String>, rocket::response::status::Custom<String>> {
     // Check if the email already exists
     let filter = doc! {"email": &user.email};
 
@@ -165,8 +174,9 @@ pub async fn sign_up(mut user: Json<SignUPDto>, db: &State<Collection<User>>) ->
         email: user.email.clone(),
         tel: user.tel.clone(),
         password: hashed_password,
-        wallet: user.wallet.clone(),
-        admin: Some(false),
+        wallet: String::new(),
+        wallet_linked_at: None,
+        roles: vec![Role::Member],
         user_type: user.user_type.clone(),
         role: user.role.clone(),
         stack: user.stack.clone(),
@@ -178,13 +188,21 @@ pub async fn sign_up(mut user: Json<SignUPDto>, db: &State<Collection<User>>) ->
         attending_events: vec![],
         created_at: now,
         updated_at: now,
+        email_verified_at: None,
+        two_factor_enabled: false,
+        identities: vec![],
     };
 
     // Insert the new user into the database
-    let result = db.insert_one(new_user).await;
+    let result = db.insert_one(&new_user).await;
 
     match result {
-        Ok(_) => Ok(Json("User registered successfully!".to_string())),
+        Ok(_) => {
+            if let Err(e) = send_verification_mail(&new_user, verification_db, mailer).await {
+                eprintln!("Failed to create verification token: {:?}", e);
+            }
+            Ok(Json("User registered successfully! Check your email to verify your account.".to_string()))
+        },
         Err(e) => Err(rocket::response::status::Custom(Status::BadRequest, format!("Error: {e}"))),
     }
 }
@@ -192,8 +210,7 @@ pub async fn sign_up(mut user: Json<SignUPDto>, db: &State<Collection<User>>) ->
 
 #[get("/users")]
 pub async fn read_users(db: &State<Collection<User>>,
-     _user: AuthenticatedUser,
-     _token: AuthToken,
+     _caller: &AuthContext,
     //  db_blacklist: &State<Collection<BlackListedToken>>
     ) -> Json<Vec<User>> {
     let mut cursor: Cursor<User> = db
@@ -210,8 +227,7 @@ pub async fn read_users(db: &State<Collection<User>>,
 #[get("/user/<id>")]
 pub async  fn read_user(db: &State<Collection<User>>,
      id: &str, 
-     _token: AuthToken,
-     _user: AuthenticatedUser) -> Result<Json<User>, Status> {
+     _caller: &AuthContext) -> Result<Json<User>, Status> {
     let collection = db;
     let object_id = match ObjectId::parse_str(id) {
         Ok(oid)=> oid,
@@ -236,70 +252,170 @@ pub async  fn read_user(db: &State<Collection<User>>,
 #[delete("/user/<id>")]
 pub async fn drop_user(id: &str, 
     db: &State<Collection<User>>,
-    _token: AuthToken,
-     _user: AuthenticatedUser) -> Result<Json<String>, Status> {
+     actor: Actor,
+     audit: Audit<'_>) -> Result<Json<String>, Status> {
     let collection = db;
 
     let object_id = match ObjectId::parse_str(id) {
         Ok(oid) => oid,
         Err(_) => return Err(Status::BadRequest),
     };
+    // users can delete their own account, deleting others needs user:manage
+    actor.authorize(&object_id, policy::DROP_USER)?;
     let filter = doc! {"_id": object_id};
-    let result = collection.delete_one(filter).await;
+    let result = collection.find_one_and_delete(filter).await;
 
     match result {
-        Ok(delete_result) => {
-            if delete_result.deleted_count > 0 {
-                Ok(Json("User deleted successfully!".to_string()))
-            } else {
-                Err(Status::NotFound)
-            }
+        Ok(Some(deleted)) => {
+            audit.record(&actor.user.email, "user.delete", vec![object_id.to_hex()], snapshot(&deleted), None).await;
+            Ok(Json("User deleted successfully!".to_string()))
         }
+        Ok(None) => Err(Status::NotFound),
         Err(_) => Err(Status::InternalServerError),
     }
 }
 
 
+// the records kept per email address, handed to update_user for moving an account to a new address
+pub struct AccountRecords<'r> {
+    sessions: &'r Collection<Session>,
+    refresh_tokens: &'r Collection<RefreshToken>,
+    blacklist: &'r Collection<BlackListedToken>,
+    revocations: &'r RevocationCache,
+    two_factor: &'r Collection<TwoFactor>,
+    throttles: &'r Collection<LoginThrottle>,
+    mfa_challenges: &'r Collection<MfaChallenge>,
+    password_resets: &'r Collection<PasswordResetToken>,
+    pub verifications: &'r Collection<EmailVerificationToken>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AccountRecords<'r> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let rocket = req.rocket();
+        match (
+            rocket.state::<Collection<Session>>(),
+            rocket.state::<Collection<RefreshToken>>(),
+            rocket.state::<Collection<BlackListedToken>>(),
+            rocket.state::<RevocationCache>(),
+            rocket.state::<Collection<TwoFactor>>(),
+            rocket.state::<Collection<LoginThrottle>>(),
+            rocket.state::<Collection<MfaChallenge>>(),
+            rocket.state::<Collection<PasswordResetToken>>(),
+            rocket.state::<Collection<EmailVerificationToken>>(),
+        ) {
+            (Some(sessions), Some(refresh_tokens), Some(blacklist), Some(revocations), Some(two_factor), Some(throttles), Some(mfa_challenges), Some(password_resets), Some(verifications)) => {
+                Outcome::Success(AccountRecords { sessions, refresh_tokens, blacklist, revocations, two_factor, throttles, mfa_challenges, password_resets, verifications })
+            }
+            _ => Outcome::Error((Status::InternalServerError, ())),
+        }
+    }
+}
+
+impl AccountRecords<'_> {
+    // tokens name the account by email, so every login of the old address ends here. Otherwise
+    // they would stop resolving, or resolve to whoever registers the old address next.
+    // The 2FA enrollment and the lockout counter move along with the account.
+    pub async fn move_email(&self, from: &str, to: &str) -> Result<(), mongodb::error::Error> {
+        revoke_sessions(doc! {"email": from}, self.sessions, self.refresh_tokens, self.blacklist, self.revocations).await?;
+        // refresh tokens from before sessions were tracked have no session to revoke
+        self.refresh_tokens.update_many(doc! {"email": from}, doc! {"$set": {"revoked": true}}).await?;
+        self.mfa_challenges.delete_many(doc! {"email": from}).await?;
+        self.password_resets.delete_many(doc! {"email": from}).await?;
+        self.verifications.delete_many(doc! {"email": from}).await?;
+
+        self.two_factor.update_many(doc! {"email": from}, doc! {"$set": {"email": to}}).await?;
+        // failures against the new address were made while it belonged to nobody
+        self.throttles.delete_many(doc! {"key": account_key(to)}).await?;
+        self.throttles
+            .update_many(doc! {"key": account_key(from)}, doc! {"$set": {"key": account_key(to)}})
+            .await?;
+        Ok(())
+    }
+}
+
 #[put("/user/<id>", format = "json", data = "<updated_user>")]
 pub async fn update_user(
-    _user: AuthenticatedUser,
+    actor: Actor,
     id: &str,
     updated_user: Json<User>,
-    _token: AuthToken,
     db: &State<Collection<User>>,
+    mailer: &State<Mailer>,
+    records: AccountRecords<'_>, // moved along when the email changes
+    audit: Audit<'_>,
 ) -> Result<Json<String>, Status> {
     let collection = db;
     let object_id = match ObjectId::parse_str(id) {
         Ok(oid) => oid,
         Err(_) => return Err(Status::BadRequest),
     };
+    // users can edit their own profile, editing others needs user:manage
+    actor.authorize(&object_id, policy::UPDATE_USER)?;
+
+    let current = match collection.find_one(doc! {"_id": object_id}).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(Status::NotFound),
+        Err(_) => return Err(Status::InternalServerError),
+    };
+    let email_changed = updated_user.email != current.email;
+    if email_changed {
+        match collection.count_documents(doc! {"email": &updated_user.email}).await {
+            Ok(0) => {}
+            Ok(_) => return Err(Status::Conflict),
+            Err(_) => return Err(Status::InternalServerError),
+        }
+    }
 
+    // the wallet is left alone, it only changes through auth::link_wallet
     let mut update_doc = doc! {
         
             "name": &updated_user.name,
             "email": &updated_user.email,
-            "wallet": &updated_user.wallet,
             "updated_at": BsonDateTime::from(SystemTime::from(Utc::now())),
     
     };
-    // only add user ranking if it is provided
-    if let Some(rank) = updated_user.admin {
-        update_doc.insert("user_rank", Bson::Boolean(rank));
+    // a new address has to be confirmed again, like at sign up
+    if email_changed {
+        update_doc.insert("email_verified_at", Bson::Null);
     }
     
-    let updated_doc = doc! {  "$set": Bson::Document(update_doc)};
+    let updated_doc = doc! {  "$set": Bson::Document(update_doc.clone())};
     
-    let filter = doc! {"_id": object_id};
+    // the email in the filter makes a concurrent change of the address fail instead of being overwritten
+    let filter = doc! {"_id": object_id, "email": &current.email};
 
     match collection
         .find_one_and_update(filter, updated_doc)
         .await
     {
-        Ok(Some(_)) => Ok(Json("User succesfully updated".to_string())),
-        Ok(None) => {
-            eprintln!("User not found: {}", id);
-            Err(Status::NotFound)
+        Ok(Some(previous)) => {
+            let mut moved = Ok(());
+            if email_changed {
+                moved = records.move_email(&previous.email, &updated_user.email).await;
+                let user = User { email: updated_user.email.clone(), email_verified_at: None, ..previous.clone() };
+                if let Err(e) = send_verification_mail(&user, records.verifications, mailer).await {
+                    eprintln!("Failed to create verification token: {:?}", e);
+                }
+            }
+            // only edits of someone else's account are admin actions
+            if previous.id != actor.user.id {
+                let before = snapshot(&previous);
+                let after = before.clone().map(|mut after| {
+                    after.extend(update_doc);
+                    after
+                });
+                audit.record(&actor.user.email, "user.update", vec![object_id.to_hex()], before, after).await;
+            }
+            // the address already changed, a failed move is reported rather than undone
+            if let Err(e) = moved {
+                eprintln!("Failed to move account records to the new email: {:?}", e);
+                return Err(Status::InternalServerError);
+            }
+            Ok(Json("User succesfully updated".to_string()))
         },
+        Ok(None) => Err(Status::Conflict),
         Err(e) => {
             eprintln!("Database error: {:?}", e);
             Err(Status::InternalServerError)},
@@ -312,8 +428,8 @@ pub async fn update_user_rank(
     id: &str,
     admin: Json<bool>,
     db: &State<Collection<User>>,
-    _token: AuthToken,
-    _user: AuthenticatedUser,
+    caller: Authorized<perm::RoleManage>, // only super admins can call this
+    audit: Audit<'_>,
 ) -> Result<Json<String>, Status> {
     let collection = db;
 
@@ -322,11 +438,21 @@ pub async fn update_user_rank(
         Err(_) => return Err(Status::BadRequest)
     };
 
+    // admin is now the super_admin role
     let filter = doc! {"_id": object_id};
-    let update = doc! {"$set": {"admin": Bson::Boolean(*admin)}};
+    let update = if *admin {
+        doc! {"$addToSet": {"roles": Role::SuperAdmin.as_str()}}
+    } else {
+        doc! {"$pull": {"roles": Role::SuperAdmin.as_str()}}
+    };
 
     match collection.find_one_and_update(filter, update).await {
-        Ok(Some(_)) => Ok(Json("User rank successfully updated".to_string())),
+        Ok(Some(previous)) => {
+            let action = if *admin { "user.promote" } else { "user.demote" };
+            let before = doc! {"roles": previous.roles.iter().map(|role| role.as_str()).collect::<Vec<_>>()};
+            audit.record(&caller.email, action, vec![object_id.to_hex()], Some(before), Some(doc! {"admin": *admin})).await;
+            Ok(Json("User rank successfully updated".to_string()))
+        },
         Ok(None) => {
             eprintln!("User not found: {}", id);
             Err(Status::NotFound)
@@ -341,15 +467,16 @@ pub async fn update_user_rank(
 #[delete("/users")]
 pub async fn delete_all_users(
     database: &State<Collection<User>>,  // Assuming you're using a `User` collection
-    _admin: AdminUser, // Only admin can call this
-    _token: AuthToken,  // Verify blacklisted tokens
-    _user: AuthenticatedUser, // Verify authenticated user
+    admin: Authorized<perm::UserManage>, // Only super admins can call this
+    audit: Audit<'_>,
 ) -> Json<String> {
     // Delete all users in the collection
     let result = database.delete_many(doc! {}).await;
 
     match result {
         Ok(delete_result) => {
+            let after = doc! {"deleted_count": delete_result.deleted_count as i64};
+            audit.record(&admin.email, "user.delete_all", vec![], None, Some(after)).await;
             if delete_result.deleted_count > 0 {
                 Json("All users successfully deleted.".to_string())
             } else {
@@ -359,3 +486,65 @@ pub async fn delete_all_users(
         Err(_) => Json("Failed to delete users.".to_string()),
     }
 }
+
+#[derive(Debug, Deserialize)]
+pub struct RoleRequest {
+    pub role: Role,
+}
+
+#[post("/user/<id>/roles", format = "json", data = "<role_req>")]
+pub async fn grant_role(
+    id: &str,
+    role_req: Json<RoleRequest>,
+    db: &State<Collection<User>>,
+    admin: Authorized<perm::RoleManage>, // only super admins can call this
+    audit: Audit<'_>,
+) -> Result<Json<String>, Status> {
+    let object_id = ObjectId::parse_str(id).map_err(|_| Status::BadRequest)?;
+
+    let update = doc! {
+        "$addToSet": {"roles": role_req.role.as_str()},
+        "$set": {"updated_at": BsonDateTime::from(SystemTime::from(Utc::now()))}
+    };
+    match db.update_one(doc! {"_id": object_id}, update).await {
+        Ok(res) if res.matched_count > 0 => {
+            let after = doc! {"role": role_req.role.as_str()};
+            audit.record(&admin.email, "user.role_grant", vec![object_id.to_hex()], None, Some(after)).await;
+            Ok(Json(format!("Role {} granted", role_req.role.as_str())))
+        },
+        Ok(_) => Err(Status::NotFound),
+        Err(e) => {
+            eprintln!("Database error : {:?}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[delete("/user/<id>/roles/<role>")]
+pub async fn revoke_role(
+    id: &str,
+    role: &str,
+    db: &State<Collection<User>>,
+    admin: Authorized<perm::RoleManage>, // only super admins can call this
+    audit: Audit<'_>,
+) -> Result<Json<String>, Status> {
+    let object_id = ObjectId::parse_str(id).map_err(|_| Status::BadRequest)?;
+    let role = Role::parse(role).ok_or(Status::BadRequest)?;
+
+    let update = doc! {
+        "$pull": {"roles": role.as_str()},
+        "$set": {"updated_at": BsonDateTime::from(SystemTime::from(Utc::now()))}
+    };
+    match db.update_one(doc! {"_id": object_id}, update).await {
+        Ok(res) if res.matched_count > 0 => {
+            let before = doc! {"role": role.as_str()};
+            audit.record(&admin.email, "user.role_revoke", vec![object_id.to_hex()], Some(before), None).await;
+            Ok(Json(format!("Role {} revoked", role.as_str())))
+        },
+        Ok(_) => Err(Status::NotFound),
+        Err(e) => {
+            eprintln!("Database error : {:?}", e);
+            Err(Status::InternalServerError)
+        }
+    }
+}
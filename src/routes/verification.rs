@@ -0,0 +1,166 @@
+use std::collections::HashSet;
+use std::env;
+use std::time::SystemTime;
+
+use chrono::{Duration, Utc};
+use dotenv::dotenv;
+use mongodb::bson::{doc, DateTime as BsonDateTime};
+use mongodb::options::FindOneOptions;
+use mongodb::Collection;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket::{post, State};
+use serde::Deserialize;
+
+use crate::mail::{self, Mail, Mailer};
+use crate::models::{EmailVerificationToken, User};
+use super::auth::{hash_token, random_token, AuthContext};
+
+const VERIFICATION_TOKEN_TTL: i64 = 24 * 60 * 60; // 24 hours
+const RESEND_COOLDOWN: i64 = 60; // 1 minute
+
+// routes (by handler name) that only accept users with a verified email
+pub struct EmailVerificationPolicy {
+    pub routes: HashSet<String>,
+}
+
+impl EmailVerificationPolicy {
+    // VERIFIED_EMAIL_ROUTES is a comma separated list of handler names
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let routes = env::var("VERIFIED_EMAIL_ROUTES")
+            .unwrap_or_else(|_| "join_event,apply_for_event".to_string())
+            .split(',')
+            .map(|route| route.trim().to_string())
+            .filter(|route| !route.is_empty())
+            .collect();
+        EmailVerificationPolicy { routes }
+    }
+
+    pub fn requires_verification(&self, route: &str) -> bool {
+        self.routes.contains(route)
+    }
+}
+
+// request guard that enforces the policy for the route it is used on
+pub struct VerifiedEmail;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for VerifiedEmail {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let policy = match req.rocket().state::<EmailVerificationPolicy>() {
+            Some(policy) => policy,
+            None => return Outcome::Error((Status::InternalServerError, ())),
+        };
+        let route_name = req.route().and_then(|route| route.name.as_deref()).unwrap_or_default();
+        if !policy.requires_verification(route_name) {
+            return Outcome::Success(VerifiedEmail);
+        }
+
+        match req.guard::<&AuthContext>().await {
+            Outcome::Success(context) if context.user.email_verified_at.is_some() => Outcome::Success(VerifiedEmail),
+            Outcome::Success(_) => Outcome::Error((Status::Forbidden, ())), // email not verified
+            Outcome::Error(e) => Outcome::Error(e),
+            Outcome::Forward(f) => Outcome::Forward(f),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+// creates a new verification token and mails it, older tokens stop working
+pub async fn send_verification_mail(
+    user: &User,
+    db: &Collection<EmailVerificationToken>,
+    mailer: &Mailer,
+) -> Result<(), mongodb::error::Error> {
+    db.delete_many(doc! {"email": &user.email}).await?;
+
+    let token = random_token();
+    let now = Utc::now();
+    let verification = EmailVerificationToken {
+        id: None,
+        token_hash: hash_token(&token),
+        email: user.email.clone(),
+        created_at: BsonDateTime::from(SystemTime::from(now)),
+        expires_at: BsonDateTime::from(SystemTime::from(now + Duration::seconds(VERIFICATION_TOKEN_TTL))),
+    };
+    db.insert_one(verification).await?;
+
+    let mail = Mail {
+        to: user.email.clone(),
+        subject: "Verify your email".to_string(),
+        body: format!(
+            "Hi {},\n\nConfirm your email address with the link below. It expires in 24 hours.\n\n{}/verify-email?token={}",
+            user.name,
+            mail::app_url(),
+            token
+        ),
+    };
+    if let Err(e) = mailer.send(&mail).await {
+        eprintln!("Failed to send verification mail: {:?}", e);
+    }
+    Ok(())
+}
+
+#[post("/auth/email/verify", format = "json", data = "<verify_req>")]
+pub async fn verify_email(
+    verify_req: Json<VerifyEmailRequest>,
+    user_db: &State<Collection<User>>,
+    verification_db: &State<Collection<EmailVerificationToken>>,
+) -> Result<Json<String>, Status> {
+    let verification = verification_db
+        .find_one_and_delete(doc! {"token_hash": hash_token(&verify_req.token)})
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Unauthorized)?;
+
+    if verification.expires_at < BsonDateTime::now() {
+        return Err(Status::Unauthorized);
+    }
+
+    let update = doc! {"$set": {"email_verified_at": Utc::now().timestamp()}};
+    match user_db.update_one(doc! {"email": &verification.email}, update).await {
+        Ok(res) if res.matched_count > 0 => Ok(Json("Email successfully verified".to_string())),
+        Ok(_) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[post("/auth/email/resend")]
+pub async fn resend_verification(
+    caller: &AuthContext,
+    verification_db: &State<Collection<EmailVerificationToken>>,
+    mailer: &State<Mailer>,
+) -> Result<Json<String>, Status> {
+    let user = &caller.user;
+
+    if user.email_verified_at.is_some() {
+        return Err(Status::Conflict);
+    }
+
+    // one mail per cooldown window
+    let latest = verification_db
+        .find_one(doc! {"email": &user.email})
+        .with_options(FindOneOptions::builder().sort(doc! {"created_at": -1}).build())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    if let Some(latest) = latest {
+        let cooldown_ends = latest.created_at.timestamp_millis() + RESEND_COOLDOWN * 1000;
+        if cooldown_ends > BsonDateTime::now().timestamp_millis() {
+            return Err(Status::TooManyRequests);
+        }
+    }
+
+    send_verification_mail(user, verification_db, mailer)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json("Verification mail sent".to_string()))
+}
@@ -0,0 +1,154 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Digest};
+use ed25519_dalek::{Signature, VerifyingKey};
+
+type Blake2b256 = Blake2b<U32>;
+
+// signature scheme flags, first byte of a serialized Sui signature
+const ED25519_FLAG: u8 = 0x00;
+const SECP256K1_FLAG: u8 = 0x01;
+const SECP256R1_FLAG: u8 = 0x02;
+
+// intent prefix for personal messages: scope PersonalMessage (3), version 0, app id Sui (0)
+const PERSONAL_MESSAGE_INTENT: [u8; 3] = [3, 0, 0];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    Malformed,
+    UnsupportedScheme,
+    Invalid,
+}
+
+// lowercases the address and checks it is 0x followed by 32 hex encoded bytes
+pub fn normalize_address(address: &str) -> Option<String> {
+    let address = address.trim().to_lowercase();
+    let hex_part = address.strip_prefix("0x")?;
+    if hex_part.len() != 64 || !hex_part.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(address)
+}
+
+// verifies a base64 serialized signature (flag || signature || public key) produced by
+// `signPersonalMessage` and returns the Sui address of the signing key
+pub fn verify_personal_message(message: &[u8], serialized_signature: &str) -> Result<String, SignatureError> {
+    let bytes = BASE64.decode(serialized_signature).map_err(|_| SignatureError::Malformed)?;
+    let (flag, rest) = bytes.split_first().ok_or(SignatureError::Malformed)?;
+
+    match *flag {
+        ED25519_FLAG => {
+            if rest.len() != 64 + 32 {
+                return Err(SignatureError::Malformed);
+            }
+            let (sig_bytes, pk_bytes) = rest.split_at(64);
+            let signature = Signature::from_slice(sig_bytes).map_err(|_| SignatureError::Malformed)?;
+            let public_key = VerifyingKey::try_from(pk_bytes).map_err(|_| SignatureError::Malformed)?;
+
+            public_key
+                .verify_strict(&personal_message_digest(message), &signature)
+                .map_err(|_| SignatureError::Invalid)?;
+
+            Ok(address_of(*flag, pk_bytes))
+        }
+        SECP256K1_FLAG | SECP256R1_FLAG => Err(SignatureError::UnsupportedScheme),
+        _ => Err(SignatureError::Malformed),
+    }
+}
+
+// blake2b-256 over the intent prefix and the BCS encoded (length prefixed) message
+fn personal_message_digest(message: &[u8]) -> [u8; 32] {
+    let mut hasher = Blake2b256::new();
+    hasher.update(PERSONAL_MESSAGE_INTENT);
+    hasher.update(uleb128(message.len()));
+    hasher.update(message);
+    hasher.finalize().into()
+}
+
+// a Sui address is blake2b-256(flag || public key)
+fn address_of(flag: u8, public_key: &[u8]) -> String {
+    let mut hasher = Blake2b256::new();
+    hasher.update([flag]);
+    hasher.update(public_key);
+    format!("0x{}", hex::encode(hasher.finalize()))
+}
+
+fn uleb128(mut value: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return out;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ed25519 key with the seed [1; 32], vectors computed independently with Python's
+    // cryptography and hashlib.blake2b
+    const PUBLIC_KEY: &str = "8a88e3dd7409f195fd52db2d3cba5d72ca6709bf1d94121bf3748801b40f6f5c";
+    const ADDRESS: &str = "0x29dfbf688abce7ab43bb8e70cae158ae961196e721440f515482f8ba1684390f";
+    const HELLO_DIGEST: &str = "e0ea06e183a8984cd8dd072440ae2a8c21125d994a9435b7c8c61886bc087d6a";
+    const HELLO_SIGNATURE: &str = "AFkrXAVrEr3K6YDHy9pUsYqxghBahBk0eIe0rWiYdxc+Cnmda4WHnyR8UGudaUIRhcUbF8U1LyobCJxq/FmURQKKiOPddAnxlf1S2y08ul1yymcJvx2UEhvzdIgBtA9vXA==";
+    // 200 bytes take two bytes of BCS length prefix
+    const LONG_DIGEST: &str = "61e3dd41984d8d30dee392b00d786e3e98a9b3f0322df1c6b26319e2e8d747f6";
+    const LONG_SIGNATURE: &str = "AP2LcPx9b+lRerWD4GUTXOd4NDm2gX8DkfLT9dCobj9VGWmYjxIDq3zhNuTUC/x/Jt2eNxk53LelJ9hj3Ccw2gWKiOPddAnxlf1S2y08ul1yymcJvx2UEhvzdIgBtA9vXA==";
+
+    #[test]
+    fn derives_addresses() {
+        let public_key = hex::decode(PUBLIC_KEY).unwrap();
+        assert_eq!(address_of(ED25519_FLAG, &public_key), ADDRESS);
+    }
+
+    #[test]
+    fn digests_personal_messages() {
+        assert_eq!(uleb128(5), vec![5]);
+        assert_eq!(uleb128(200), vec![0xc8, 0x01]);
+        assert_eq!(hex::encode(personal_message_digest(b"hello")), HELLO_DIGEST);
+        assert_eq!(hex::encode(personal_message_digest(&[b'x'; 200])), LONG_DIGEST);
+    }
+
+    #[test]
+    fn verifies_signed_messages() {
+        assert_eq!(verify_personal_message(b"hello", HELLO_SIGNATURE), Ok(ADDRESS.to_string()));
+        assert_eq!(verify_personal_message(&[b'x'; 200], LONG_SIGNATURE), Ok(ADDRESS.to_string()));
+    }
+
+    #[test]
+    fn rejects_other_messages_and_keys() {
+        assert_eq!(verify_personal_message(b"hello!", HELLO_SIGNATURE), Err(SignatureError::Invalid));
+        assert_eq!(verify_personal_message(b"hello", LONG_SIGNATURE), Err(SignatureError::Invalid));
+
+        // the same signature presented with someone else's public key
+        let mut bytes = BASE64.decode(HELLO_SIGNATURE).unwrap();
+        let other = ed25519_dalek::SigningKey::from_bytes(&[2; 32]).verifying_key();
+        bytes[65..].copy_from_slice(other.as_bytes());
+        assert_eq!(verify_personal_message(b"hello", &BASE64.encode(&bytes)), Err(SignatureError::Invalid));
+
+        // a valid signature only names its own signer, wallet_login compares it with the claimed one
+        let claimed = normalize_address(&format!("0x{}", "ab".repeat(32))).unwrap();
+        assert_ne!(verify_personal_message(b"hello", HELLO_SIGNATURE).unwrap(), claimed);
+    }
+
+    #[test]
+    fn rejects_malformed_and_unsupported_signatures() {
+        assert_eq!(verify_personal_message(b"hello", "not base64!"), Err(SignatureError::Malformed));
+        assert_eq!(verify_personal_message(b"hello", &BASE64.encode([0u8; 10])), Err(SignatureError::Malformed));
+        let mut secp = BASE64.decode(HELLO_SIGNATURE).unwrap();
+        secp[0] = SECP256K1_FLAG;
+        assert_eq!(verify_personal_message(b"hello", &BASE64.encode(&secp)), Err(SignatureError::UnsupportedScheme));
+    }
+
+    #[test]
+    fn normalizes_addresses() {
+        assert_eq!(normalize_address(&format!(" {} ", ADDRESS.to_uppercase().replace("0X", "0x"))), Some(ADDRESS.to_string()));
+        assert_eq!(normalize_address("0x1234"), None);
+        assert_eq!(normalize_address(&ADDRESS[2..]), None);
+    }
+}
@@ -0,0 +1,81 @@
+use base32::Alphabet;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha1::Sha1;
+
+// RFC 6238 defaults, what every authenticator app expects
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+const SECRET_BYTES: usize = 20;
+const ALPHABET: Alphabet = Alphabet::Rfc4648 { padding: false };
+
+pub fn generate_secret() -> String {
+    let bytes: [u8; SECRET_BYTES] = rand::rng().random();
+    base32::encode(ALPHABET, &bytes)
+}
+
+pub fn otpauth_uri(secret: &str, account: &str, issuer: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        urlencoding::encode(issuer),
+        urlencoding::encode(account),
+        secret,
+        urlencoding::encode(issuer),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+fn code_at(key: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+    binary % 10u32.pow(DIGITS)
+}
+
+// accepts the current step and one on either side for clock drift. Returns the matched step,
+// which has to be newer than `last_used_step` so a code can't be replayed
+pub fn verify(secret: &str, code: &str, unix_time: i64, last_used_step: i64) -> Option<i64> {
+    let key = base32::decode(ALPHABET, secret)?;
+    // exactly DIGITS digits, parse alone would also take "+123456" or "1"
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let current = unix_time / STEP_SECONDS;
+
+    (current - 1..=current + 1)
+        .filter(|step| *step > last_used_step)
+        .find(|step| code_at(&key, *step) == code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 SHA-1 secret "12345678901234567890", the code at T=59 is 94287082 cut to six digits
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn accepts_the_rfc_code() {
+        assert_eq!(verify(SECRET, "287082", 59, -1), Some(1));
+        assert_eq!(verify(SECRET, " 287082 ", 59, -1), Some(1));
+    }
+
+    #[test]
+    fn rejects_anything_but_six_digits() {
+        for code in ["+287082", "0287082", "28708", "287 082", "２８７０８２", ""] {
+            assert_eq!(verify(SECRET, code, 59, -1), None, "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn rejects_replayed_steps() {
+        assert_eq!(verify(SECRET, "287082", 59, 1), None);
+    }
+}